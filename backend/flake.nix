@@ -72,11 +72,30 @@
                 description = "The domain name";
               };
 
+              postsDomain = mkOption {
+                type = types.nullOr types.str;
+                default = null;
+                example = "flakm.com";
+                description = "Host of the post URLs (baseURL of the Hugo site), posts on other hosts are rejected. Not checked when null";
+              };
+
               posts_path = mkOption {
                 type = types.path;
                 default = "./posts.json";
                 description = "The path to the posts json file";
               };
+
+              trustedProxies = mkOption {
+                type = types.listOf types.str;
+                default = [ "127.0.0.1/32" "::1/128" ];
+                description = "CIDRs of proxies allowed to set client IP headers (x-forwarded-for, x-real-ip)";
+              };
+
+              trustedProxiesFile = mkOption {
+                type = types.nullOr types.path;
+                default = ./cloudflare-ips.txt;
+                description = "File with the Cloudflare ranges, one CIDR per line - trusted proxies whose cf-* headers are honoured";
+              };
             };
 
             config = mkIf cfg.enable {
@@ -86,6 +105,7 @@
                 requires = [ "postgresql.service" ];
                 serviceConfig = {
                   Restart = "on-failure";
+                  CacheDirectory = "blog-backend";
                   ExecStart = "${server}/bin/backend ${config.services.backend.posts_path}";
                 };
                 environment = {
@@ -95,16 +115,34 @@
                   "OTEL_SERVICE_NAME" = "blog-backend";
                   "OTEL_SERVICE_VERSION" = "1.0.0";
                   "OTEL_RESOURCE_ATTRIBUTES" = "deployment.environment=production";
+                  "TRUSTED_PROXIES" = concatStringsSep "," cfg.trustedProxies;
+                  "ACTIVITYPUB_DOMAIN" = cfg.domain;
+                  "OG_IMAGE_CACHE_DIR" = "/var/cache/blog-backend/og";
+                  "OG_IMAGE_BRAND" = cfg.domain;
+                } // optionalAttrs (cfg.postsDomain != null) {
+                  "POSTS_DOMAIN" = cfg.postsDomain;
+                } // optionalAttrs (cfg.trustedProxiesFile != null) {
+                  "TRUSTED_PROXIES_FILE" = "${cfg.trustedProxiesFile}";
                 };
               };
 
+              # Like $proxy_add_x_forwarded_for, but with the address nginx got the request
+              # from even when real_ip replaced it, the backend only honours the cf-* headers
+              # when that address is a Cloudflare edge
+              services.nginx.appendHttpConfig = ''
+                map $http_x_forwarded_for $backend_forwarded_for {
+                  "" $realip_remote_addr;
+                  default "$http_x_forwarded_for, $realip_remote_addr";
+                }
+              '';
+
               services.nginx.virtualHosts.${cfg.domain} = {
                 locations."/api/health" = {
                   proxyPass = "http://127.0.0.1:3000/health";
                   extraConfig = ''
                     proxy_set_header Host $host;
                     proxy_set_header X-Real-IP $remote_addr;
-                    proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+                    proxy_set_header X-Forwarded-For $backend_forwarded_for;
                     proxy_set_header X-Forwarded-Proto $scheme;
                   '';
                   priority = 10;
@@ -114,17 +152,39 @@
                   extraConfig = ''
                     proxy_set_header Host $host;
                     proxy_set_header X-Real-IP $remote_addr;
-                    proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+                    proxy_set_header X-Forwarded-For $backend_forwarded_for;
+                    proxy_set_header X-Forwarded-Proto $scheme;
+                  '';
+                  priority = 10;
+                };
+                # Plausible compatible, the tracker script posts to <api host>/api/event
+                locations."= /api/event" = {
+                  proxyPass = "http://127.0.0.1:3000";
+                  extraConfig = ''
+                    proxy_set_header Host $host;
+                    proxy_set_header X-Real-IP $remote_addr;
+                    proxy_set_header X-Forwarded-For $backend_forwarded_for;
+                    proxy_set_header X-Forwarded-Proto $scheme;
+                  '';
+                  priority = 10;
+                };
+                # Fediverse servers look up @blog@<domain> here
+                locations."= /.well-known/webfinger" = {
+                  proxyPass = "http://127.0.0.1:3000";
+                  extraConfig = ''
+                    proxy_set_header Host $host;
+                    proxy_set_header X-Real-IP $remote_addr;
+                    proxy_set_header X-Forwarded-For $backend_forwarded_for;
                     proxy_set_header X-Forwarded-Proto $scheme;
                   '';
                   priority = 10;
                 };
-                locations."~ ^/api/likes?/" = {
+                locations."~ ^/api/((likes?|react|views|privacy|activitypub|og|badge)/|(un)?subscribe|search)" = {
                   proxyPass = "http://127.0.0.1:3000";
                   extraConfig = ''
                     proxy_set_header Host $host;
                     proxy_set_header X-Real-IP $remote_addr;
-                    proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+                    proxy_set_header X-Forwarded-For $backend_forwarded_for;
                     proxy_set_header X-Forwarded-Proto $scheme;
                     rewrite ^/api(/.*) $1 break;
                   '';
@@ -33,6 +33,21 @@ def test_step(description, test_func):
         raise
 
 
+# Likes from scripts are rejected, pretend to be a browser
+BROWSER_USER_AGENT = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0"
+
+
+def like_post(client, slug, flags="-s"):
+    """Like a post the way the blog page does - fetch a like token first."""
+    likes = json.loads(client.succeed(
+        f"curl -s -A '{BROWSER_USER_AGENT}' http://server/api/likes/{slug}"
+    ))
+    return client.succeed(
+        f"curl {flags} -A '{BROWSER_USER_AGENT}' -H 'X-Like-Token: {likes['like_token']}' "
+        f"-X POST http://server/api/like/{slug}"
+    )
+
+
 def run_integration_tests(server, client):
     """
     Run all integration tests for the blog backend.
@@ -70,7 +85,7 @@ def run_integration_tests(server, client):
 
     # Test 3: Like a post (should create database entry)
     test_step("Like a post", lambda: 
-        client.succeed("curl -f -X POST http://server/api/like/test-post")
+        like_post(client, "test-post", flags="-sf")
     )
 
     # Test 4: Get likes count
@@ -98,7 +113,7 @@ def run_integration_tests(server, client):
 
     # Test 7: Test error handling - like non-existent post
     def test_error_handling():
-        result = client.succeed("curl -s -X POST http://server/api/like/non-existent-post")
+        result = like_post(client, "non-existent-post")
         data = json.loads(result)
         assert data["success"] == False
         assert "not found" in data["message"].lower()
@@ -108,11 +123,11 @@ def run_integration_tests(server, client):
     # Test 8: Test rate limiting - try to like same post twice quickly
     def test_rate_limiting():
         # First like should succeed - just ensure it returns valid JSON
-        result1 = client.succeed("curl -s -X POST http://server/api/like/test-post")
+        result1 = like_post(client, "test-post")
         json.loads(result1)  # Validate JSON format
         
         # Second like within the hour should be rate limited
-        result2 = client.succeed("curl -s -X POST http://server/api/like/test-post") 
+        result2 = like_post(client, "test-post")
         data2 = json.loads(result2)
         
         # At least one should mention rate limiting
@@ -0,0 +1,13 @@
+-- Audit log of GDPR erasures of likes
+-- The erased hash itself is not stored, only who asked for it and how much was removed
+CREATE TABLE like_erasures (
+    id SERIAL PRIMARY KEY,
+    requested_by VARCHAR(16) NOT NULL, -- 'visitor' or 'admin'
+    likes_erased BIGINT NOT NULL,
+    correlation_id VARCHAR,
+    request_id VARCHAR,
+    erased_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Lookups by the hashed connecting IP (erasure also covers it)
+CREATE INDEX idx_blog_post_likes_cf_connecting_ip_hash ON blog_post_likes(cf_connecting_ip_hash);
@@ -0,0 +1,8 @@
+-- Personal metadata of likes is stripped after the retention period,
+-- the like itself stays so the counts are not affected
+ALTER TABLE blog_post_likes
+ALTER COLUMN user_ip_hash DROP NOT NULL;
+
+-- Retention job scans old likes that still carry metadata
+CREATE INDEX idx_blog_post_likes_liked_at ON blog_post_likes(liked_at)
+WHERE user_ip_hash IS NOT NULL;
@@ -0,0 +1,9 @@
+-- Denormalized like counter maintained in the same transaction as the likes,
+-- so reading the count does not need COUNT(*) over blog_post_likes
+ALTER TABLE blog_posts
+ADD COLUMN like_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE blog_posts
+SET like_count = (
+    SELECT COUNT(*) FROM blog_post_likes WHERE blog_post_likes.post_slug = blog_posts.slug
+);
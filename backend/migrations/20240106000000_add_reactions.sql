@@ -0,0 +1,22 @@
+-- Likes become reactions of a configurable kind, existing likes are hearts
+ALTER TABLE blog_post_likes
+ADD COLUMN reaction VARCHAR(32) NOT NULL DEFAULT 'heart';
+
+-- Rate limiting applies to each reaction separately
+ALTER TABLE blog_post_likes
+DROP CONSTRAINT blog_post_likes_post_slug_user_ip_hash_hour_bucket_key;
+
+ALTER TABLE blog_post_likes
+ADD CONSTRAINT blog_post_likes_post_slug_user_ip_hash_reaction_hour_bucket_key
+UNIQUE(post_slug, user_ip_hash, reaction, hour_bucket);
+
+-- Denormalized per reaction counters, blog_posts.like_count stays the total
+CREATE TABLE blog_post_reaction_counts (
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    reaction VARCHAR(32) NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (post_slug, reaction)
+);
+
+INSERT INTO blog_post_reaction_counts (post_slug, reaction, count)
+SELECT post_slug, reaction, COUNT(*) FROM blog_post_likes GROUP BY post_slug, reaction;
@@ -0,0 +1,9 @@
+-- Daily views of a post, unique visitors are estimated with a HyperLogLog sketch
+-- so no per-visitor rows are stored
+CREATE TABLE post_views (
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    day DATE NOT NULL,
+    views BIGINT NOT NULL DEFAULT 0,
+    visitors BYTEA NOT NULL DEFAULT '', -- HyperLogLog registers keyed on the salted IP hash
+    PRIMARY KEY (post_slug, day)
+);
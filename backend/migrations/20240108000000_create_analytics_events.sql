@@ -0,0 +1,20 @@
+-- Pageviews and custom events received on the Plausible compatible /api/event endpoint
+-- Visitors are identified by the same hashed IP as likes
+CREATE TABLE analytics_events (
+    id BIGSERIAL PRIMARY KEY,
+    domain VARCHAR NOT NULL,
+    name VARCHAR NOT NULL, -- 'pageview' or the name of a custom event
+    hostname VARCHAR NOT NULL,
+    pathname VARCHAR NOT NULL,
+    props JSONB NOT NULL DEFAULT '{}',
+    user_ip_hash VARCHAR(64), -- nulled by the retention job like the likes metadata
+    cf_country VARCHAR(2),
+    screen_width INTEGER,
+    occurred_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_analytics_events_domain_time ON analytics_events(domain, occurred_at);
+
+-- Retention job scans old events that still carry the hashed IP
+CREATE INDEX idx_analytics_events_occurred_at ON analytics_events(occurred_at)
+WHERE user_ip_hash IS NOT NULL;
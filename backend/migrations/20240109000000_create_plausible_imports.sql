@@ -0,0 +1,33 @@
+-- Stats imported from a Plausible CSV export, kept apart from the events collected
+-- here. Rows of the dashboard export cover the whole exported period, rows of the
+-- site export a single day (period_start = period_end).
+CREATE TABLE imported_daily_visitors (
+    day DATE PRIMARY KEY,
+    visitors BIGINT NOT NULL,
+    pageviews BIGINT NOT NULL
+);
+
+CREATE TABLE imported_page_stats (
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    period_start DATE NOT NULL,
+    period_end DATE NOT NULL,
+    visitors BIGINT NOT NULL,
+    pageviews BIGINT NOT NULL,
+    PRIMARY KEY (post_slug, period_start, period_end)
+);
+
+CREATE TABLE imported_source_stats (
+    source VARCHAR NOT NULL,
+    period_start DATE NOT NULL,
+    period_end DATE NOT NULL,
+    visitors BIGINT NOT NULL,
+    PRIMARY KEY (source, period_start, period_end)
+);
+
+CREATE TABLE imported_country_stats (
+    country VARCHAR NOT NULL,
+    period_start DATE NOT NULL,
+    period_end DATE NOT NULL,
+    visitors BIGINT NOT NULL,
+    PRIMARY KEY (country, period_start, period_end)
+);
@@ -0,0 +1,23 @@
+-- Normalized origins of likes and views: the referring host without www. and the
+-- UTM parameters of the post URL, '' when absent so the combination is unique
+CREATE TABLE referrers (
+    id SERIAL PRIMARY KEY,
+    host VARCHAR(253) NOT NULL DEFAULT '',
+    utm_source VARCHAR(100) NOT NULL DEFAULT '',
+    utm_medium VARCHAR(100) NOT NULL DEFAULT '',
+    utm_campaign VARCHAR(100) NOT NULL DEFAULT '',
+    UNIQUE (host, utm_source, utm_medium, utm_campaign)
+);
+
+-- NULL for likes without a known origin
+ALTER TABLE blog_post_likes ADD COLUMN referrer_id INTEGER REFERENCES referrers(id);
+
+-- Views per post, day and origin, views without a known origin are only counted in
+-- post_views
+CREATE TABLE post_referrer_views (
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    day DATE NOT NULL,
+    referrer_id INTEGER NOT NULL REFERENCES referrers(id),
+    views BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (post_slug, day, referrer_id)
+);
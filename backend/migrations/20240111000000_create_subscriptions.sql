@@ -0,0 +1,37 @@
+-- Newsletter subscribers, confirmed through a signed link sent to the address
+CREATE TABLE subscribers (
+    id SERIAL PRIMARY KEY,
+    email VARCHAR(254) NOT NULL UNIQUE, -- lowercased
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'confirmed', 'unsubscribed')),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    confirmation_sent_at TIMESTAMP WITH TIME ZONE,
+    confirmed_at TIMESTAMP WITH TIME ZONE,
+    unsubscribed_at TIMESTAMP WITH TIME ZONE
+);
+
+-- Posts are announced once, in the first digest after they are ingested.
+-- Posts published before the newsletter existed are never announced.
+ALTER TABLE blog_posts ADD COLUMN announced BOOLEAN NOT NULL DEFAULT false;
+UPDATE blog_posts SET announced = true;
+
+CREATE TABLE newsletter_digests (
+    id SERIAL PRIMARY KEY,
+    post_slugs VARCHAR[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Delivery of a digest to each subscriber confirmed when it was created
+CREATE TABLE newsletter_deliveries (
+    digest_id INTEGER NOT NULL REFERENCES newsletter_digests(id),
+    subscriber_id INTEGER NOT NULL REFERENCES subscribers(id),
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'sent', 'failed')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (digest_id, subscriber_id)
+);
+
+CREATE INDEX idx_newsletter_deliveries_status ON newsletter_deliveries(status)
+WHERE status <> 'sent';
@@ -0,0 +1,19 @@
+-- Key pair of the ActivityPub actor, generated on the first start. Followers
+-- verify our deliveries with its public half, so it has to survive restarts.
+CREATE TABLE activitypub_keys (
+    id BOOLEAN PRIMARY KEY DEFAULT true CHECK (id), -- a single row
+    private_key_pem TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE activitypub_followers (
+    actor_id VARCHAR(2048) PRIMARY KEY,
+    inbox VARCHAR(2048) NOT NULL,
+    shared_inbox VARCHAR(2048),
+    followed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Posts are delivered to the followers once, after they are first ingested.
+-- Posts published before the actor existed are only listed in the outbox.
+ALTER TABLE blog_posts ADD COLUMN federated BOOLEAN NOT NULL DEFAULT false;
+UPDATE blog_posts SET federated = true;
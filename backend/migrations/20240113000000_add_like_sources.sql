@@ -0,0 +1,24 @@
+-- Likes come from the blog itself ('web') or from fediverse favourites and boosts
+-- of the federated posts ('fediverse')
+ALTER TABLE blog_post_likes
+ADD COLUMN source VARCHAR(16) NOT NULL DEFAULT 'web'
+    CHECK (source IN ('web', 'fediverse'));
+
+-- Actor who favourited or boosted, so repeated and undone activities can be matched.
+-- Fediverse likes carry no IP hash and are never rate limited by hour_bucket.
+ALTER TABLE blog_post_likes ADD COLUMN remote_actor VARCHAR(2048);
+
+CREATE UNIQUE INDEX idx_blog_post_likes_remote_actor
+ON blog_post_likes(post_slug, reaction, remote_actor)
+WHERE remote_actor IS NOT NULL;
+
+-- Denormalized per source counters, blog_posts.like_count stays the total
+CREATE TABLE blog_post_source_counts (
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    source VARCHAR(16) NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (post_slug, source)
+);
+
+INSERT INTO blog_post_source_counts (post_slug, source, count)
+SELECT post_slug, source, COUNT(*) FROM blog_post_likes GROUP BY post_slug, source;
@@ -0,0 +1,15 @@
+-- Cross-posts of blog posts on Bluesky, at most one per post. Posts that existed
+-- before are recorded as skipped, so only new posts are cross-posted.
+CREATE TABLE bluesky_posts (
+    post_slug VARCHAR PRIMARY KEY REFERENCES blog_posts(slug),
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'posted', 'failed', 'skipped')),
+    uri VARCHAR(512), -- at:// URI of the app.bsky.feed.post record
+    cid VARCHAR(128),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    posted_at TIMESTAMP WITH TIME ZONE
+);
+
+INSERT INTO bluesky_posts (post_slug, status) SELECT slug, 'skipped' FROM blog_posts;
@@ -0,0 +1,44 @@
+-- Durable queue of cross-posts: one job per post and target, retried with a
+-- backoff until it succeeds or runs out of attempts
+CREATE TABLE syndication_outbox (
+    id SERIAL PRIMARY KEY,
+    post_slug VARCHAR NOT NULL REFERENCES blog_posts(slug),
+    target VARCHAR(32) NOT NULL, -- 'activitypub', 'bluesky'
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'done', 'failed', 'skipped')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_error TEXT,
+    result VARCHAR(2048), -- what the target returned, ie. the at:// URI of the Bluesky post
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE,
+    UNIQUE (post_slug, target)
+);
+
+CREATE INDEX idx_syndication_outbox_due ON syndication_outbox(next_attempt_at)
+WHERE status = 'pending';
+
+-- Federated posts were delivered or predate the actor, either way they are not sent again
+INSERT INTO syndication_outbox (post_slug, target, status)
+SELECT slug, 'activitypub', CASE WHEN federated THEN 'skipped' ELSE 'pending' END
+FROM blog_posts;
+
+ALTER TABLE blog_posts DROP COLUMN federated;
+
+INSERT INTO syndication_outbox (post_slug, target, status, attempts, last_error, result, completed_at)
+SELECT post_slug, 'bluesky', CASE WHEN status = 'posted' THEN 'done' ELSE status END,
+    attempts, last_error, uri, posted_at
+FROM bluesky_posts;
+
+-- The outbox tracks the status, bluesky_posts only keeps the records of the posts
+DELETE FROM bluesky_posts WHERE uri IS NULL;
+ALTER TABLE bluesky_posts
+    DROP COLUMN status,
+    DROP COLUMN attempts,
+    DROP COLUMN last_error,
+    DROP COLUMN created_at,
+    ALTER COLUMN uri SET NOT NULL,
+    ALTER COLUMN cid SET NOT NULL,
+    ALTER COLUMN posted_at SET NOT NULL,
+    ALTER COLUMN posted_at SET DEFAULT NOW();
@@ -0,0 +1,13 @@
+-- Plain text of the post, from the optional `content` of the Hugo export
+ALTER TABLE blog_posts ADD COLUMN content TEXT;
+
+-- Full-text search document, kept up to date by every insert and update of a post.
+-- Matches in the title rank above the tags, the description and the body.
+ALTER TABLE blog_posts ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('english', replace(coalesce(tags, ''), ',', ' ')), 'B') ||
+    setweight(to_tsvector('english', coalesce(description, '')), 'C') ||
+    setweight(to_tsvector('english', coalesce(content, '')), 'D')
+) STORED;
+
+CREATE INDEX idx_blog_posts_search ON blog_posts USING GIN (search_vector);
@@ -0,0 +1,8 @@
+-- Optional metadata of newer Hugo exports, NULL for posts ingested from older ones
+ALTER TABLE blog_posts
+    ADD COLUMN lastmod TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN draft BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN reading_time INTEGER, -- minutes, as estimated by Hugo
+    ADD COLUMN word_count INTEGER,
+    ADD COLUMN series TEXT, -- comma-separated list of series, like tags
+    ADD COLUMN summary TEXT;
@@ -0,0 +1,8 @@
+-- The outbox is published by `syndication` jobs, which retry on their own schedule.
+-- Entries that were waiting for the outbox worker get a job.
+INSERT INTO jobs (kind, payload, run_at)
+SELECT 'syndication', jsonb_build_object('outbox_id', id), next_attempt_at
+FROM syndication_outbox
+WHERE status = 'pending';
+
+DROP INDEX idx_syndication_outbox_due;
@@ -0,0 +1,9 @@
+-- Visitor of the day, kept after the retention job nulls the hashed IP so the
+-- visitors of old periods can still be counted
+ALTER TABLE analytics_events ADD COLUMN visitor_id BIGINT;
+
+-- Existing events get an id of the same shape from their hashed IP and day. Events
+-- already stripped by the retention job can't be recovered and stay without one.
+UPDATE analytics_events
+SET visitor_id = ('x' || left(md5(occurred_at::date || ':' || user_ip_hash), 16))::bit(64)::bigint
+WHERE user_ip_hash IS NOT NULL;
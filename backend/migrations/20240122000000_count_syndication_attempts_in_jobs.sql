@@ -0,0 +1,28 @@
+-- The `syndication` job of an entry counts its attempts and schedules its retries,
+-- the outbox only points at the latest one
+ALTER TABLE syndication_outbox
+ADD COLUMN job_id BIGINT REFERENCES jobs(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_syndication_outbox_job_id ON syndication_outbox(job_id);
+
+UPDATE syndication_outbox
+SET job_id = latest.id
+FROM (
+    SELECT DISTINCT ON (payload->>'outbox_id') id, (payload->>'outbox_id')::INTEGER AS outbox_id
+    FROM jobs
+    WHERE kind = 'syndication'
+    ORDER BY payload->>'outbox_id', id DESC
+) AS latest
+WHERE syndication_outbox.id = latest.outbox_id;
+
+-- Entries whose job gave up while the outbox still counted its own attempts
+UPDATE syndication_outbox
+SET status = 'failed', last_error = jobs.last_error, updated_at = NOW()
+FROM jobs
+WHERE jobs.id = syndication_outbox.job_id
+    AND syndication_outbox.status = 'pending'
+    AND jobs.status = 'failed';
+
+ALTER TABLE syndication_outbox
+    DROP COLUMN attempts,
+    DROP COLUMN next_attempt_at;
@@ -0,0 +1,26 @@
+-- The `webhook_delivery` job of a delivery counts its attempts and schedules its
+-- retries, the delivery log only points at it
+ALTER TABLE webhook_deliveries
+ADD COLUMN job_id BIGINT REFERENCES jobs(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_webhook_deliveries_job_id ON webhook_deliveries(job_id);
+
+UPDATE webhook_deliveries
+SET job_id = latest.id
+FROM (
+    SELECT DISTINCT ON (payload->>'delivery_id') id, (payload->>'delivery_id')::BIGINT AS delivery_id
+    FROM jobs
+    WHERE kind = 'webhook_delivery'
+    ORDER BY payload->>'delivery_id', id DESC
+) AS latest
+WHERE webhook_deliveries.id = latest.delivery_id;
+
+-- Deliveries whose job gave up while the log still counted its own attempts
+UPDATE webhook_deliveries
+SET status = 'failed', updated_at = NOW()
+FROM jobs
+WHERE jobs.id = webhook_deliveries.job_id
+    AND webhook_deliveries.status = 'pending'
+    AND jobs.status = 'failed';
+
+ALTER TABLE webhook_deliveries DROP COLUMN attempts;
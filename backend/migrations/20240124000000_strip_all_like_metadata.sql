@@ -0,0 +1,9 @@
+-- The retention job strips every column of personal metadata, a like keeps being
+-- scanned until none is left
+DROP INDEX idx_blog_post_likes_liked_at;
+
+CREATE INDEX idx_blog_post_likes_liked_at ON blog_post_likes(liked_at)
+WHERE user_agent IS NOT NULL
+    OR user_ip_hash IS NOT NULL
+    OR cf_connecting_ip_hash IS NOT NULL
+    OR cf_country IS NOT NULL;
@@ -0,0 +1,10 @@
+-- The actor id of a fediverse like is personal data too, stripped with the rest of
+-- the metadata after the retention period
+DROP INDEX idx_blog_post_likes_liked_at;
+
+CREATE INDEX idx_blog_post_likes_liked_at ON blog_post_likes(liked_at)
+WHERE user_agent IS NOT NULL
+    OR user_ip_hash IS NOT NULL
+    OR cf_connecting_ip_hash IS NOT NULL
+    OR cf_country IS NOT NULL
+    OR remote_actor IS NOT NULL;
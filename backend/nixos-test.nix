@@ -86,6 +86,7 @@ let
             const likeCount = document.getElementById('like-count');
             const errorMessage = document.getElementById('error-message');
             const postSlug = likeButton.dataset.slug;
+            let likeToken = null;
 
             // Load initial like count
             async function loadLikeCount() {
@@ -94,6 +95,7 @@ let
                     const data = await response.json();
                     if (data.success) {
                         likeCount.textContent = `$${data.total_likes} likes`;
+                        likeToken = data.like_token;
                     } else {
                         throw new Error(data.message);
                     }
@@ -113,7 +115,8 @@ let
                     const response = await fetch(`/api/like/$${postSlug}`, {
                         method: 'POST',
                         headers: {
-                            'Content-Type': 'application/json'
+                            'Content-Type': 'application/json',
+                            'X-Like-Token': likeToken
                         }
                     });
 
@@ -261,6 +264,7 @@ in
       services.backend = {
         enable = true;
         domain = "server";
+        postsDomain = "blog.flakm.com";
         posts_path = "${testBlogPosts}";
       };
       
@@ -283,7 +287,7 @@ in
             };
             
             # API endpoints to backend
-            "~ ^/api/(health|like|likes)" = {
+            "~ ^/api/(health|like|likes|privacy)" = {
               proxyPass = "http://127.0.0.1:3000";
               extraConfig = ''
                 proxy_set_header Host $host;
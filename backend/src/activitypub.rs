@@ -0,0 +1,766 @@
+use crate::{
+    correlation::CorrelationContext,
+    likes::sources::{self, FEDIVERSE_BOOST, FEDIVERSE_FAVOURITE},
+};
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use remote::{check_actor_key, check_remote_url, ActorCache, PublicResolver};
+use rsa::{
+    pkcs8::{DecodePrivateKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    RsaPrivateKey,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use signatures::SignatureHeader;
+use sqlx::PgPool;
+use std::{sync::Arc, time::SystemTime};
+use tracing::{info, instrument, warn};
+use url::Url;
+
+pub mod delivery;
+pub mod remote;
+pub mod signatures;
+
+pub const ACTIVITY_CONTENT_TYPE: &str = "application/activity+json";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+/// Posts listed in the outbox, the newest first
+const OUTBOX_SIZE: i64 = 20;
+/// How long the actor of a key is trusted without fetching it again
+const ACTOR_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Identity of the blog in the fediverse, read from the environment
+#[derive(Debug, Clone)]
+pub struct ActivityPubConfig {
+    /// Domain of the `@username@domain` handle, WebFinger is answered for it
+    pub domain: String,
+    pub username: String,
+    pub display_name: String,
+    pub summary: String,
+    /// Public URL of the backend, the ids of the actor and its objects start with it
+    pub base_url: Url,
+}
+
+impl ActivityPubConfig {
+    /// Reads `ACTIVITYPUB_DOMAIN`, `ACTIVITYPUB_USERNAME`, `ACTIVITYPUB_DISPLAY_NAME`,
+    /// `ACTIVITYPUB_SUMMARY` and `ACTIVITYPUB_BASE_URL`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let domain =
+            std::env::var("ACTIVITYPUB_DOMAIN").unwrap_or_else(|_| "blog.flakm.com".to_string());
+        let username = std::env::var("ACTIVITYPUB_USERNAME").unwrap_or_else(|_| "blog".to_string());
+        let display_name =
+            std::env::var("ACTIVITYPUB_DISPLAY_NAME").unwrap_or_else(|_| domain.clone());
+        let summary = std::env::var("ACTIVITYPUB_SUMMARY")
+            .unwrap_or_else(|_| format!("New posts from {domain}"));
+        let base_url = std::env::var("ACTIVITYPUB_BASE_URL")
+            .unwrap_or_else(|_| format!("https://{domain}/api"))
+            .parse()?;
+
+        Ok(Self {
+            domain,
+            username,
+            display_name,
+            summary,
+            base_url,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url.as_str().trim_end_matches('/'))
+    }
+
+    pub fn actor_id(&self) -> String {
+        self.url("/activitypub/actor")
+    }
+
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id())
+    }
+
+    pub fn inbox(&self) -> String {
+        self.url("/activitypub/inbox")
+    }
+
+    pub fn outbox(&self) -> String {
+        self.url("/activitypub/outbox")
+    }
+
+    pub fn followers(&self) -> String {
+        self.url("/activitypub/followers")
+    }
+
+    pub fn article_id(&self, slug: &str) -> String {
+        self.url(&format!("/activitypub/posts/{slug}"))
+    }
+
+    /// Slug of the post an [`Self::article_id`] points to
+    pub fn article_slug<'a>(&self, id: &'a str) -> Option<&'a str> {
+        id.strip_prefix(&self.article_id(""))
+            .filter(|slug| !slug.is_empty() && !slug.contains(['/', '?', '#']))
+    }
+
+    /// Path the senders sign, nginx strips the prefix of `base_url` before it reaches us
+    pub fn public_path(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url.path().trim_end_matches('/'))
+    }
+}
+
+/// Blog post as it is federated
+#[derive(Debug, Clone)]
+pub struct FederatedPost {
+    pub slug: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub date: DateTime<Utc>,
+    /// Comma separated, as stored in `blog_posts`
+    pub tags: Option<String>,
+    pub url: String,
+}
+
+/// The actor of the blog, its key and the client talking to other servers
+pub struct Federation {
+    pub config: ActivityPubConfig,
+    private_key: RsaPrivateKey,
+    public_key_pem: String,
+    http: reqwest::Client,
+    actors: ActorCache,
+}
+
+impl Federation {
+    pub fn new(config: ActivityPubConfig, private_key: RsaPrivateKey) -> anyhow::Result<Self> {
+        let public_key_pem = private_key
+            .to_public_key()
+            .to_public_key_pem(LineEnding::LF)?;
+        // The URLs come from other servers, redirects are checked like the URLs themselves
+        let redirects = reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= 5 || check_remote_url(attempt.url()).is_err() {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        });
+        let http = reqwest::Client::builder()
+            .user_agent(format!("blog-backend (+https://{})", config.domain))
+            .timeout(std::time::Duration::from_secs(10))
+            .dns_resolver(Arc::new(PublicResolver))
+            .redirect(redirects)
+            .build()?;
+
+        Ok(Self {
+            config,
+            private_key,
+            public_key_pem,
+            http,
+            actors: ActorCache::new(ACTOR_CACHE_TTL),
+        })
+    }
+
+    /// Configuration from the environment and the key of the actor from the database,
+    /// generated on the first start so followers keep trusting it
+    pub async fn from_env(pool: &PgPool) -> anyhow::Result<Self> {
+        let config = ActivityPubConfig::from_env()?;
+        let private_key = load_or_create_key(pool).await?;
+        Self::new(config, private_key)
+    }
+
+    pub fn actor_document(&self) -> Value {
+        let config = &self.config;
+        json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/v1"
+            ],
+            "id": config.actor_id(),
+            "type": "Service",
+            "preferredUsername": config.username,
+            "name": config.display_name,
+            "summary": config.summary,
+            "url": format!("https://{}/", config.domain),
+            "inbox": config.inbox(),
+            "outbox": config.outbox(),
+            "followers": config.followers(),
+            "endpoints": { "sharedInbox": config.inbox() },
+            "manuallyApprovesFollowers": false,
+            "discoverable": true,
+            "publicKey": {
+                "id": config.key_id(),
+                "owner": config.actor_id(),
+                "publicKeyPem": self.public_key_pem
+            }
+        })
+    }
+
+    /// Actor that owns `key_id`, from the cache or fetched and checked with
+    /// [`check_actor_key`]
+    pub async fn signing_actor(&self, key_id: &str) -> Result<RemoteActor, &'static str> {
+        if let Some(cached) = self.actors.get(key_id) {
+            return cached.ok_or("unknown_key");
+        }
+
+        let actor = match self.fetch_actor(key_id).await {
+            Ok(actor) => check_actor_key(&actor, key_id).map(|_| actor),
+            Err(e) => {
+                info!(error = %e, key_id, "Could not fetch the signing actor");
+                Err("unknown_key")
+            }
+        };
+        self.actors.insert(key_id, actor.as_ref().ok().cloned());
+        actor
+    }
+
+    /// Fetch the actor owning `key_id`, with a signed request for servers in secure mode
+    pub async fn fetch_actor(&self, key_id: &str) -> anyhow::Result<RemoteActor> {
+        let mut url: Url = key_id.parse()?;
+        url.set_fragment(None);
+        check_remote_url(&url).map_err(anyhow::Error::msg)?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static(ACTIVITY_CONTENT_TYPE),
+        );
+        signatures::sign_request(
+            &self.private_key,
+            &self.config.key_id(),
+            &Method::GET,
+            &url,
+            &mut headers,
+            None,
+            SystemTime::now(),
+        )?;
+
+        let actor = self
+            .http
+            .get(url)
+            .headers(headers)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<RemoteActor>()
+            .await?;
+        Ok(actor)
+    }
+
+    /// POST a signed activity to an inbox
+    pub async fn deliver(&self, inbox: &str, activity: &Value) -> anyhow::Result<()> {
+        let url: Url = inbox.parse()?;
+        check_remote_url(&url).map_err(anyhow::Error::msg)?;
+        let body = serde_json::to_vec(activity)?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(ACTIVITY_CONTENT_TYPE),
+        );
+        signatures::sign_request(
+            &self.private_key,
+            &self.config.key_id(),
+            &Method::POST,
+            &url,
+            &mut headers,
+            Some(&body),
+            SystemTime::now(),
+        )?;
+
+        self.http
+            .post(url)
+            .headers(headers)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+async fn load_or_create_key(pool: &PgPool) -> anyhow::Result<RsaPrivateKey> {
+    let stored = sqlx::query_scalar!("SELECT private_key_pem FROM activitypub_keys")
+        .fetch_optional(pool)
+        .await?;
+
+    let pem = match stored {
+        Some(pem) => pem,
+        None => {
+            info!("Generating the key of the ActivityPub actor");
+            let key =
+                tokio::task::spawn_blocking(|| RsaPrivateKey::new(&mut rand::thread_rng(), 2048))
+                    .await??;
+            let pem = key.to_pkcs8_pem(LineEnding::LF)?.to_string();
+            // Another instance may have been faster, its key wins
+            sqlx::query!(
+                "INSERT INTO activitypub_keys (private_key_pem) VALUES ($1) ON CONFLICT DO NOTHING",
+                pem
+            )
+            .execute(pool)
+            .await?;
+            sqlx::query_scalar!("SELECT private_key_pem FROM activitypub_keys")
+                .fetch_one(pool)
+                .await?
+        }
+    };
+
+    Ok(RsaPrivateKey::from_pkcs8_pem(&pem)?)
+}
+
+/// Actor of another server, only the parts we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    #[serde(default)]
+    pub endpoints: Option<RemoteEndpoints>,
+    #[serde(rename = "publicKey")]
+    pub public_key: RemotePublicKey,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteEndpoints {
+    #[serde(rename = "sharedInbox")]
+    pub shared_inbox: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemotePublicKey {
+    pub id: String,
+    /// Actor the key belongs to, must be the actor it was fetched for
+    pub owner: String,
+    #[serde(rename = "publicKeyPem")]
+    pub public_key_pem: String,
+}
+
+impl RemoteActor {
+    pub fn shared_inbox(&self) -> Option<&str> {
+        self.endpoints.as_ref()?.shared_inbox.as_deref()
+    }
+}
+
+/// `acct:` resource of the blog, `None` for any other resource
+pub fn webfinger_document(config: &ActivityPubConfig, resource: &str) -> Option<Value> {
+    let handle = format!("acct:{}@{}", config.username, config.domain);
+    if !resource.eq_ignore_ascii_case(&handle) && resource != config.actor_id() {
+        return None;
+    }
+    Some(json!({
+        "subject": handle,
+        "aliases": [config.actor_id()],
+        "links": [
+            {
+                "rel": "self",
+                "type": ACTIVITY_CONTENT_TYPE,
+                "href": config.actor_id()
+            },
+            {
+                "rel": "http://webfinger.net/rel/profile-page",
+                "type": "text/html",
+                "href": format!("https://{}/", config.domain)
+            }
+        ]
+    }))
+}
+
+pub fn article(config: &ActivityPubConfig, post: &FederatedPost) -> Value {
+    let mut content = format!(
+        r#"<p><a href="{}">{}</a></p>"#,
+        escape_html(&post.url),
+        escape_html(&post.title)
+    );
+    if let Some(description) = post.description.as_deref().filter(|d| !d.is_empty()) {
+        content.push_str(&format!("<p>{}</p>", escape_html(description)));
+    }
+
+    let tags: Vec<Value> = post
+        .tags
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| {
+            json!({
+                "type": "Hashtag",
+                "href": format!("https://{}/tags/{}/", config.domain, tag.to_lowercase()),
+                "name": format!("#{}", tag.replace(' ', ""))
+            })
+        })
+        .collect();
+
+    json!({
+        "id": config.article_id(&post.slug),
+        "type": "Article",
+        "attributedTo": config.actor_id(),
+        "name": post.title,
+        "content": content,
+        "url": post.url,
+        "published": post.date.to_rfc3339(),
+        "to": [PUBLIC],
+        "cc": [config.followers()],
+        "tag": tags
+    })
+}
+
+pub fn create_activity(config: &ActivityPubConfig, post: &FederatedPost) -> Value {
+    let object = article(config, post);
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#create", config.article_id(&post.slug)),
+        "type": "Create",
+        "actor": config.actor_id(),
+        "published": object["published"],
+        "to": [PUBLIC],
+        "cc": [config.followers()],
+        "object": object
+    })
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn activity_response(document: Value) -> Response {
+    (
+        [(header::CONTENT_TYPE, ACTIVITY_CONTENT_TYPE)],
+        document.to_string(),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebfingerQuery {
+    pub resource: String,
+}
+
+/// `/.well-known/webfinger`, lets `@username@domain` be found from other servers
+#[instrument(skip(federation))]
+pub async fn webfinger(
+    Query(query): Query<WebfingerQuery>,
+    State(federation): State<Arc<Federation>>,
+) -> Response {
+    match webfinger_document(&federation.config, &query.resource) {
+        Some(document) => (
+            [(header::CONTENT_TYPE, "application/jrd+json")],
+            document.to_string(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+pub async fn actor(State(federation): State<Arc<Federation>>) -> Response {
+    activity_response(federation.actor_document())
+}
+
+/// Create activities of the latest posts
+#[instrument(skip(pool, federation))]
+pub async fn outbox(
+    State(pool): State<PgPool>,
+    State(federation): State<Arc<Federation>>,
+) -> Result<Response, StatusCode> {
+    let config = &federation.config;
+    let posts = sqlx::query_as!(
+        FederatedPost,
+        "SELECT slug, title, description, date, tags, url FROM blog_posts WHERE NOT draft ORDER BY date DESC LIMIT $1",
+        OUTBOX_SIZE
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error loading the outbox");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let total =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM blog_posts WHERE NOT draft"#)
+            .fetch_one(&pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let items: Vec<Value> = posts
+        .iter()
+        .map(|post| create_activity(config, post))
+        .collect();
+
+    Ok(activity_response(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": config.outbox(),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items
+    })))
+}
+
+/// Only the number of followers, the list is not public
+#[instrument(skip(pool, federation))]
+pub async fn followers(
+    State(pool): State<PgPool>,
+    State(federation): State<Arc<Federation>>,
+) -> Result<Response, StatusCode> {
+    let total = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM activitypub_followers"#)
+        .fetch_one(&pool)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "Database error counting followers");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(activity_response(json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": federation.config.followers(),
+        "type": "OrderedCollection",
+        "totalItems": total
+    })))
+}
+
+/// The Article of a post, its id is dereferenced by servers that see it
+#[instrument(skip(pool, federation))]
+pub async fn post_article(
+    Path(slug): Path<String>,
+    State(pool): State<PgPool>,
+    State(federation): State<Arc<Federation>>,
+) -> Result<Response, StatusCode> {
+    let post = sqlx::query_as!(
+        FederatedPost,
+        "SELECT slug, title, description, date, tags, url FROM blog_posts WHERE slug = $1 AND NOT draft",
+        slug
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error loading a federated post");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut document = article(&federation.config, &post);
+    document["@context"] = json!("https://www.w3.org/ns/activitystreams");
+    Ok(activity_response(document))
+}
+
+/// Activity received in the inbox, only the fields we act on
+#[derive(Debug, Deserialize)]
+pub struct InboxActivity {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub actor: Value,
+    #[serde(default)]
+    pub object: Value,
+}
+
+/// `id` of an object that can be embedded or only referenced
+pub fn object_id(value: &Value) -> Option<&str> {
+    value.as_str().or_else(|| value.get("id")?.as_str())
+}
+
+/// Reaction a favourite (`Like`) or boost (`Announce`) of a post is counted as
+pub fn fediverse_reaction(kind: &str) -> Option<&'static str> {
+    match kind {
+        "Like" => Some(FEDIVERSE_FAVOURITE),
+        "Announce" => Some(FEDIVERSE_BOOST),
+        _ => None,
+    }
+}
+
+/// Shared inbox of the actor, follows, favourites and boosts (and undoing them)
+/// are handled here
+///
+/// Every activity has to carry a valid HTTP signature of its actor. Activities we
+/// do not act on are accepted and dropped.
+#[instrument(skip(pool, federation, correlation_ctx, headers, body))]
+pub async fn inbox(
+    State(pool): State<PgPool>,
+    State(federation): State<Arc<Federation>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let start_time = std::time::Instant::now();
+    counter!("blog_activitypub_inbox_requests_total").increment(1);
+
+    let status = match handle_inbox(&pool, &federation, &headers, &body).await {
+        Ok(kind) => {
+            counter!("blog_activitypub_activities_total", "type" => kind).increment(1);
+            StatusCode::ACCEPTED
+        }
+        Err(rejection) => {
+            info!(
+                reason = rejection.reason,
+                correlation_id = %correlation_ctx.correlation_id,
+                "Inbox activity rejected"
+            );
+            counter!("blog_activitypub_inbox_rejected_total", "reason" => rejection.reason)
+                .increment(1);
+            rejection.status
+        }
+    };
+
+    histogram!("blog_activitypub_inbox_duration_ms", "status" => status.as_str().to_string())
+        .record(start_time.elapsed().as_millis() as f64);
+    status
+}
+
+struct InboxRejection {
+    status: StatusCode,
+    reason: &'static str,
+}
+
+impl InboxRejection {
+    fn unauthorized(reason: &'static str) -> Self {
+        Self {
+            status: StatusCode::UNAUTHORIZED,
+            reason,
+        }
+    }
+
+    fn bad_request(reason: &'static str) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            reason,
+        }
+    }
+}
+
+/// Verify and apply an activity, returns the label it is counted under
+async fn handle_inbox(
+    pool: &PgPool,
+    federation: &Arc<Federation>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<&'static str, InboxRejection> {
+    let activity: InboxActivity =
+        serde_json::from_slice(body).map_err(|_| InboxRejection::bad_request("invalid_json"))?;
+
+    let signature = headers
+        .get("signature")
+        .and_then(|h| h.to_str().ok())
+        .ok_or(InboxRejection::unauthorized("missing_signature"))
+        .and_then(|value| SignatureHeader::parse(value).map_err(InboxRejection::unauthorized))?;
+
+    // Everything that needs no key is checked before the key is fetched
+    signatures::check_request(&signature, &Method::POST, headers, body, SystemTime::now())
+        .map_err(InboxRejection::unauthorized)?;
+
+    let remote = federation
+        .signing_actor(&signature.key_id)
+        .await
+        .map_err(InboxRejection::unauthorized)?;
+    let public_key = signatures::parse_public_key(&remote.public_key.public_key_pem)
+        .map_err(InboxRejection::unauthorized)?;
+    signatures::verify_request(
+        &public_key,
+        &signature,
+        &Method::POST,
+        &federation.config.public_path("/activitypub/inbox"),
+        headers,
+        body,
+        SystemTime::now(),
+    )
+    .map_err(InboxRejection::unauthorized)?;
+
+    if object_id(&activity.actor) != Some(remote.id.as_str()) {
+        return Err(InboxRejection::unauthorized("actor_mismatch"));
+    }
+
+    let database_error = |e: sqlx::Error| {
+        warn!(error = %e, actor = %remote.id, "Database error handling inbox activity");
+        InboxRejection {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            reason: "database_error",
+        }
+    };
+
+    match activity.kind.as_str() {
+        "Follow" if object_id(&activity.object) == Some(federation.config.actor_id().as_str()) => {
+            sqlx::query!(
+                r#"
+                INSERT INTO activitypub_followers (actor_id, inbox, shared_inbox)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (actor_id) DO UPDATE
+                SET inbox = excluded.inbox, shared_inbox = excluded.shared_inbox
+                "#,
+                remote.id,
+                remote.inbox,
+                remote.shared_inbox()
+            )
+            .execute(pool)
+            .await
+            .map_err(database_error)?;
+            info!(actor = %remote.id, "New follower");
+
+            let accept = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": format!("{}#accepts/{}", federation.config.actor_id(), uuid::Uuid::new_v4()),
+                "type": "Accept",
+                "actor": federation.config.actor_id(),
+                "object": serde_json::from_slice::<Value>(body).unwrap_or_default()
+            });
+            delivery::spawn_delivery(federation.clone(), remote.inbox.clone(), accept);
+            Ok("follow")
+        }
+        "Undo" if activity.object.get("type").and_then(Value::as_str) == Some("Follow") => {
+            sqlx::query!(
+                "DELETE FROM activitypub_followers WHERE actor_id = $1",
+                remote.id
+            )
+            .execute(pool)
+            .await
+            .map_err(database_error)?;
+            info!(actor = %remote.id, "Follower left");
+            Ok("undo_follow")
+        }
+        "Like" | "Announce" => {
+            let Some(post_slug) =
+                object_id(&activity.object).and_then(|id| federation.config.article_slug(id))
+            else {
+                return Ok("ignored");
+            };
+            let reaction = fediverse_reaction(&activity.kind).unwrap_or(FEDIVERSE_FAVOURITE);
+            match sources::record_fediverse_like(pool, post_slug, reaction, &remote.id).await {
+                Ok(Some(counts)) => {
+                    info!(post_slug, reaction, actor = %remote.id, total_likes = counts.total, "Fediverse like recorded");
+                    Ok(if reaction == FEDIVERSE_BOOST {
+                        "announce"
+                    } else {
+                        "like"
+                    })
+                }
+                Ok(None) => Ok("duplicate"),
+                // Not one of our posts
+                Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => Ok("ignored"),
+                Err(e) => Err(database_error(e)),
+            }
+        }
+        "Undo" => {
+            let undone = &activity.object;
+            let (Some(reaction), Some(post_slug)) = (
+                undone
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .and_then(fediverse_reaction),
+                undone
+                    .get("object")
+                    .and_then(object_id)
+                    .and_then(|id| federation.config.article_slug(id)),
+            ) else {
+                return Ok("ignored");
+            };
+            let removed = sources::remove_fediverse_like(pool, post_slug, reaction, &remote.id)
+                .await
+                .map_err(database_error)?;
+            if let Some(counts) = removed {
+                info!(post_slug, reaction, actor = %remote.id, total_likes = counts.total, "Fediverse like undone");
+            }
+            Ok(if reaction == FEDIVERSE_BOOST {
+                "undo_announce"
+            } else {
+                "undo_like"
+            })
+        }
+        _ => Ok("ignored"),
+    }
+}
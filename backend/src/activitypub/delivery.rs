@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use metrics::{counter, histogram};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::{info, instrument, warn};
+
+use super::{create_activity, FederatedPost, Federation};
+
+/// Send the Create activity of a post to the inboxes of the followers
+///
+/// Returns the number of inboxes that accepted it. Followers on the same server
+/// share an inbox and get the post once. It only fails when no inbox accepted the
+/// post, one unreachable server must not make everyone get it again on retry.
+#[instrument(skip(pool, federation))]
+pub async fn federate_post(
+    pool: &PgPool,
+    federation: &Federation,
+    post_slug: &str,
+) -> anyhow::Result<usize> {
+    let post = sqlx::query_as!(
+        FederatedPost,
+        "SELECT slug, title, description, date, tags, url FROM blog_posts WHERE slug = $1",
+        post_slug
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let inboxes = sqlx::query_scalar!(
+        r#"SELECT DISTINCT COALESCE(shared_inbox, inbox) AS "inbox!" FROM activitypub_followers"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let activity = create_activity(&federation.config, &post);
+    let mut delivered = 0;
+    let mut last_error = None;
+    for inbox in &inboxes {
+        match deliver(federation, inbox, &activity).await {
+            Ok(()) => delivered += 1,
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    info!(
+        post_slug,
+        inboxes = inboxes.len(),
+        delivered,
+        "Post federated"
+    );
+    match last_error {
+        Some(e) if delivered == 0 => Err(e).context("No inbox accepted the post"),
+        _ => Ok(delivered),
+    }
+}
+
+/// Deliver in the background, for replies that should not hold up the inbox response
+pub fn spawn_delivery(federation: Arc<Federation>, inbox: String, activity: Value) {
+    tokio::spawn(async move {
+        let _ = deliver(&federation, &inbox, &activity).await;
+    });
+}
+
+async fn deliver(federation: &Federation, inbox: &str, activity: &Value) -> anyhow::Result<()> {
+    let start_time = std::time::Instant::now();
+    let kind = match activity["type"].as_str() {
+        Some("Create") => "create",
+        Some("Accept") => "accept",
+        _ => "other",
+    };
+
+    let delivered = federation.deliver(inbox, activity).await;
+    let status = match &delivered {
+        Ok(()) => "delivered",
+        Err(e) => {
+            warn!(error = %e, inbox, "Failed to deliver activity");
+            "failed"
+        }
+    };
+
+    counter!("blog_activitypub_deliveries_total", "type" => kind, "status" => status).increment(1);
+    histogram!("blog_activitypub_delivery_duration_ms")
+        .record(start_time.elapsed().as_millis() as f64);
+    delivered
+}
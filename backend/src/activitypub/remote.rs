@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use metrics::counter;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use url::{Host, Url};
+
+use super::RemoteActor;
+
+/// Upper bound of cached actors, signatures with random key ids must not grow the map forever
+const MAX_ENTRIES: usize = 10_000;
+/// Key ids that could not be fetched are not tried again for this long
+const FAILURE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Addresses other servers may live at, anything on our own machine or network is refused
+///
+/// Inbox requests make us fetch URLs chosen by the sender, they must not reach the admin
+/// listener or anything else that trusts local requests.
+pub fn is_public_address(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space of carrier-grade NAT
+        || (a == 100 && (64..128).contains(&b))
+        // Benchmarking
+        || (a == 198 && (18..20).contains(&b))
+        // Reserved for future use
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local
+        || (first & 0xfe00) == 0xfc00
+        // Link local
+        || (first & 0xffc0) == 0xfe80
+        // Documentation
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+/// Check a URL of another server before requesting it
+///
+/// Hosts given as names are checked once resolved, by [`PublicResolver`].
+pub fn check_remote_url(url: &Url) -> Result<(), &'static str> {
+    if !matches!(url.scheme(), "https" | "http") {
+        return Err("unsupported_scheme");
+    }
+    let public = match url.host() {
+        Some(Host::Domain(domain)) => !domain.eq_ignore_ascii_case("localhost"),
+        Some(Host::Ipv4(ip)) => is_public_address(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_public_address(IpAddr::V6(ip)),
+        None => false,
+    };
+    if public {
+        Ok(())
+    } else {
+        Err("private_address")
+    }
+}
+
+/// Resolver of the federation client, drops the addresses [`is_public_address`] refuses
+#[derive(Debug, Default)]
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_address(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                counter!("blog_activitypub_private_address_total").increment(1);
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Check that the actor fetched for a signature really owns the key that signed it
+///
+/// Anyone can publish a document claiming to be any actor, so the key must be the one
+/// the signature names, belong to the actor, and live on the same server as the actor.
+pub fn check_actor_key(actor: &RemoteActor, key_id: &str) -> Result<(), &'static str> {
+    if actor.public_key.id != key_id {
+        return Err("key_mismatch");
+    }
+    if actor.public_key.owner != actor.id {
+        return Err("key_owner_mismatch");
+    }
+    let key_url = Url::parse(key_id).map_err(|_| "invalid_key_id")?;
+    let actor_url = Url::parse(&actor.id).map_err(|_| "invalid_actor_id")?;
+    if key_url.origin() != actor_url.origin() {
+        return Err("actor_origin_mismatch");
+    }
+    Ok(())
+}
+
+/// Actors of the keys that signed inbox requests
+///
+/// Keeps a flood of signed requests from turning into a flood of fetches, failures are
+/// remembered too.
+pub struct ActorCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Option<RemoteActor>, Instant)>>,
+}
+
+impl ActorCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `Some(None)` when fetching the key failed recently
+    pub fn get(&self, key_id: &str) -> Option<Option<RemoteActor>> {
+        let entries = self.entries.lock().expect("actor cache poisoned");
+        let cached = entries
+            .get(key_id)
+            .filter(|(actor, stored_at)| stored_at.elapsed() < self.entry_ttl(actor.is_some()))
+            .map(|(actor, _)| actor.clone());
+
+        let result = if cached.is_some() { "hit" } else { "miss" };
+        counter!("blog_activitypub_actor_cache_total", "result" => result).increment(1);
+
+        cached
+    }
+
+    pub fn insert(&self, key_id: &str, actor: Option<RemoteActor>) {
+        let mut entries = self.entries.lock().expect("actor cache poisoned");
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(key_id) {
+            entries.retain(|_, (actor, stored_at)| {
+                stored_at.elapsed() < self.entry_ttl(actor.is_some())
+            });
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(key_id.to_string(), (actor, Instant::now()));
+    }
+
+    fn entry_ttl(&self, found: bool) -> Duration {
+        if found {
+            self.ttl
+        } else {
+            FAILURE_TTL.min(self.ttl)
+        }
+    }
+}
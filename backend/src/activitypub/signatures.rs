@@ -0,0 +1,270 @@
+use std::time::{Duration, SystemTime};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::DecodePublicKey,
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Requests signed longer ago (or further in the future) are rejected, like Mastodon does
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Headers covered by the signatures of the requests we send
+const SIGNED_HEADERS_GET: &[&str] = &["(request-target)", "host", "date"];
+const SIGNED_HEADERS_POST: &[&str] = &["(request-target)", "host", "date", "digest"];
+
+/// Parsed `Signature` header of the draft-cavage HTTP Signatures used by the fediverse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureHeader {
+    pub key_id: String,
+    /// Lowercased names of the signed headers, `date` when absent as the draft says
+    pub headers: Vec<String>,
+    /// Unix time the signature was made, the value of the `(created)` pseudo-header
+    pub created: Option<u64>,
+    pub signature: Vec<u8>,
+}
+
+impl SignatureHeader {
+    /// `keyId="...",algorithm="rsa-sha256",headers="(request-target) host date",signature="..."`
+    ///
+    /// `created` and `expires` are numbers and may come without quotes
+    pub fn parse(value: &str) -> Result<Self, &'static str> {
+        let mut key_id = None;
+        let mut headers = None;
+        let mut created = None;
+        let mut signature = None;
+
+        let mut rest = value.trim();
+        while !rest.is_empty() {
+            let (name, after_name) = rest.split_once('=').ok_or("malformed_signature")?;
+            let name = name.trim();
+            let (value, after_value) = match after_name.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').ok_or("malformed_signature")?,
+                None if matches!(name, "created" | "expires") => {
+                    after_name.split_once(',').unwrap_or((after_name, ""))
+                }
+                None => return Err("malformed_signature"),
+            };
+            match name {
+                "keyId" => key_id = Some(value.to_string()),
+                "headers" => headers = Some(value.to_lowercase()),
+                "created" => {
+                    created = Some(value.trim().parse().map_err(|_| "malformed_signature")?)
+                }
+                "signature" => {
+                    signature = Some(BASE64.decode(value).map_err(|_| "malformed_signature")?)
+                }
+                "algorithm" if !matches!(value, "rsa-sha256" | "hs2019") => {
+                    return Err("unsupported_algorithm")
+                }
+                _ => {}
+            }
+            rest = after_value.trim_start_matches([',', ' ']);
+        }
+
+        Ok(Self {
+            key_id: key_id.ok_or("malformed_signature")?,
+            headers: headers
+                .as_deref()
+                .unwrap_or("date")
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            created,
+            signature: signature.ok_or("malformed_signature")?,
+        })
+    }
+}
+
+/// Value of the `Digest` header of `body`
+pub fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+/// The string that is signed, one `name: value` line per signed header
+///
+/// `path_and_query` is the request target as the sender saw it, before any proxy
+/// rewrote it. `created` is the value of the `(created)` pseudo-header.
+pub fn signing_string(
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    names: &[impl AsRef<str>],
+    created: Option<u64>,
+) -> Result<String, &'static str> {
+    let lines = names
+        .iter()
+        .map(|name| {
+            let name = name.as_ref();
+            if name == "(request-target)" {
+                return Ok(format!(
+                    "(request-target): {} {path_and_query}",
+                    method.as_str().to_lowercase()
+                ));
+            }
+            if name == "(created)" {
+                return created
+                    .map(|created| format!("(created): {created}"))
+                    .ok_or("missing_signed_header");
+            }
+            let values: Vec<&str> = headers
+                .get_all(name)
+                .iter()
+                .map(|value| value.to_str().map(str::trim))
+                .collect::<Result<_, _>>()
+                .map_err(|_| "invalid_signed_header")?;
+            if values.is_empty() {
+                return Err("missing_signed_header");
+            }
+            Ok(format!("{name}: {}", values.join(", ")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.join("\n"))
+}
+
+/// Public key of an actor, either SPKI (`BEGIN PUBLIC KEY`) or PKCS#1 PEM
+pub fn parse_public_key(pem: &str) -> Result<RsaPublicKey, &'static str> {
+    RsaPublicKey::from_public_key_pem(pem.trim())
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem.trim()))
+        .map_err(|_| "invalid_public_key")
+}
+
+/// The checks of [`verify_request`] that need no key: the signed headers, the date and
+/// the digest. Run before the key of the signer is fetched.
+///
+/// The host must be signed, otherwise a request signed for another server could be
+/// replayed here.
+pub fn check_request(
+    signature: &SignatureHeader,
+    method: &Method,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: SystemTime,
+) -> Result<(), &'static str> {
+    let signed = |name: &str| signature.headers.iter().any(|header| header == name);
+    if !signed("(request-target)") || !signed("host") || !(signed("date") || signed("(created)")) {
+        return Err("insufficient_signed_headers");
+    }
+
+    let check_skew = |signed_at: SystemTime| {
+        let skew = now
+            .duration_since(signed_at)
+            .or_else(|_| signed_at.duration_since(now))
+            .unwrap_or_default();
+        if skew > MAX_CLOCK_SKEW {
+            return Err("expired_signature");
+        }
+        Ok(())
+    };
+    if signed("date") {
+        let date = headers
+            .get("date")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .ok_or("invalid_date")?;
+        check_skew(date)?;
+    }
+    if signed("(created)") {
+        let created = signature
+            .created
+            .and_then(|created| SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(created)))
+            .ok_or("invalid_created")?;
+        check_skew(created)?;
+    }
+
+    if method == Method::POST {
+        if !signed("digest") {
+            return Err("insufficient_signed_headers");
+        }
+        let expected = digest(body);
+        let matches = headers
+            .get("digest")
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|digest| digest.trim() == expected));
+        if !matches {
+            return Err("digest_mismatch");
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a signed request: the signature itself, that the date is recent and, for
+/// requests with a body, that the body is the one the digest was computed over
+pub fn verify_request(
+    public_key: &RsaPublicKey,
+    signature: &SignatureHeader,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: SystemTime,
+) -> Result<(), &'static str> {
+    check_request(signature, method, headers, body, now)?;
+
+    let message = signing_string(
+        method,
+        path_and_query,
+        headers,
+        &signature.headers,
+        signature.created,
+    )?;
+    let signature_bytes =
+        Signature::try_from(signature.signature.as_slice()).map_err(|_| "invalid_signature")?;
+    VerifyingKey::<Sha256>::new(public_key.clone())
+        .verify(message.as_bytes(), &signature_bytes)
+        .map_err(|_| "invalid_signature")
+}
+
+/// Add `Host`, `Date`, `Digest` (with a body) and the `Signature` to an outgoing request
+pub fn sign_request(
+    private_key: &RsaPrivateKey,
+    key_id: &str,
+    method: &Method,
+    url: &Url,
+    headers: &mut HeaderMap,
+    body: Option<&[u8]>,
+    now: SystemTime,
+) -> anyhow::Result<()> {
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    headers.insert("host", HeaderValue::from_str(&host)?);
+    headers.insert(
+        "date",
+        HeaderValue::from_str(&httpdate::fmt_http_date(now))?,
+    );
+
+    let names = match body {
+        Some(body) => {
+            headers.insert("digest", HeaderValue::from_str(&digest(body))?);
+            SIGNED_HEADERS_POST
+        }
+        None => SIGNED_HEADERS_GET,
+    };
+
+    let path_and_query = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let message = signing_string(method, &path_and_query, headers, names, None)
+        .map_err(|reason| anyhow::anyhow!("Cannot sign request: {reason}"))?;
+    let signature = SigningKey::<Sha256>::new(private_key.clone()).sign(message.as_bytes());
+
+    headers.insert(
+        HeaderName::from_static("signature"),
+        HeaderValue::from_str(&format!(
+            r#"keyId="{key_id}",algorithm="rsa-sha256",headers="{}",signature="{}""#,
+            names.join(" "),
+            BASE64.encode(signature.to_bytes())
+        ))?,
+    );
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use axum::{
+    routing::{delete, get, post},
+    Router,
+};
+
+use crate::{
+    analytics, jobs, likes, privacy, referrers, retention, state::AppState, syndication, views,
+    webhooks,
+};
+
+/// Routes of the admin API
+///
+/// It is served on a separate listener bound to localhost (`ADMIN_BIND_ADDRESS`) and
+/// never exposed through nginx, so being able to connect is the authorization.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/admin/likes/:user_ip_hash",
+            delete(privacy::admin_erase_likes),
+        )
+        .route(
+            "/admin/likes/actor",
+            get(privacy::admin_export_actor_likes).delete(privacy::admin_erase_actor_likes),
+        )
+        .route(
+            "/admin/likes/reconcile",
+            post(likes::counters::reconcile_now),
+        )
+        .route("/admin/retention/prune", post(retention::prune_now))
+        .route("/admin/views/:post_slug", get(views::view_stats))
+        .route("/admin/referrers/:post_slug", get(referrers::top_referrers))
+        .route("/admin/stats/aggregate", get(analytics::stats::aggregate))
+        .route("/admin/stats/breakdown", get(analytics::stats::breakdown))
+        .route("/admin/syndication", get(syndication::list_syndication))
+        .route(
+            "/admin/syndication/:post_slug",
+            get(syndication::post_syndication),
+        )
+        .route(
+            "/admin/syndication/:post_slug/:target/retry",
+            post(syndication::retry_syndication),
+        )
+        .route(
+            "/admin/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::create_webhook),
+        )
+        .route("/admin/webhooks/:id", delete(webhooks::delete_webhook))
+        .route("/admin/webhooks/deliveries", get(webhooks::list_deliveries))
+        .route("/admin/jobs", get(jobs::list_jobs))
+        .route("/admin/jobs/:kind/run", post(jobs::run_scheduled_job))
+}
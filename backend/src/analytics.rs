@@ -0,0 +1,240 @@
+use crate::{
+    correlation::CorrelationContext,
+    likes::{abuse::is_automated_user_agent, client_ip_hash},
+    views::VisitorHasher,
+};
+use axum::{
+    body::Bytes,
+    extract::{Extension, State},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::Utc;
+use metrics::{counter, histogram};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+use url::Url;
+
+pub mod stats;
+
+/// Longest event name or path that is stored, Plausible truncates similarly
+const MAX_FIELD_LEN: usize = 2000;
+
+/// Sites the event endpoint accepts events for
+#[derive(Debug, Clone, Default)]
+pub struct AnalyticsConfig {
+    /// Empty accepts every domain
+    pub domains: Vec<String>,
+}
+
+impl AnalyticsConfig {
+    /// Reads the comma separated `ANALYTICS_DOMAINS`
+    pub fn from_env() -> Self {
+        let domains = std::env::var("ANALYTICS_DOMAINS")
+            .map(|domains| {
+                domains
+                    .split(',')
+                    .map(|domain| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { domains }
+    }
+
+    /// First domain of the comma separated `domain` field of the event that is accepted
+    pub fn accepted_domain(&self, domains: &str) -> Option<String> {
+        domains
+            .split(',')
+            .map(|domain| domain.trim().to_lowercase())
+            .find(|domain| {
+                !domain.is_empty() && (self.domains.is_empty() || self.domains.contains(domain))
+            })
+    }
+}
+
+/// Body of `POST /api/event` as sent by the Plausible tracker script
+///
+/// Both the short keys of the script and the long ones of the Events API are accepted
+#[derive(Debug, Deserialize)]
+pub struct PlausibleEvent {
+    #[serde(alias = "n")]
+    pub name: String,
+    #[serde(alias = "u")]
+    pub url: String,
+    #[serde(alias = "d")]
+    pub domain: String,
+    #[serde(alias = "w", default)]
+    pub screen_width: Option<i32>,
+    /// Custom properties, the script sends them JSON encoded in `m`
+    #[serde(alias = "m", alias = "p", default)]
+    pub props: Option<EventProps>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EventProps {
+    Encoded(String),
+    Object(Map<String, Value>),
+}
+
+impl EventProps {
+    /// Properties as an object, malformed ones are dropped
+    fn into_object(self) -> Map<String, Value> {
+        match self {
+            EventProps::Object(props) => props,
+            EventProps::Encoded(encoded) => serde_json::from_str(&encoded).unwrap_or_default(),
+        }
+    }
+}
+
+/// Event ready to be stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyticsEvent {
+    pub domain: String,
+    pub name: String,
+    pub hostname: String,
+    pub pathname: String,
+    pub props: Map<String, Value>,
+    pub screen_width: Option<i32>,
+}
+
+impl AnalyticsEvent {
+    /// Validate the payload, `Err` carries the reason used as a metrics label
+    pub fn from_plausible(
+        event: PlausibleEvent,
+        config: &AnalyticsConfig,
+    ) -> Result<Self, &'static str> {
+        let domain = config
+            .accepted_domain(&event.domain)
+            .ok_or("unknown_domain")?;
+        let name = event.name.trim();
+        if name.is_empty() || name.len() > MAX_FIELD_LEN {
+            return Err("invalid_name");
+        }
+        let url = Url::parse(&event.url).map_err(|_| "invalid_url")?;
+        let hostname = url.host_str().ok_or("invalid_url")?.to_lowercase();
+        let pathname = url.path();
+        if pathname.len() > MAX_FIELD_LEN {
+            return Err("invalid_url");
+        }
+
+        // Only scalar properties, like Plausible
+        let props = event
+            .props
+            .map(EventProps::into_object)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(_, value)| !value.is_object() && !value.is_array() && !value.is_null())
+            .collect();
+
+        Ok(Self {
+            domain,
+            name: name.to_string(),
+            hostname,
+            pathname: pathname.to_string(),
+            props,
+            screen_width: event.screen_width,
+        })
+    }
+}
+
+/// Plausible compatible event ingestion
+///
+/// Answers `202 ok` like Plausible does, also for events from bots that are dropped.
+/// The tracker sends the JSON as `text/plain`, so the content type is not checked.
+#[instrument(skip(pool, config, visitors, correlation_ctx, headers, body))]
+pub async fn ingest_event(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<AnalyticsConfig>>,
+    State(visitors): State<Arc<VisitorHasher>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, &'static str) {
+    let start_time = std::time::Instant::now();
+    counter!("blog_analytics_requests_total").increment(1);
+
+    let user_agent = headers.get("user-agent").and_then(|h| h.to_str().ok());
+    if is_automated_user_agent(user_agent) {
+        counter!("blog_analytics_dropped_total", "reason" => "bot_user_agent").increment(1);
+        return (StatusCode::ACCEPTED, "ok");
+    }
+
+    let event = serde_json::from_slice::<PlausibleEvent>(&body)
+        .map_err(|_| "invalid_payload")
+        .and_then(|event| AnalyticsEvent::from_plausible(event, &config));
+    let event = match event {
+        Ok(event) => event,
+        Err(reason) => {
+            counter!("blog_analytics_dropped_total", "reason" => reason).increment(1);
+            return (StatusCode::BAD_REQUEST, "invalid event");
+        }
+    };
+
+    let cf_country = correlation_ctx.cf_header(&headers, "cf-ipcountry");
+
+    let user_ip_hash = client_ip_hash(&correlation_ctx);
+    let visitor_id = visitors.daily_id(Utc::now().date_naive(), &user_ip_hash);
+    let result = insert_event(&pool, &event, &user_ip_hash, visitor_id, cf_country).await;
+    if let Err(e) = result {
+        warn!(
+            error = %e,
+            correlation_id = %correlation_ctx.correlation_id,
+            "Database error storing analytics event"
+        );
+        counter!("blog_analytics_errors_total").increment(1);
+        return (StatusCode::INTERNAL_SERVER_ERROR, "error");
+    }
+
+    counter!("blog_analytics_events_total", "kind" => event_kind(&event.name)).increment(1);
+    histogram!("blog_analytics_request_duration_ms")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    (StatusCode::ACCEPTED, "ok")
+}
+
+#[instrument(skip(pool, user_ip_hash, visitor_id))]
+pub async fn insert_event(
+    pool: &PgPool,
+    event: &AnalyticsEvent,
+    user_ip_hash: &str,
+    visitor_id: i64,
+    cf_country: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO analytics_events (domain, name, hostname, pathname, props, user_ip_hash, visitor_id, cf_country, screen_width)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+        event.domain,
+        event.name,
+        event.hostname,
+        event.pathname,
+        Value::Object(event.props.clone()),
+        user_ip_hash,
+        visitor_id,
+        cf_country,
+        event.screen_width
+    )
+    .execute(pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "insert_analytics_event")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(())
+}
+
+/// Custom event names are unbounded, keep them out of the metrics labels
+fn event_kind(name: &str) -> &'static str {
+    if name == "pageview" {
+        "pageview"
+    } else {
+        "custom"
+    }
+}
@@ -0,0 +1,301 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
+use metrics::histogram;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+use tracing::{instrument, warn};
+
+/// Metrics the stats API can compute
+const METRICS: &[&str] = &["visitors", "pageviews", "events"];
+
+/// Query parameters shared by the aggregate and breakdown endpoints, named after the
+/// Plausible Stats API
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    pub site_id: String,
+    /// `day`, `month`, `<n>d`, `<n>mo` or `custom` together with `date`
+    pub period: Option<String>,
+    /// `YYYY-MM-DD,YYYY-MM-DD` for the custom period
+    pub date: Option<String>,
+    /// Comma separated, defaults to `visitors`
+    pub metrics: Option<String>,
+    /// `event:page==<path>` and `event:name==<name>` joined by `;`
+    pub filters: Option<String>,
+    /// Breakdown only - `event:page`, `event:name` or `visit:country`
+    pub property: Option<String>,
+    /// Breakdown only
+    pub limit: Option<i64>,
+}
+
+/// Error answered in the Plausible format `{"error": "..."}`
+#[derive(Debug)]
+pub struct StatsError(StatusCode, String);
+
+impl StatsError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self(StatusCode::BAD_REQUEST, message.into())
+    }
+}
+
+impl From<sqlx::Error> for StatsError {
+    fn from(e: sqlx::Error) -> Self {
+        warn!(error = %e, "Database error querying analytics stats");
+        Self(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Internal error".to_string(),
+        )
+    }
+}
+
+impl IntoResponse for StatsError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Half-open time range `[start, end)` the stats are computed over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Period {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Period {
+    /// Parse a Plausible period relative to `today`, periods end with today included
+    pub fn parse(period: &str, date: Option<&str>, today: NaiveDate) -> Result<Self, String> {
+        let tomorrow = today + Duration::days(1);
+        let (first_day, end_day) = match period {
+            "day" => (today, tomorrow),
+            "month" => (
+                today.with_day(1).expect("every month has a first day"),
+                tomorrow,
+            ),
+            "custom" => {
+                let (from, to) = date
+                    .and_then(|date| date.split_once(','))
+                    .ok_or("The custom period needs date=YYYY-MM-DD,YYYY-MM-DD")?;
+                let parse = |day: &str| {
+                    NaiveDate::parse_from_str(day.trim(), "%Y-%m-%d")
+                        .map_err(|_| format!("Invalid date: {day}"))
+                };
+                let (from, to) = (parse(from)?, parse(to)?);
+                if from > to {
+                    return Err("The custom period ends before it starts".to_string());
+                }
+                (from, to + Duration::days(1))
+            }
+            _ => {
+                let invalid = || format!("Invalid period: {period}");
+                if let Some(months) = period.strip_suffix("mo") {
+                    let months: u32 = months.parse().map_err(|_| invalid())?;
+                    let first_day = today
+                        .checked_sub_months(Months::new(months))
+                        .ok_or_else(invalid)?;
+                    (first_day + Duration::days(1), tomorrow)
+                } else if let Some(days) = period.strip_suffix('d') {
+                    let days: i64 = days.parse().map_err(|_| invalid())?;
+                    if !(1..=3660).contains(&days) {
+                        return Err(invalid());
+                    }
+                    (tomorrow - Duration::days(days), tomorrow)
+                } else {
+                    return Err(invalid());
+                }
+            }
+        };
+
+        Ok(Self {
+            start: first_day.and_hms_opt(0, 0, 0).expect("midnight").and_utc(),
+            end: end_day.and_hms_opt(0, 0, 0).expect("midnight").and_utc(),
+        })
+    }
+}
+
+/// Supported subset of the Plausible filters
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Filters {
+    pub page: Option<String>,
+    pub name: Option<String>,
+}
+
+impl Filters {
+    pub fn parse(filters: Option<&str>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for filter in filters.unwrap_or_default().split(';') {
+            let filter = filter.trim();
+            if filter.is_empty() {
+                continue;
+            }
+            match filter.split_once("==") {
+                Some(("event:page", page)) => parsed.page = Some(page.to_string()),
+                Some(("event:name", name)) => parsed.name = Some(name.to_string()),
+                _ => return Err(format!("Unsupported filter: {filter}")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_metrics(metrics: Option<&str>) -> Result<Vec<&str>, String> {
+    let metrics: Vec<&str> = metrics
+        .unwrap_or("visitors")
+        .split(',')
+        .map(str::trim)
+        .filter(|metric| !metric.is_empty())
+        .collect();
+    match metrics.iter().find(|metric| !METRICS.contains(metric)) {
+        Some(metric) => Err(format!("Unsupported metric: {metric}")),
+        None => Ok(metrics),
+    }
+}
+
+/// What a breakdown groups by, also the key the value is reported under
+fn breakdown_property(property: Option<&str>) -> Result<&'static str, String> {
+    match property {
+        Some("event:page") => Ok("page"),
+        Some("event:name") => Ok("name"),
+        Some("visit:country") => Ok("country"),
+        Some(property) => Err(format!("Unsupported property: {property}")),
+        None => Err("The property parameter is required".to_string()),
+    }
+}
+
+fn parse_common(query: &StatsQuery) -> Result<(Period, Filters, Vec<&str>), StatsError> {
+    let period = Period::parse(
+        query.period.as_deref().unwrap_or("30d"),
+        query.date.as_deref(),
+        Utc::now().date_naive(),
+    )
+    .map_err(StatsError::bad_request)?;
+    let filters = Filters::parse(query.filters.as_deref()).map_err(StatsError::bad_request)?;
+    let metrics = parse_metrics(query.metrics.as_deref()).map_err(StatsError::bad_request)?;
+    Ok((period, filters, metrics))
+}
+
+fn select_metrics(
+    metrics: &[&str],
+    visitors: i64,
+    pageviews: i64,
+    events: i64,
+) -> Map<String, Value> {
+    metrics
+        .iter()
+        .map(|&metric| {
+            let value = match metric {
+                "visitors" => visitors,
+                "pageviews" => pageviews,
+                _ => events,
+            };
+            (metric.to_string(), json!(value))
+        })
+        .collect()
+}
+
+/// Totals of the requested metrics over the period, like Plausible's
+/// `/api/v1/stats/aggregate`
+#[instrument(skip(pool))]
+pub async fn aggregate(
+    Query(query): Query<StatsQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Value>, StatsError> {
+    let (period, filters, metrics) = parse_common(&query)?;
+    let start_time = std::time::Instant::now();
+
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(DISTINCT visitor_id) AS "visitors!",
+            COUNT(*) FILTER (WHERE name = 'pageview') AS "pageviews!",
+            COUNT(*) AS "events!"
+        FROM analytics_events
+        WHERE domain = $1 AND occurred_at >= $2 AND occurred_at < $3
+            AND ($4::varchar IS NULL OR pathname = $4)
+            AND ($5::varchar IS NULL OR name = $5)
+        "#,
+        query.site_id.to_lowercase(),
+        period.start,
+        period.end,
+        filters.page,
+        filters.name
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "analytics_aggregate")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    let results: Map<String, Value> =
+        select_metrics(&metrics, totals.visitors, totals.pageviews, totals.events)
+            .into_iter()
+            .map(|(metric, value)| (metric, json!({ "value": value })))
+            .collect();
+
+    Ok(Json(json!({ "results": results })))
+}
+
+/// Metrics per page, event name or country, like Plausible's `/api/v1/stats/breakdown`
+#[instrument(skip(pool))]
+pub async fn breakdown(
+    Query(query): Query<StatsQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Value>, StatsError> {
+    let (period, filters, metrics) = parse_common(&query)?;
+    let key = breakdown_property(query.property.as_deref()).map_err(StatsError::bad_request)?;
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let start_time = std::time::Instant::now();
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            CASE $6
+                WHEN 'page' THEN pathname
+                WHEN 'name' THEN name
+                ELSE COALESCE(cf_country, '')
+            END AS "value!",
+            COUNT(DISTINCT visitor_id) AS "visitors!",
+            COUNT(*) FILTER (WHERE name = 'pageview') AS "pageviews!",
+            COUNT(*) AS "events!"
+        FROM analytics_events
+        WHERE domain = $1 AND occurred_at >= $2 AND occurred_at < $3
+            AND ($4::varchar IS NULL OR pathname = $4)
+            AND ($5::varchar IS NULL OR name = $5)
+        GROUP BY 1
+        ORDER BY 2 DESC, 4 DESC, 1
+        LIMIT $7
+        "#,
+        query.site_id.to_lowercase(),
+        period.start,
+        period.end,
+        filters.page,
+        filters.name,
+        key,
+        limit
+    )
+    .fetch_all(&pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "analytics_breakdown")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    let results: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            let mut entry = Map::new();
+            entry.insert(key.to_string(), json!(row.value));
+            entry.extend(select_metrics(
+                &metrics,
+                row.visitors,
+                row.pageviews,
+                row.events,
+            ));
+            Value::Object(entry)
+        })
+        .collect();
+
+    Ok(Json(json!({ "results": results })))
+}
@@ -0,0 +1,264 @@
+use anyhow::{bail, Context};
+use chrono::Utc;
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use crate::hugo_posts::HugoBlogPost;
+
+pub mod syndication;
+
+/// Longest text of a post, Bluesky counts graphemes - chars are close enough for us
+pub const MAX_POST_CHARS: usize = 300;
+/// Largest thumbnail the link card accepts
+pub const MAX_THUMB_BYTES: usize = 1_000_000;
+
+/// Account the posts are cross-posted from
+#[derive(Debug, Clone)]
+pub struct BlueskyConfig {
+    /// Handle or DID of the account
+    pub identifier: String,
+    /// App password, not the password of the account
+    pub app_password: String,
+    /// PDS hosting the account, `https://bsky.social` for most accounts
+    pub pds_url: Url,
+}
+
+impl BlueskyConfig {
+    /// Reads `BLUESKY_IDENTIFIER`, `BLUESKY_APP_PASSWORD` and `BLUESKY_PDS_URL`,
+    /// `None` disables the syndication
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let (Ok(identifier), Ok(app_password)) = (
+            std::env::var("BLUESKY_IDENTIFIER"),
+            std::env::var("BLUESKY_APP_PASSWORD"),
+        ) else {
+            info!(
+                "BLUESKY_IDENTIFIER or BLUESKY_APP_PASSWORD not set - Bluesky syndication disabled"
+            );
+            return Ok(None);
+        };
+        let pds_url = std::env::var("BLUESKY_PDS_URL")
+            .unwrap_or_else(|_| "https://bsky.social".to_string())
+            .parse()
+            .context("Invalid BLUESKY_PDS_URL")?;
+
+        Ok(Some(Self {
+            identifier,
+            app_password,
+            pds_url,
+        }))
+    }
+}
+
+/// Logged in session, the records are created in the repository of `did`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Session {
+    #[serde(rename = "accessJwt")]
+    pub access_jwt: String,
+    pub did: String,
+}
+
+/// Reference to a created record
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrongRef {
+    /// `at://<did>/app.bsky.feed.post/<rkey>`
+    pub uri: String,
+    pub cid: String,
+}
+
+/// Error body of the XRPC endpoints
+#[derive(Debug, Deserialize)]
+struct XrpcError {
+    error: Option<String>,
+    message: Option<String>,
+}
+
+/// Client of the XRPC API of the PDS
+pub struct BlueskyClient {
+    config: BlueskyConfig,
+    http: reqwest::Client,
+}
+
+impl BlueskyClient {
+    pub fn new(config: BlueskyConfig) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent("blog-backend")
+            .timeout(std::time::Duration::from_secs(30))
+            .build()?;
+        Ok(Self { config, http })
+    }
+
+    fn xrpc(&self, method: &str) -> anyhow::Result<Url> {
+        Ok(self.config.pds_url.join(&format!("/xrpc/{method}"))?)
+    }
+
+    /// `com.atproto.server.createSession` with the app password
+    #[instrument(skip(self))]
+    pub async fn login(&self) -> anyhow::Result<Session> {
+        let response = self
+            .http
+            .post(self.xrpc("com.atproto.server.createSession")?)
+            .json(&json!({
+                "identifier": self.config.identifier,
+                "password": self.config.app_password
+            }))
+            .send()
+            .await?;
+        Ok(xrpc_response(response).await?.json().await?)
+    }
+
+    /// `com.atproto.repo.uploadBlob`, returns the blob reference to embed in a record
+    #[instrument(skip(self, session, data))]
+    pub async fn upload_blob(
+        &self,
+        session: &Session,
+        data: Vec<u8>,
+        mime_type: &str,
+    ) -> anyhow::Result<Value> {
+        let response = self
+            .http
+            .post(self.xrpc("com.atproto.repo.uploadBlob")?)
+            .bearer_auth(&session.access_jwt)
+            .header(CONTENT_TYPE, mime_type)
+            .body(data)
+            .send()
+            .await?;
+        let body: Value = xrpc_response(response).await?.json().await?;
+        body.get("blob")
+            .cloned()
+            .context("uploadBlob response without a blob")
+    }
+
+    /// `com.atproto.repo.createRecord` of an `app.bsky.feed.post`
+    #[instrument(skip(self, session, record))]
+    pub async fn create_record(
+        &self,
+        session: &Session,
+        record: &Value,
+    ) -> anyhow::Result<StrongRef> {
+        let response = self
+            .http
+            .post(self.xrpc("com.atproto.repo.createRecord")?)
+            .bearer_auth(&session.access_jwt)
+            .json(&json!({
+                "repo": session.did,
+                "collection": "app.bsky.feed.post",
+                "record": record
+            }))
+            .send()
+            .await?;
+        Ok(xrpc_response(response).await?.json().await?)
+    }
+
+    /// Cross-post a blog post with a link card, the featured image becomes the
+    /// thumbnail of the card when it can be fetched
+    #[instrument(skip(self, session, post), fields(slug = %post.slug))]
+    pub async fn create_post(
+        &self,
+        session: &Session,
+        post: &HugoBlogPost,
+    ) -> anyhow::Result<StrongRef> {
+        let thumb = match self.featured_image(post).await {
+            Ok(Some((data, mime_type))) => Some(self.upload_blob(session, data, &mime_type).await?),
+            Ok(None) => None,
+            Err(e) => {
+                warn!(error = %e, slug = %post.slug, "Featured image not usable as thumbnail");
+                None
+            }
+        };
+        self.create_record(session, &post_record(post, thumb)).await
+    }
+
+    /// Download the featured image, relative paths are resolved against the post URL
+    async fn featured_image(
+        &self,
+        post: &HugoBlogPost,
+    ) -> anyhow::Result<Option<(Vec<u8>, String)>> {
+        let Some(image) = post.featured_image.as_deref().filter(|i| !i.is_empty()) else {
+            return Ok(None);
+        };
+        let url = post.url.join(image)?;
+        let response = self.http.get(url).send().await?.error_for_status()?;
+        let mime_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if !mime_type.starts_with("image/") {
+            bail!("not an image: {mime_type}");
+        }
+        let data = response.bytes().await?;
+        if data.len() > MAX_THUMB_BYTES {
+            bail!("{} bytes, more than the thumbnail limit", data.len());
+        }
+        Ok(Some((data.to_vec(), mime_type)))
+    }
+}
+
+/// Turn XRPC errors (`{"error": "...", "message": "..."}`) into errors
+async fn xrpc_response(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let error: Option<XrpcError> = response.json().await.ok();
+    let (error, message) = error.map(|e| (e.error, e.message)).unwrap_or_default();
+    bail!(
+        "XRPC request failed with {status}: {} {}",
+        error.unwrap_or_default(),
+        message.unwrap_or_default()
+    )
+}
+
+/// `app.bsky.feed.post` record announcing `post`
+///
+/// The text is the title followed by the tags as hashtags, as many as fit. The link
+/// card carries the URL, title, description and the uploaded thumbnail.
+pub fn post_record(post: &HugoBlogPost, thumb: Option<Value>) -> Value {
+    let mut text: String = post.title.chars().take(MAX_POST_CHARS).collect();
+    let mut facets = Vec::new();
+
+    let tags = post
+        .tags
+        .iter()
+        .flatten()
+        .map(|tag| tag.trim().replace(' ', ""));
+    for (i, tag) in tags.filter(|tag| !tag.is_empty()).enumerate() {
+        let separator = if i == 0 { "\n\n" } else { " " };
+        let hashtag = format!("#{tag}");
+        if text.chars().count() + separator.len() + hashtag.chars().count() > MAX_POST_CHARS {
+            break;
+        }
+        text.push_str(separator);
+        // Facets index the UTF-8 bytes of the text
+        let byte_start = text.len();
+        text.push_str(&hashtag);
+        facets.push(json!({
+            "index": { "byteStart": byte_start, "byteEnd": text.len() },
+            "features": [{ "$type": "app.bsky.richtext.facet#tag", "tag": tag }]
+        }));
+    }
+
+    let mut external = json!({
+        "uri": post.url.as_str(),
+        "title": post.title,
+        "description": post.description
+    });
+    if let Some(thumb) = thumb {
+        external["thumb"] = thumb;
+    }
+
+    let mut record = json!({
+        "$type": "app.bsky.feed.post",
+        "text": text,
+        "createdAt": Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "embed": { "$type": "app.bsky.embed.external", "external": external }
+    });
+    if !facets.is_empty() {
+        record["facets"] = json!(facets);
+    }
+    record
+}
@@ -0,0 +1,52 @@
+use sqlx::PgPool;
+use tracing::{info, instrument};
+
+use super::{BlueskyClient, StrongRef};
+use crate::hugo_posts::HugoBlogPost;
+
+/// Cross-post a blog post, returns the record of the post on Bluesky
+///
+/// Posts that already have a record in `bluesky_posts` are not posted again.
+#[instrument(skip(pool, client))]
+pub async fn syndicate_post(
+    pool: &PgPool,
+    client: &BlueskyClient,
+    post_slug: &str,
+) -> anyhow::Result<StrongRef> {
+    let existing = sqlx::query_as!(
+        StrongRef,
+        "SELECT uri, cid FROM bluesky_posts WHERE post_slug = $1",
+        post_slug
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(record) = existing {
+        return Ok(record);
+    }
+
+    let post = sqlx::query_as::<_, HugoBlogPost>(
+        r#"
+        SELECT title, slug, description, date, featured_image, tags, url, content,
+            lastmod, draft, reading_time, word_count, series, summary
+        FROM blog_posts WHERE slug = $1
+        "#,
+    )
+    .bind(post_slug)
+    .fetch_one(pool)
+    .await?;
+
+    let session = client.login().await?;
+    let record = client.create_post(&session, &post).await?;
+
+    sqlx::query!(
+        "INSERT INTO bluesky_posts (post_slug, uri, cid) VALUES ($1, $2, $3)",
+        post_slug,
+        record.uri,
+        record.cid
+    )
+    .execute(pool)
+    .await?;
+
+    info!(post_slug, uri = %record.uri, "Post syndicated to Bluesky");
+    Ok(record)
+}
@@ -1,9 +1,15 @@
+use crate::trusted_proxies::TrustedProxies;
 use axum::{
+    extract::{ConnectInfo, State},
     http::{HeaderMap, HeaderName, HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
-use std::str::FromStr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
 use tracing::Span;
 use uuid::Uuid;
 
@@ -21,13 +27,23 @@ pub struct CorrelationContext {
     pub session_id: Option<String>,
     pub user_id: Option<String>,
     pub user_agent: Option<String>,
+    /// Client address resolved through the trusted proxies
     pub remote_ip: Option<String>,
     pub forwarded_for: Option<String>,
+    /// Whether the client connected to a Cloudflare edge, ie. the `cf-*` headers are genuine
+    pub via_cloudflare: bool,
 }
 
 impl CorrelationContext {
     /// Extract correlation context from HTTP headers
-    pub fn from_headers(headers: &HeaderMap) -> Self {
+    ///
+    /// `peer_ip` is the address of the TCP peer, client IP headers are only taken
+    /// into account when it belongs to one of the `trusted_proxies`
+    pub fn from_headers(
+        headers: &HeaderMap,
+        peer_ip: Option<IpAddr>,
+        trusted_proxies: &TrustedProxies,
+    ) -> Self {
         let correlation_id = extract_header_or_generate(headers, CORRELATION_ID_HEADER);
         let request_id = extract_header_or_generate(headers, REQUEST_ID_HEADER);
 
@@ -35,10 +51,10 @@ impl CorrelationContext {
         let user_id = extract_optional_header(headers, USER_ID_HEADER);
         let user_agent = extract_optional_header(headers, "user-agent");
 
-        // Extract IP information (prioritize Cloudflare headers)
-        let remote_ip = extract_optional_header(headers, "cf-connecting-ip")
-            .or_else(|| extract_optional_header(headers, "x-real-ip"))
-            .or_else(|| extract_optional_header(headers, "x-client-ip"));
+        let via_cloudflare = peer_ip.is_some_and(|ip| trusted_proxies.via_cloudflare(ip, headers));
+        let remote_ip = peer_ip
+            .map(|ip| trusted_proxies.resolve_client_ip(ip, headers))
+            .map(|ip| ip.to_string());
 
         let forwarded_for = extract_optional_header(headers, "x-forwarded-for");
 
@@ -50,9 +66,20 @@ impl CorrelationContext {
             user_agent,
             remote_ip,
             forwarded_for,
+            via_cloudflare,
         }
     }
 
+    /// Value of a `cf-*` header, ignored unless the request came through Cloudflare
+    ///
+    /// Anyone talking to nginx or the backend directly can send these headers.
+    pub fn cf_header<'a>(&self, headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+        headers
+            .get(name)
+            .filter(|_| self.via_cloudflare)
+            .and_then(|h| h.to_str().ok())
+    }
+
     /// Add correlation context to the current tracing span
     pub fn add_to_span(&self, span: &Span) {
         span.record("correlation_id", &self.correlation_id);
@@ -96,11 +123,20 @@ impl CorrelationContext {
 }
 
 /// Middleware to extract and inject correlation context
+///
+/// The peer address comes from [`ConnectInfo`], so the app has to be served with
+/// `into_make_service_with_connect_info::<SocketAddr>()`
 pub async fn correlation_middleware(
+    State(trusted_proxies): State<Arc<TrustedProxies>>,
     mut request: Request<axum::body::Body>,
     next: Next,
 ) -> Response {
-    let correlation_ctx = CorrelationContext::from_headers(request.headers());
+    let peer_ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let correlation_ctx =
+        CorrelationContext::from_headers(request.headers(), peer_ip, &trusted_proxies);
 
     // Store correlation context in request extensions for handlers to access
     request.extensions_mut().insert(correlation_ctx.clone());
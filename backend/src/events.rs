@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgExecutor, PgPool};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Postgres channel the events are published on
+pub const EVENTS_CHANNEL: &str = "backend_events";
+
+/// Something that happened in the backend that other modules may react to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendEvent {
+    /// A post from the static site generator was stored
+    PostIngested { slug: String },
+    /// A reaction was recorded, the counts are the ones after it
+    LikeRecorded {
+        post_slug: String,
+        reaction: String,
+        total_likes: i64,
+        reaction_count: i64,
+    },
+    /// A fediverse favourite or boost was undone or likes were erased, the counts are the
+    /// ones after it
+    LikeRemoved {
+        post_slug: String,
+        reaction: String,
+        total_likes: i64,
+        reaction_count: i64,
+    },
+    /// The stored counts of the posts drifted from their likes and were recomputed
+    LikeCountsReconciled { post_slugs: Vec<String> },
+}
+
+impl BackendEvent {
+    /// Short name used as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BackendEvent::PostIngested { .. } => "post_ingested",
+            BackendEvent::LikeRecorded { .. } => "like_recorded",
+            BackendEvent::LikeRemoved { .. } => "like_removed",
+            BackendEvent::LikeCountsReconciled { .. } => "like_counts_reconciled",
+        }
+    }
+}
+
+/// Module reacting to backend events, see [`EventBus::register`]
+pub trait EventSubscriber: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+    fn handle(&self, event: &BackendEvent);
+}
+
+/// Typed event bus shared by all backend processes
+///
+/// Events are published with `NOTIFY` and every process delivers what it receives on
+/// its `LISTEN` connection to the local subscribers - including its own events, so
+/// there is a single delivery path. Publishing with a transaction as the executor
+/// delivers the event only when the transaction commits.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<BackendEvent>,
+}
+
+/// Bus not connected to Postgres, delivers only what is passed to [`EventBus::deliver`]
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    /// Subscribe to Postgres and start delivering events
+    ///
+    /// Returns once `LISTEN` is in place so nothing published afterwards is missed
+    pub async fn start(pool: PgPool) -> Result<Self, sqlx::Error> {
+        let bus = Self::default();
+
+        let mut listener = listen(&pool).await?;
+        let events = bus.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = events.forward(&mut listener).await {
+                    warn!(error = %e, "Event bus listener failed, reconnecting");
+                    counter!("blog_events_listener_errors_total").increment(1);
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                match listen(&pool).await {
+                    Ok(new_listener) => listener = new_listener,
+                    Err(e) => warn!(error = %e, "Event bus reconnect failed"),
+                }
+            }
+        });
+
+        Ok(bus)
+    }
+
+    /// Publish the event to all processes
+    pub async fn publish<'e>(
+        executor: impl PgExecutor<'e>,
+        event: &BackendEvent,
+    ) -> Result<(), sqlx::Error> {
+        let payload = serde_json::to_string(event).expect("backend event serializes");
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(EVENTS_CHANNEL)
+            .bind(payload)
+            .execute(executor)
+            .await?;
+        counter!("blog_events_published_total", "event" => event.kind()).increment(1);
+        Ok(())
+    }
+
+    /// Receive the events delivered to this process
+    pub fn subscribe(&self) -> broadcast::Receiver<BackendEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Call `subscriber` for every event delivered to this process
+    pub fn register(&self, subscriber: impl EventSubscriber) {
+        let mut receiver = self.subscribe();
+        info!(
+            subscriber = subscriber.name(),
+            "Event subscriber registered"
+        );
+
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => subscriber.handle(&event),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(
+                            subscriber = subscriber.name(),
+                            missed, "Event subscriber lagged"
+                        );
+                        counter!("blog_events_lagged_total", "subscriber" => subscriber.name())
+                            .increment(missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Hand the event to the local subscribers, no-op when there are none
+    pub fn deliver(&self, event: BackendEvent) {
+        counter!("blog_events_delivered_total", "event" => event.kind()).increment(1);
+        let _ = self.sender.send(event);
+    }
+
+    async fn forward(&self, listener: &mut PgListener) -> Result<(), sqlx::Error> {
+        loop {
+            let notification = listener.recv().await?;
+            match serde_json::from_str::<BackendEvent>(notification.payload()) {
+                Ok(event) => {
+                    debug!(event = ?event, "Backend event received");
+                    self.deliver(event);
+                }
+                Err(e) => warn!(error = %e, "Invalid backend event"),
+            }
+        }
+    }
+}
+
+async fn listen(pool: &PgPool) -> Result<PgListener, sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(EVENTS_CHANNEL).await?;
+    info!(channel = EVENTS_CHANNEL, "Listening for backend events");
+    Ok(listener)
+}
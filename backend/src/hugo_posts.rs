@@ -7,6 +7,11 @@ use sqlx::{postgres::PgRow, Error, FromRow, Row};
 use tracing::instrument;
 use url::Url;
 
+use crate::events::{BackendEvent, EventBus};
+use validation::PostValidation;
+
+pub mod validation;
+
 /// Represents a blog post from the static site generator
 /// This is the format of the json file that is generated by the static site generator
 /// and contains the blog posts that might require publishing
@@ -30,6 +35,20 @@ pub struct HugoBlogPost {
     pub tags: Option<Vec<String>>,
     /// The URL of the post itself
     pub url: Url,
+    /// Plain text of the post, makes the body searchable. Older exports do not have it
+    pub content: Option<String>,
+    /// When the post was last changed, older exports do not have the fields below
+    pub lastmod: Option<DateTime<Utc>>,
+    /// Drafts are only exported when Hugo builds them
+    #[serde(default)]
+    pub draft: bool,
+    /// Estimated minutes of reading
+    pub reading_time: Option<i32>,
+    pub word_count: Option<i32>,
+    /// Series the post belongs to
+    pub series: Option<Vec<String>>,
+    /// Summary of the post, the description or the first paragraphs
+    pub summary: Option<String>,
 }
 
 impl FromRow<'_, PgRow> for HugoBlogPost {
@@ -41,12 +60,20 @@ impl FromRow<'_, PgRow> for HugoBlogPost {
         let featured_image: Option<String> = row.try_get("featured_image")?;
         let tags_str: Option<String> = row.try_get("tags")?;
         let url_str: String = row.try_get("url")?;
+        let content: Option<String> = row.try_get("content")?;
+        let lastmod: Option<DateTime<Utc>> = row.try_get("lastmod")?;
+        let draft: bool = row.try_get("draft")?;
+        let reading_time: Option<i32> = row.try_get("reading_time")?;
+        let word_count: Option<i32> = row.try_get("word_count")?;
+        let series_str: Option<String> = row.try_get("series")?;
+        let summary: Option<String> = row.try_get("summary")?;
 
         // Parse the URL
         let url = Url::parse(&url_str).map_err(|e| Error::Decode(Box::new(e)))?;
 
         // Parse the tags if they exist
         let tags = tags_str.map(|s| s.split(',').map(String::from).collect());
+        let series = series_str.map(|s| s.split(',').map(String::from).collect());
 
         Ok(HugoBlogPost {
             title,
@@ -56,6 +83,13 @@ impl FromRow<'_, PgRow> for HugoBlogPost {
             featured_image,
             tags,
             url,
+            content,
+            lastmod,
+            draft,
+            reading_time,
+            word_count,
+            series,
+            summary,
         })
     }
 }
@@ -63,14 +97,23 @@ impl FromRow<'_, PgRow> for HugoBlogPost {
 type BlogPosts = Vec<HugoBlogPost>;
 
 impl HugoBlogPost {
-    /// Read and deserialize the blog posts from the filesystem file
-    #[instrument(skip(path))]
-    pub fn load_new_posts(path: impl AsRef<Path>) -> Result<BlogPosts, crate::error::Error> {
+    /// Read the blog posts from the filesystem file and validate each of them
+    ///
+    /// In strict mode any invalid post fails the load with a report of all the problems,
+    /// in lenient mode the invalid posts are skipped.
+    #[instrument(skip(path, validation))]
+    pub fn load_new_posts(
+        path: impl AsRef<Path>,
+        validation: &PostValidation,
+    ) -> Result<BlogPosts, crate::error::Error> {
         let start_time = std::time::Instant::now();
 
         let file = File::open(path)?;
         let reader = BufReader::new(file);
-        let blog_posts: BlogPosts = serde_json::from_reader(reader)?;
+        let entries: Vec<serde_json::Value> = serde_json::from_reader(reader)?;
+        let blog_posts = validation
+            .validate(entries, Utc::now())
+            .into_posts(validation.mode)?;
 
         counter!("blog_posts_loaded_total").increment(blog_posts.len() as u64);
         histogram!("blog_posts_load_duration_ms").record(start_time.elapsed().as_millis() as f64);
@@ -91,18 +134,53 @@ impl BlogRepository {
         let start_time = std::time::Instant::now();
 
         let tags_str = blog_post.tags.clone().map(|tags| tags.join(","));
-        sqlx::query!(
-            "INSERT INTO blog_posts (title, slug, description, date, featured_image, tags, url) VALUES ($1, $2, $3, $4, $5, $6, $7) ON CONFLICT(slug) DO UPDATE SET title = excluded.title, description = excluded.description, date = excluded.date, featured_image = excluded.featured_image, tags = excluded.tags, url = excluded.url",
+        let series_str = blog_post.series.clone().map(|series| series.join(","));
+        let mut tx = self.db.begin().await?;
+        // Unchanged posts are not updated and return no row
+        let changed = sqlx::query!(
+            r#"
+            WITH previous AS (SELECT draft FROM blog_posts WHERE slug = $2)
+            INSERT INTO blog_posts (title, slug, description, date, featured_image, tags, url, content, lastmod, draft, reading_time, word_count, series, summary)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            ON CONFLICT(slug) DO UPDATE SET title = excluded.title, description = excluded.description, date = excluded.date, featured_image = excluded.featured_image, tags = excluded.tags, url = excluded.url, content = excluded.content,
+                lastmod = excluded.lastmod, draft = excluded.draft, reading_time = excluded.reading_time, word_count = excluded.word_count, series = excluded.series, summary = excluded.summary
+            WHERE (blog_posts.title, blog_posts.description, blog_posts.date, blog_posts.featured_image, blog_posts.tags, blog_posts.url, blog_posts.content,
+                   blog_posts.lastmod, blog_posts.draft, blog_posts.reading_time, blog_posts.word_count, blog_posts.series, blog_posts.summary)
+                IS DISTINCT FROM (excluded.title, excluded.description, excluded.date, excluded.featured_image, excluded.tags, excluded.url, excluded.content,
+                   excluded.lastmod, excluded.draft, excluded.reading_time, excluded.word_count, excluded.series, excluded.summary)
+            RETURNING (xmax = 0) AS "inserted!", (SELECT draft FROM previous) AS was_draft
+            "#,
             blog_post.title,
             blog_post.slug,
             blog_post.description,
             blog_post.date,
             blog_post.featured_image,
             tags_str,
-            blog_post.url.to_string()
+            blog_post.url.to_string(),
+            blog_post.content,
+            blog_post.lastmod,
+            blog_post.draft,
+            blog_post.reading_time,
+            blog_post.word_count,
+            series_str,
+            blog_post.summary
         )
-        .execute(&self.db)
+        .fetch_optional(&mut *tx)
         .await?;
+        // Drafts are stored so their links work, but nobody is told about them until
+        // they are ingested without the flag. Unchanged posts, ie. every post on a
+        // restart, were announced when they were last stored.
+        if let (false, Some(changed)) = (blog_post.draft, changed) {
+            let published = changed.inserted || changed.was_draft == Some(true);
+            crate::webhooks::post_changed(&mut tx, blog_post, published).await?;
+            crate::syndication::enqueue(&mut tx, &blog_post.slug).await?;
+
+            let event = BackendEvent::PostIngested {
+                slug: blog_post.slug.clone(),
+            };
+            EventBus::publish(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
 
         counter!("blog_posts_processed_total").increment(1);
         histogram!("blog_database_query_duration_ms", "query" => "new_blog_entry")
@@ -0,0 +1,232 @@
+use std::{collections::HashMap, fmt};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Utc};
+use metrics::counter;
+use serde_json::Value;
+use tracing::warn;
+
+use super::HugoBlogPost;
+
+/// Longest slug the `blog_posts` key is expected to hold
+const MAX_SLUG_LEN: usize = 200;
+
+/// What happens to a file with invalid posts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Any invalid post stops the startup, nothing is ingested
+    Strict,
+    /// Invalid posts are logged and skipped, the valid ones are ingested
+    Lenient,
+}
+
+/// Rules every ingested post has to follow
+#[derive(Debug, Clone)]
+pub struct PostValidation {
+    pub mode: ValidationMode,
+    /// Host the post URLs must point at, any host is accepted when unset
+    pub domain: Option<String>,
+    /// How far past now a post may be dated, more is a typo rather than clock skew
+    pub max_future: Duration,
+}
+
+impl PostValidation {
+    /// Reads `POSTS_VALIDATION` (`strict` or `lenient`), `POSTS_DOMAIN` and
+    /// `POSTS_MAX_FUTURE_HOURS`
+    ///
+    /// The posts are served from the Hugo site, not from the host of the backend, so the
+    /// domain is never guessed from the other settings.
+    pub fn from_env() -> Result<Self> {
+        let mode = match std::env::var("POSTS_VALIDATION").as_deref() {
+            Ok("strict") | Err(_) => ValidationMode::Strict,
+            Ok("lenient") => ValidationMode::Lenient,
+            Ok(other) => bail!("POSTS_VALIDATION must be strict or lenient, not {other:?}"),
+        };
+        let domain = std::env::var("POSTS_DOMAIN")
+            .ok()
+            .filter(|domain| !domain.is_empty())
+            .map(|domain| domain.to_lowercase());
+        if domain.is_none() {
+            warn!("POSTS_DOMAIN is not set, the hosts of the post URLs are not checked");
+        }
+        let max_future = std::env::var("POSTS_MAX_FUTURE_HOURS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::hours)
+            .unwrap_or_else(|| Duration::hours(24));
+
+        Ok(Self {
+            mode,
+            domain,
+            max_future,
+        })
+    }
+
+    /// Parse and check every entry of the export on its own
+    ///
+    /// Entries with a problem are left out of the returned posts, and each problem is
+    /// reported with the JSON path of the entry or field. Of valid posts sharing a slug
+    /// only the first one is kept.
+    pub fn validate(&self, entries: Vec<Value>, now: DateTime<Utc>) -> Validated {
+        let mut posts = Vec::with_capacity(entries.len());
+        let mut errors = Vec::new();
+        let mut seen: HashMap<String, usize> = HashMap::new();
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            let post: HugoBlogPost = match serde_json::from_value(entry) {
+                Ok(post) => post,
+                Err(e) => {
+                    errors.push(PostError::new(
+                        format!("$[{index}]"),
+                        "invalid_entry",
+                        e.to_string(),
+                    ));
+                    continue;
+                }
+            };
+
+            let found = self.check(&post, now);
+            if !found.is_empty() {
+                // Left out, so it does not take the slug from a valid entry
+                errors.extend(found.into_iter().map(|(field, reason, message)| {
+                    PostError::new(format!("$[{index}].{field}"), reason, message)
+                }));
+                continue;
+            }
+
+            if let Some(first) = seen.get(&post.slug) {
+                errors.push(PostError::new(
+                    format!("$[{index}].slug"),
+                    "duplicate_slug",
+                    format!("{:?} is already used by $[{first}]", post.slug),
+                ));
+                continue;
+            }
+            seen.insert(post.slug.clone(), index);
+            posts.push(post);
+        }
+
+        Validated { posts, errors }
+    }
+
+    /// Problems of a single post as `(field, reason, message)`
+    fn check(
+        &self,
+        post: &HugoBlogPost,
+        now: DateTime<Utc>,
+    ) -> Vec<(&'static str, &'static str, String)> {
+        let mut found = Vec::new();
+
+        if post.title.trim().is_empty() {
+            found.push(("title", "empty_title", "is empty".to_string()));
+        }
+        if !is_valid_slug(&post.slug) {
+            found.push((
+                "slug",
+                "invalid_slug",
+                format!(
+                    "{:?} must be 1 to {MAX_SLUG_LEN} ASCII letters, digits, '-' or '_'",
+                    post.slug
+                ),
+            ));
+        }
+        if let Some(domain) = &self.domain {
+            match post.url.host_str() {
+                Some(host) if host == domain => {}
+                host => found.push((
+                    "url",
+                    "foreign_url",
+                    format!("host {:?} is not {:?}", host.unwrap_or_default(), domain),
+                )),
+            }
+        }
+        if post.date > now + self.max_future {
+            found.push((
+                "date",
+                "future_date",
+                format!(
+                    "{} is more than {} hours ahead",
+                    post.date.to_rfc3339(),
+                    self.max_future.num_hours()
+                ),
+            ));
+        }
+
+        found
+    }
+}
+
+/// Slugs end up in URLs and file names, keep them to a safe set of characters
+pub fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && slug.len() <= MAX_SLUG_LEN
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A problem found in the export
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostError {
+    /// JSON path of the entry or field, `$[3].slug`
+    pub path: String,
+    /// Short name of the problem, used as a metrics label
+    pub reason: &'static str,
+    pub message: String,
+}
+
+impl PostError {
+    fn new(path: String, reason: &'static str, message: String) -> Self {
+        Self {
+            path,
+            reason,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Outcome of [`PostValidation::validate`]
+#[derive(Debug)]
+pub struct Validated {
+    /// Posts without problems, in the order of the file
+    pub posts: Vec<HugoBlogPost>,
+    pub errors: Vec<PostError>,
+}
+
+impl Validated {
+    /// Apply the mode: record the problems, then fail in strict mode or keep the
+    /// valid posts in lenient mode
+    pub fn into_posts(self, mode: ValidationMode) -> Result<Vec<HugoBlogPost>> {
+        for error in &self.errors {
+            counter!("blog_posts_rejected_total", "reason" => error.reason).increment(1);
+        }
+        if self.errors.is_empty() {
+            return Ok(self.posts);
+        }
+
+        match mode {
+            ValidationMode::Strict => bail!("{}", self.report()),
+            ValidationMode::Lenient => {
+                for error in &self.errors {
+                    warn!(path = %error.path, reason = error.reason, "Skipping invalid post: {}", error.message);
+                }
+                Ok(self.posts)
+            }
+        }
+    }
+
+    /// One line per problem, readable in the startup log
+    pub fn report(&self) -> String {
+        let mut report = format!("{} problems in the blog posts file:", self.errors.len());
+        for error in &self.errors {
+            report.push_str(&format!("\n  {error}"));
+        }
+        report
+    }
+}
@@ -0,0 +1,701 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{PgConnection, PgPool};
+use tokio::{
+    sync::{Notify, Semaphore},
+    task::{JoinHandle, JoinSet},
+};
+use tracing::{info, info_span, instrument, warn, Instrument};
+
+type JobFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+type JobHandler = Arc<dyn Fn(Value) -> JobFuture + Send + Sync>;
+
+/// Called in the transaction marking a job failed, with its payload and last error
+///
+/// Lets the rows a job works on follow the attempts of the job, see [`JobRunner::on_give_up`].
+pub type GiveUpHook =
+    for<'c> fn(
+        &'c mut PgConnection,
+        &'c Value,
+        &'c str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'c>>;
+
+/// When a scheduled job runs
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// At a fixed interval, the first run is right after the job is scheduled
+    Every(Duration),
+    /// At the times of a cron expression with seconds, ie. `0 30 3 * * *` at 3:30 UTC
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    pub fn cron(expression: &str) -> anyhow::Result<Self> {
+        Ok(Schedule::Cron(Box::new(cron::Schedule::from_str(
+            expression,
+        )?)))
+    }
+
+    /// Cron expression from the `name` variable, an interval of `every` when it is not set
+    pub fn from_env(name: &str, every: Duration) -> Self {
+        let Ok(expression) = std::env::var(name) else {
+            return Schedule::Every(every);
+        };
+        Self::cron(&expression).unwrap_or_else(|e| {
+            warn!(error = %e, name, expression, "Invalid cron expression, using the interval");
+            Schedule::Every(every)
+        })
+    }
+
+    /// First run of a job scheduled at `now`
+    pub fn first_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(_) => Some(now),
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+
+    /// Run following one at `now`, `None` when a cron expression has no future times
+    pub fn next_run(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Every(every) => chrono::Duration::from_std(*every).ok().map(|d| now + d),
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+}
+
+/// How a kind of job is run
+#[derive(Debug, Clone)]
+pub struct JobOptions {
+    /// Jobs of this kind running at the same time in this instance
+    pub concurrency: usize,
+    /// Attempts before a job is given up and marked failed
+    pub max_attempts: i32,
+}
+
+impl Default for JobOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Settings of the runner shared by all kinds of jobs
+#[derive(Debug, Clone)]
+pub struct JobsConfig {
+    /// How often the runner looks for due jobs and schedules
+    pub poll_interval: Duration,
+    /// Running jobs not heard of for this long are considered abandoned by a crashed
+    /// instance and run again, the lock of a running job is refreshed three times as
+    /// often
+    pub lock_timeout: Duration,
+    /// How long running jobs get to finish on shutdown before they are cancelled
+    pub shutdown_timeout: Duration,
+    /// Wait after the first failed attempt, doubled after each following one
+    pub retry_base: Duration,
+    /// Longest wait between two attempts
+    pub retry_max: Duration,
+    /// Finished jobs are deleted after this many days
+    pub retention_days: i32,
+}
+
+impl JobsConfig {
+    pub fn from_env() -> Self {
+        let secs = |name: &str, default: u64| {
+            std::env::var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or_else(|| Duration::from_secs(default))
+        };
+
+        Self {
+            poll_interval: secs("JOBS_POLL_INTERVAL_SECS", 5),
+            lock_timeout: secs("JOBS_LOCK_TIMEOUT_SECS", 60 * 60),
+            shutdown_timeout: secs("JOBS_SHUTDOWN_TIMEOUT_SECS", 30),
+            retry_base: secs("JOBS_RETRY_BASE_SECS", 30),
+            retry_max: secs("JOBS_RETRY_MAX_SECS", 6 * 60 * 60),
+            retention_days: std::env::var("JOBS_RETENTION_DAYS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(7),
+        }
+    }
+
+    /// Wait before the next attempt of a job that failed `attempts` times
+    pub fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+        self.retry_base
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.retry_max)
+    }
+}
+
+/// Queue a one-off job of `kind`, due at `run_at`, returns its id
+///
+/// Takes an executor so jobs can be queued in the transaction of the change that
+/// needs them.
+pub async fn enqueue<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    kind: &str,
+    payload: &Value,
+    run_at: DateTime<Utc>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO jobs (kind, payload, run_at) VALUES ($1, $2, $3) RETURNING id",
+        kind,
+        payload,
+        run_at
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Queue a job of `kind` unless one is already waiting to run, returns whether it was
+/// queued
+///
+/// For jobs that pick up whatever there is to do when they run, a pending one will
+/// also take care of the work that made the caller queue another.
+pub async fn enqueue_unless_pending<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    kind: &str,
+    payload: &Value,
+    run_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let queued = sqlx::query!(
+        r#"
+        INSERT INTO jobs (kind, payload, run_at)
+        SELECT $1::VARCHAR, $2, $3 WHERE NOT EXISTS (
+            SELECT 1 FROM jobs WHERE kind = $1 AND status = 'pending'
+        )
+        "#,
+        kind,
+        payload,
+        run_at
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+    Ok(queued > 0)
+}
+
+struct JobKind {
+    handler: JobHandler,
+    options: JobOptions,
+    permits: Arc<Semaphore>,
+    on_give_up: Option<GiveUpHook>,
+}
+
+/// Runs the jobs of the `jobs` table
+///
+/// Kinds of jobs are registered with their handler before the runner is started.
+/// Jobs of unregistered kinds are left alone, another version of the backend may
+/// know them.
+pub struct JobRunner {
+    pool: PgPool,
+    config: JobsConfig,
+    kinds: BTreeMap<&'static str, JobKind>,
+    schedules: Vec<(&'static str, Schedule)>,
+    /// Jobs claimed by this runner that have not stored their result yet
+    running: Mutex<BTreeSet<i64>>,
+}
+
+/// Stops the started runner, see [`JobRunner::start`]
+pub struct JobRunnerHandle {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl JobRunnerHandle {
+    /// Stop claiming jobs and wait for the running ones to finish
+    pub async fn shutdown(self) {
+        self.stop.notify_one();
+        if let Err(e) = self.task.await {
+            warn!(error = %e, "Job runner stopped with an error");
+        }
+    }
+}
+
+impl JobRunner {
+    pub fn new(pool: PgPool, config: JobsConfig) -> Self {
+        Self {
+            pool,
+            config,
+            kinds: BTreeMap::new(),
+            schedules: Vec::new(),
+            running: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Run jobs of `kind` with `handler`, which gets the payload of the job
+    pub fn register<F, Fut>(&mut self, kind: &'static str, options: JobOptions, handler: F)
+    where
+        F: Fn(Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let handler: JobHandler = Arc::new(move |payload| Box::pin(handler(payload)));
+        let permits = Arc::new(Semaphore::new(options.concurrency.max(1)));
+        self.kinds.insert(
+            kind,
+            JobKind {
+                handler,
+                options,
+                permits,
+                on_give_up: None,
+            },
+        );
+    }
+
+    /// Call `hook` when a job of the registered `kind` runs out of attempts
+    ///
+    /// The hook runs in the same transaction that marks the job failed, so whatever it
+    /// records can't disagree with the job.
+    pub fn on_give_up(&mut self, kind: &'static str, hook: GiveUpHook) {
+        let job_kind = self
+            .kinds
+            .get_mut(kind)
+            .unwrap_or_else(|| panic!("{kind} is not registered"));
+        job_kind.on_give_up = Some(hook);
+    }
+
+    /// Queue a job of the registered `kind` on `schedule`
+    ///
+    /// A run is skipped while the previous one is still pending or running, so slow
+    /// jobs do not pile up.
+    pub fn schedule(&mut self, kind: &'static str, schedule: Schedule) {
+        debug_assert!(self.kinds.contains_key(kind), "{kind} is not registered");
+        self.schedules.push((kind, schedule));
+    }
+
+    /// Start polling for jobs, the returned handle shuts the runner down
+    pub fn start(self) -> JobRunnerHandle {
+        let stop = Arc::new(Notify::new());
+        let handle_stop = stop.clone();
+        info!(
+            kinds = ?self.kinds.keys().collect::<Vec<_>>(),
+            schedules = self.schedules.len(),
+            "Starting job runner"
+        );
+
+        let task = tokio::spawn(async move {
+            let runner = Arc::new(self);
+            if let Err(e) = runner.register_schedules().await {
+                warn!(error = %e, "Failed to register the job schedules");
+                counter!("blog_jobs_errors_total").increment(1);
+            }
+
+            let mut running = JoinSet::new();
+            loop {
+                if let Err(e) = runner.tick(&mut running).await {
+                    warn!(error = %e, "Failed to poll for jobs");
+                    counter!("blog_jobs_errors_total").increment(1);
+                }
+                tokio::select! {
+                    _ = stop.notified() => break,
+                    _ = tokio::time::sleep(runner.config.poll_interval) => {}
+                    // A freed slot may let the next job of its kind run
+                    Some(_) = running.join_next(), if !running.is_empty() => {}
+                }
+            }
+
+            info!(running = running.len(), "Job runner stopping");
+            let finished = tokio::time::timeout(runner.config.shutdown_timeout, async {
+                while running.join_next().await.is_some() {}
+            })
+            .await;
+            if finished.is_err() {
+                warn!(
+                    cancelled = running.len(),
+                    "Jobs did not finish in time, cancelling them"
+                );
+                running.abort_all();
+                while running.join_next().await.is_some() {}
+                if let Err(e) = runner.release_unfinished().await {
+                    warn!(error = %e, "Failed to queue the cancelled jobs again");
+                    counter!("blog_jobs_errors_total").increment(1);
+                }
+            }
+        });
+
+        JobRunnerHandle {
+            stop: handle_stop,
+            task,
+        }
+    }
+
+    /// Store the first run of new schedules
+    ///
+    /// Runs stored by a previous start are kept, unless the schedule now runs earlier.
+    async fn register_schedules(&self) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+        for (kind, schedule) in &self.schedules {
+            let (Some(first_run), Some(next_run)) =
+                (schedule.first_run(now), schedule.next_run(now))
+            else {
+                continue;
+            };
+            sqlx::query!(
+                r#"
+                INSERT INTO job_schedules (kind, next_run_at) VALUES ($1, $2)
+                ON CONFLICT (kind) DO UPDATE
+                SET next_run_at = LEAST(job_schedules.next_run_at, $3)
+                "#,
+                kind,
+                first_run,
+                next_run
+            )
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn tick(self: &Arc<Self>, running: &mut JoinSet<()>) -> Result<(), sqlx::Error> {
+        let recovered = sqlx::query!(
+            r#"
+            UPDATE jobs SET status = 'pending', locked_at = NULL, updated_at = NOW()
+            WHERE status = 'running' AND locked_at < NOW() - make_interval(secs => $1)
+            "#,
+            self.config.lock_timeout.as_secs_f64()
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        if recovered > 0 {
+            warn!(recovered, "Abandoned jobs queued again");
+            counter!("blog_jobs_recovered_total").increment(recovered);
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM jobs
+            WHERE status IN ('done', 'failed') AND completed_at < NOW() - make_interval(days => $1)
+            "#,
+            self.config.retention_days
+        )
+        .execute(&self.pool)
+        .await?;
+
+        for (kind, schedule) in &self.schedules {
+            self.run_schedule(kind, schedule).await?;
+        }
+
+        for (&kind, job_kind) in &self.kinds {
+            while let Ok(permit) = job_kind.permits.clone().try_acquire_owned() {
+                let Some(job) = self.claim(kind).await? else {
+                    break;
+                };
+                let job_id = job.id;
+                self.running.lock().unwrap().insert(job_id);
+                let runner = self.clone();
+                running.spawn(async move {
+                    runner.run(kind, job).await;
+                    runner.running.lock().unwrap().remove(&job_id);
+                    drop(permit);
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand the jobs cancelled on shutdown to the next instance right away, instead of
+    /// after the lock timeout
+    async fn release_unfinished(&self) -> Result<(), sqlx::Error> {
+        let unfinished: Vec<i64> = std::mem::take(&mut *self.running.lock().unwrap())
+            .into_iter()
+            .collect();
+        let released = sqlx::query!(
+            r#"
+            UPDATE jobs SET status = 'pending', locked_at = NULL, updated_at = NOW()
+            WHERE id = ANY($1) AND status = 'running'
+            "#,
+            &unfinished
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        info!(released, "Cancelled jobs queued again");
+        Ok(())
+    }
+
+    /// Queue the job of a due schedule and move the schedule to its next run
+    async fn run_schedule(&self, kind: &str, schedule: &Schedule) -> Result<(), sqlx::Error> {
+        let Some(next_run) = schedule.next_run(Utc::now()) else {
+            return Ok(());
+        };
+        let mut tx = self.pool.begin().await?;
+        let due = sqlx::query!(
+            r#"
+            UPDATE job_schedules SET next_run_at = $2, last_run_at = NOW()
+            WHERE kind = $1 AND next_run_at <= NOW()
+            "#,
+            kind,
+            next_run
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if due > 0 {
+            let queued = sqlx::query!(
+                r#"
+                INSERT INTO jobs (kind)
+                SELECT $1::VARCHAR WHERE NOT EXISTS (
+                    SELECT 1 FROM jobs WHERE kind = $1 AND status IN ('pending', 'running')
+                )
+                "#,
+                kind
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            if queued == 0 {
+                counter!("blog_jobs_schedule_skipped_total", "kind" => kind.to_string())
+                    .increment(1);
+            }
+        }
+        tx.commit().await
+    }
+
+    /// Mark the next due job of `kind` as running
+    async fn claim(&self, kind: &str) -> Result<Option<ClaimedJob>, sqlx::Error> {
+        sqlx::query_as!(
+            ClaimedJob,
+            r#"
+            UPDATE jobs
+            SET status = 'running', attempts = attempts + 1, locked_at = NOW(), updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE kind = $1 AND status = 'pending' AND run_at <= NOW()
+                ORDER BY run_at, id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, payload, attempts
+            "#,
+            kind
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn run(&self, kind: &'static str, job: ClaimedJob) {
+        let job_kind = &self.kinds[kind];
+        let span = info_span!("job", kind, job_id = job.id, attempt = job.attempts);
+
+        async {
+            let start_time = std::time::Instant::now();
+            gauge!("blog_jobs_running", "kind" => kind).increment(1.0);
+            // Spawned so a panicking handler fails the job instead of the runner
+            let mut handler = tokio::spawn((job_kind.handler)(job.payload.clone()));
+            let mut heartbeat = tokio::time::interval(
+                (self.config.lock_timeout / 3).max(Duration::from_millis(100)),
+            );
+            heartbeat.tick().await;
+            let result = loop {
+                tokio::select! {
+                    joined = &mut handler => break match joined {
+                        Ok(result) => result,
+                        Err(e) => Err(anyhow::anyhow!("Job panicked: {e}")),
+                    },
+                    _ = heartbeat.tick() => self.heartbeat(job.id).await,
+                }
+            };
+            gauge!("blog_jobs_running", "kind" => kind).decrement(1.0);
+            histogram!("blog_job_duration_ms", "kind" => kind)
+                .record(start_time.elapsed().as_millis() as f64);
+
+            let (status, error) = match &result {
+                Ok(()) => ("done", None),
+                Err(e) if job.attempts >= job_kind.options.max_attempts => {
+                    ("failed", Some(format!("{e:#}")))
+                }
+                Err(e) => ("pending", Some(format!("{e:#}"))),
+            };
+            let retry_in = self.config.backoff(job.attempts);
+            let updated = self
+                .store_result(job_kind, &job, status, error.as_deref(), retry_in)
+                .await;
+
+            match (&result, status) {
+                (Ok(()), _) => info!("Job done"),
+                (Err(e), "failed") => warn!(error = %e, "Job failed, giving up"),
+                (Err(e), _) => {
+                    warn!(error = %e, retry_in_secs = retry_in.as_secs(), "Job failed, retrying")
+                }
+            }
+            if let Err(e) = updated {
+                warn!(error = %e, "Failed to store the job result");
+                counter!("blog_jobs_errors_total").increment(1);
+            }
+            let status = if status == "pending" { "retry" } else { status };
+            counter!("blog_jobs_total", "kind" => kind, "status" => status).increment(1);
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Refresh the lock of a running job, so it is not taken for abandoned
+    async fn heartbeat(&self, job_id: i64) {
+        let refreshed = sqlx::query!(
+            "UPDATE jobs SET locked_at = NOW() WHERE id = $1 AND status = 'running'",
+            job_id
+        )
+        .execute(&self.pool)
+        .await;
+        if let Err(e) = refreshed {
+            warn!(error = %e, "Failed to refresh the lock of the job");
+            counter!("blog_jobs_errors_total").increment(1);
+        }
+    }
+
+    /// Record the outcome of an attempt, a job given up is passed to the hook of its kind
+    async fn store_result(
+        &self,
+        job_kind: &JobKind,
+        job: &ClaimedJob,
+        status: &str,
+        error: Option<&str>,
+        retry_in: Duration,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            r#"
+            UPDATE jobs
+            SET status = $2::VARCHAR, last_error = $3, locked_at = NULL, updated_at = NOW(),
+                run_at = CASE WHEN $2::VARCHAR = 'pending' THEN NOW() + make_interval(secs => $4) ELSE run_at END,
+                completed_at = CASE WHEN $2::VARCHAR = 'pending' THEN NULL ELSE NOW() END
+            WHERE id = $1
+            "#,
+            job.id,
+            status,
+            error,
+            retry_in.as_secs_f64()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if let (Some(hook), "failed", Some(error)) = (job_kind.on_give_up, status, error) {
+            hook(&mut tx, &job.payload, error).await?;
+        }
+        tx.commit().await
+    }
+}
+
+struct ClaimedJob {
+    id: i64,
+    payload: Value,
+    attempts: i32,
+}
+
+/// Job as listed by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: i64,
+    pub kind: String,
+    pub payload: Value,
+    /// `pending`, `running`, `done` or `failed`
+    pub status: String,
+    pub attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JobsQuery {
+    pub kind: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Latest jobs, from the admin API
+#[instrument(skip(pool))]
+pub async fn list_jobs(
+    Query(query): Query<JobsQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<JobInfo>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let jobs = sqlx::query_as!(
+        JobInfo,
+        r#"
+        SELECT id, kind, payload, status, attempts, run_at, last_error, created_at, completed_at
+        FROM jobs
+        WHERE ($1::VARCHAR IS NULL OR kind = $1) AND ($2::VARCHAR IS NULL OR status = $2)
+        ORDER BY id DESC
+        LIMIT $3
+        "#,
+        query.kind,
+        query.status,
+        limit
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error listing jobs");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(jobs))
+}
+
+/// Queue a run of a scheduled job now, from the admin API
+///
+/// Only scheduled kinds can be queued, they are the ones that need no payload.
+#[instrument(skip(pool))]
+pub async fn run_scheduled_job(
+    Path(kind): Path<String>,
+    State(pool): State<PgPool>,
+) -> Result<(StatusCode, Json<JobInfo>), StatusCode> {
+    let database_error = |e: sqlx::Error| {
+        warn!(error = %e, kind = %kind, "Database error queueing a job");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let scheduled = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM job_schedules WHERE kind = $1) AS "exists!""#,
+        kind
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(database_error)?;
+    if !scheduled {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let id = enqueue(&pool, &kind, &Value::Object(Default::default()), Utc::now())
+        .await
+        .map_err(database_error)?;
+    let job = sqlx::query_as!(
+        JobInfo,
+        r#"
+        SELECT id, kind, payload, status, attempts, run_at, last_error, created_at, completed_at
+        FROM jobs WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(database_error)?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
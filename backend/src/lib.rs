@@ -1,6 +1,24 @@
+pub mod activitypub;
+pub mod admin;
+pub mod analytics;
+pub mod bluesky;
 pub mod correlation;
 pub mod database;
 pub mod error;
+pub mod events;
 pub mod hugo_posts;
+pub mod jobs;
 pub mod likes;
 pub mod observability;
+pub mod og_image;
+pub mod plausible_import;
+pub mod privacy;
+pub mod referrers;
+pub mod retention;
+pub mod search;
+pub mod state;
+pub mod subscriptions;
+pub mod syndication;
+pub mod trusted_proxies;
+pub mod views;
+pub mod webhooks;
@@ -1,21 +1,69 @@
-use crate::correlation::CorrelationContext;
+use crate::{
+    correlation::CorrelationContext,
+    events::{BackendEvent, EventBus},
+    referrers::{referrer_id, Referrer, ReferrerQuery},
+};
+use abuse::{AbusePipeline, LikeAttempt, LIKE_TOKEN_HEADER, PROOF_OF_WORK_HEADER};
 use axum::{
-    extract::{Extension, Path, State},
-    http::{HeaderMap, StatusCode},
-    response::Json,
+    extract::{Extension, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
 };
+use cache::LikeCountCache;
 use chrono::{DateTime, Utc};
-use metrics::{counter, gauge, histogram};
+use metrics::{counter, histogram};
+use reactions::Reactions;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use sources::{bump_source_count, fetch_source_counts, LikeSource};
 use sqlx::PgPool;
+use std::{collections::BTreeMap, sync::Arc};
 use tracing::{info, instrument, warn};
 
+pub mod abuse;
+pub mod badge;
+pub mod cache;
+pub mod counters;
+pub mod reactions;
+pub mod sources;
+pub mod stream;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LikeResponse {
     pub success: bool,
     pub message: String,
     pub total_likes: i64,
+    /// Count per reaction kind, reactions nobody left are missing
+    #[serde(default)]
+    pub reactions: BTreeMap<String, i64>,
+    /// Count per source (`web`, `fediverse`), they add up to `total_likes`
+    #[serde(default)]
+    pub sources: BTreeMap<String, i64>,
+}
+
+/// Token of one client for liking a post, see [`get_like_token`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LikeTokenResponse {
+    /// Token that has to be sent back in `x-like-token` when liking the post
+    pub like_token: String,
+    /// Leading zero bits required from `sha256("<token>:<nonce>")`, absent when disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pow_difficulty: Option<u8>,
+}
+
+/// Likes of a post - the total over all reactions and sources and the count of each
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LikeCounts {
+    pub total: i64,
+    pub reactions: BTreeMap<String, i64>,
+    pub sources: BTreeMap<String, i64>,
+}
+
+/// `/like/:post_slug` has no reaction and records the default one
+#[derive(Debug, Deserialize)]
+pub struct ReactionPath {
+    post_slug: String,
+    reaction: Option<String>,
 }
 
 #[allow(dead_code)]
@@ -33,19 +81,52 @@ fn hash_ip(ip: &str) -> String {
     hex::encode(result)
 }
 
-#[instrument(skip(pool, headers, correlation_ctx), fields(post_slug = %post_slug))]
+/// Hash of the client IP resolved by the correlation middleware - the only
+/// identifier of a visitor we keep
+pub(crate) fn client_ip_hash(correlation_ctx: &CorrelationContext) -> String {
+    hash_ip(correlation_ctx.remote_ip.as_deref().unwrap_or("unknown"))
+}
+
+/// Record a reaction to a post - served both by `/like/:post_slug` and
+/// `/react/:post_slug/:reaction`
+#[instrument(skip(pool, abuse, cache, reactions, headers, correlation_ctx), fields(post_slug = %path.post_slug))]
+#[allow(clippy::too_many_arguments)]
 pub async fn like_post(
-    Path(post_slug): Path<String>,
+    Path(path): Path<ReactionPath>,
+    Query(referrer_query): Query<ReferrerQuery>,
     State(pool): State<PgPool>,
+    State(abuse): State<Arc<AbusePipeline>>,
+    State(cache): State<Arc<LikeCountCache>>,
+    State(reactions): State<Arc<Reactions>>,
     Extension(correlation_ctx): Extension<CorrelationContext>,
     headers: HeaderMap,
 ) -> Result<Json<LikeResponse>, StatusCode> {
     let start_time = std::time::Instant::now();
     counter!("blog_likes_requests_total", "endpoint" => "like_post").increment(1);
 
-    // Extract IP address from Cloudflare headers or fallback
-    let user_ip = extract_user_ip(&headers);
-    let user_ip_hash = hash_ip(&user_ip);
+    let post_slug = path.post_slug;
+    let reaction = path
+        .reaction
+        .unwrap_or_else(|| reactions.default_reaction().to_string());
+
+    if !reactions.is_allowed(&reaction) {
+        counter!("blog_likes_errors_total", "reason" => "unknown_reaction").increment(1);
+        histogram!("blog_likes_request_duration_ms", "endpoint" => "like_post", "status" => "error")
+            .record(start_time.elapsed().as_millis() as f64);
+        let counts = get_like_counts(&pool, &cache, &post_slug)
+            .await
+            .unwrap_or_default();
+        return Ok(Json(LikeResponse {
+            success: false,
+            message: format!("Unknown reaction: {reaction}"),
+            total_likes: counts.total,
+            reactions: counts.reactions,
+            sources: counts.sources,
+        }));
+    }
+
+    // Client IP resolved by the correlation middleware through the trusted proxies
+    let user_ip_hash = client_ip_hash(&correlation_ctx);
 
     let user_agent = headers
         .get("user-agent")
@@ -53,28 +134,57 @@ pub async fn like_post(
         .unwrap_or("unknown")
         .to_string();
 
-    let cf_country = headers
-        .get("cf-ipcountry")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
-
-    let cf_connecting_ip = headers
-        .get("cf-connecting-ip")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.to_string());
+    let cf_country = correlation_ctx.cf_header(&headers, "cf-ipcountry");
+    let cf_connecting_ip = correlation_ctx.cf_header(&headers, "cf-connecting-ip");
 
-    let cf_connecting_ip_hash = cf_connecting_ip.as_ref().map(|ip| hash_ip(ip));
+    let cf_connecting_ip_hash = cf_connecting_ip.map(hash_ip);
+    let referrer = Referrer::from_request(&headers, referrer_query.referrer.as_deref());
 
     info!(
         post_slug = %post_slug,
+        reaction = %reaction,
         user_ip_hash = %user_ip_hash,
         user_agent = %user_agent,
         cf_country = ?cf_country,
+        referrer = ?referrer.host,
         correlation_id = %correlation_ctx.correlation_id,
         request_id = %correlation_ctx.request_id,
         "Processing like request"
     );
 
+    let attempt = LikeAttempt {
+        post_slug: &post_slug,
+        user_ip_hash: &user_ip_hash,
+        user_agent: headers.get("user-agent").and_then(|h| h.to_str().ok()),
+        token: headers.get(LIKE_TOKEN_HEADER).and_then(|h| h.to_str().ok()),
+        proof_of_work: headers
+            .get(PROOF_OF_WORK_HEADER)
+            .and_then(|h| h.to_str().ok()),
+        now: Utc::now(),
+    };
+
+    if let Err(rejection) = abuse.check(&attempt) {
+        info!(
+            post_slug = %post_slug,
+            user_ip_hash = %user_ip_hash,
+            reason = rejection.reason,
+            "Like rejected by abuse checks"
+        );
+        counter!("blog_likes_rejected_total", "reason" => rejection.reason).increment(1);
+        histogram!("blog_likes_request_duration_ms", "endpoint" => "like_post", "status" => "rejected")
+            .record(start_time.elapsed().as_millis() as f64);
+        let counts = get_like_counts(&pool, &cache, &post_slug)
+            .await
+            .unwrap_or_default();
+        return Ok(Json(LikeResponse {
+            success: false,
+            message: rejection.message,
+            total_likes: counts.total,
+            reactions: counts.reactions,
+            sources: counts.sources,
+        }));
+    }
+
     // Check if the post exists
     let post_exists = sqlx::query!("SELECT slug FROM blog_posts WHERE slug = $1", post_slug)
         .fetch_optional(&pool)
@@ -92,6 +202,8 @@ pub async fn like_post(
             success: false,
             message: "Blog post not found".to_string(),
             total_likes: 0,
+            reactions: BTreeMap::new(),
+            sources: BTreeMap::new(),
         }));
     }
 
@@ -99,35 +211,45 @@ pub async fn like_post(
     let now = Utc::now();
     let hour_bucket = now.format("%Y-%m-%d %H").to_string();
 
-    let result = sqlx::query!(
-        r#"
-        INSERT INTO blog_post_likes (post_slug, user_ip_hash, user_agent, cf_country, cf_connecting_ip_hash, liked_at, hour_bucket)
-        VALUES ($1, $2, $3, $4, $5, NOW(), $6)
-        "#,
-        post_slug,
-        user_ip_hash,
-        user_agent,
+    let result = insert_like(
+        &pool,
+        &post_slug,
+        &reaction,
+        &user_ip_hash,
+        &user_agent,
         cf_country,
-        cf_connecting_ip_hash,
-        hour_bucket
+        cf_connecting_ip_hash.as_deref(),
+        &referrer,
+        &hour_bucket,
     )
-    .execute(&pool)
     .await;
 
-    match result {
-        Ok(_) => {
-            info!(post_slug = %post_slug, user_ip_hash = %user_ip_hash, "Like recorded successfully");
-            counter!("blog_likes_successful_total").increment(1);
+    let counts = match result {
+        Ok(counts) => {
+            info!(post_slug = %post_slug, reaction = %reaction, user_ip_hash = %user_ip_hash, "Like recorded successfully");
+            counter!("blog_likes_successful_total", "reaction" => reaction.clone()).increment(1);
+            cache.invalidate(&post_slug);
+            counts
         }
         Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-            info!(post_slug = %post_slug, user_ip_hash = %user_ip_hash, "Like already exists within rate limit window");
+            info!(post_slug = %post_slug, reaction = %reaction, user_ip_hash = %user_ip_hash, "Like already exists within rate limit window");
             counter!("blog_likes_rate_limited_total").increment(1);
             histogram!("blog_likes_request_duration_ms", "endpoint" => "like_post", "status" => "rate_limited")
                 .record(start_time.elapsed().as_millis() as f64);
+            let message = if reaction == reactions.default_reaction() {
+                "You can only like a post once per hour".to_string()
+            } else {
+                format!("You can only react with {reaction} to a post once per hour")
+            };
+            let counts = get_like_counts(&pool, &cache, &post_slug)
+                .await
+                .unwrap_or_default();
             return Ok(Json(LikeResponse {
                 success: false,
-                message: "You can only like a post once per hour".to_string(),
-                total_likes: get_like_count(&pool, &post_slug).await.unwrap_or(0),
+                message,
+                total_likes: counts.total,
+                reactions: counts.reactions,
+                sources: counts.sources,
             }));
         }
         Err(e) => {
@@ -137,29 +259,28 @@ pub async fn like_post(
                 .record(start_time.elapsed().as_millis() as f64);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
-    }
-
-    // Get the total like count for this post
-    let total_likes = get_like_count(&pool, &post_slug).await.unwrap_or(0);
+    };
 
-    // Update metrics
-    gauge!("blog_post_likes_total", "post_slug" => post_slug.clone()).set(total_likes as f64);
     histogram!("blog_likes_request_duration_ms", "endpoint" => "like_post", "status" => "success")
         .record(start_time.elapsed().as_millis() as f64);
 
     Ok(Json(LikeResponse {
         success: true,
         message: "Like recorded successfully".to_string(),
-        total_likes,
+        total_likes: counts.total,
+        reactions: counts.reactions,
+        sources: counts.sources,
     }))
 }
 
-#[instrument(skip(pool, correlation_ctx), fields(post_slug = %post_slug))]
+#[instrument(skip(pool, cache, correlation_ctx, headers), fields(post_slug = %post_slug))]
 pub async fn get_likes(
     Path(post_slug): Path<String>,
     State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
     Extension(correlation_ctx): Extension<CorrelationContext>,
-) -> Result<Json<LikeResponse>, StatusCode> {
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
     let start_time = std::time::Instant::now();
     counter!("blog_likes_requests_total", "endpoint" => "get_likes").increment(1);
 
@@ -170,7 +291,7 @@ pub async fn get_likes(
         "Retrieving like count"
     );
 
-    let total_likes = get_like_count(&pool, &post_slug).await.map_err(|e| {
+    let counts = get_like_counts(&pool, &cache, &post_slug).await.map_err(|e| {
         warn!(
             error = %e,
             post_slug = %post_slug,
@@ -185,7 +306,7 @@ pub async fn get_likes(
 
     info!(
         post_slug = %post_slug,
-        total_likes = %total_likes,
+        total_likes = %counts.total,
         correlation_id = %correlation_ctx.correlation_id,
         "Like count retrieved successfully"
     );
@@ -193,60 +314,207 @@ pub async fn get_likes(
     histogram!("blog_likes_request_duration_ms", "endpoint" => "get_likes", "status" => "success")
         .record(start_time.elapsed().as_millis() as f64);
 
-    Ok(Json(LikeResponse {
-        success: true,
-        message: "Like count retrieved successfully".to_string(),
-        total_likes,
-    }))
+    // The same for every client, the like token is issued by `get_like_token`
+    let etag = like_count_etag(&post_slug, &counts);
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            HeaderValue::from_str(&format!("public, max-age={}", cache.ttl().as_secs()))
+                .expect("valid header value"),
+        ),
+    ];
+
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes());
+    if not_modified {
+        counter!("blog_likes_not_modified_total").increment(1);
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((
+        cache_headers,
+        Json(LikeResponse {
+            success: true,
+            message: "Like count retrieved successfully".to_string(),
+            total_likes: counts.total,
+            reactions: counts.reactions,
+            sources: counts.sources,
+        }),
+    )
+        .into_response())
+}
+
+/// Issue a token for liking the post, bound to the client and never cached
+///
+/// Proof that the page was loaded, required to like the post.
+#[instrument(skip(abuse, correlation_ctx), fields(post_slug = %post_slug))]
+pub async fn get_like_token(
+    Path(post_slug): Path<String>,
+    State(abuse): State<Arc<AbusePipeline>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> impl IntoResponse {
+    counter!("blog_likes_requests_total", "endpoint" => "get_like_token").increment(1);
+
+    let user_ip_hash = client_ip_hash(&correlation_ctx);
+    let like_token = abuse.issue_token(&post_slug, &user_ip_hash);
+    let pow_difficulty = Some(abuse.pow_difficulty()).filter(|d| *d > 0);
+    (
+        [(header::CACHE_CONTROL, HeaderValue::from_static("no-store"))],
+        Json(LikeTokenResponse {
+            like_token,
+            pow_difficulty,
+        }),
+    )
 }
 
+fn like_count_etag(post_slug: &str, counts: &LikeCounts) -> HeaderValue {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{post_slug}\n{}\n", counts.total));
+    for (reaction, count) in &counts.reactions {
+        hasher.update(format!("{reaction}={count}\n"));
+    }
+    for (source, count) in &counts.sources {
+        hasher.update(format!("source:{source}={count}\n"));
+    }
+    let digest = hasher.finalize();
+    HeaderValue::from_str(&format!("\"{}\"", hex::encode(&digest[..16]))).expect("valid etag")
+}
+
+/// Insert the like and bump the post and reaction counters in one transaction,
+/// returns the new counts
 #[instrument(skip(pool))]
-async fn get_like_count(pool: &PgPool, post_slug: &str) -> Result<i64, sqlx::Error> {
+#[allow(clippy::too_many_arguments)]
+async fn insert_like(
+    pool: &PgPool,
+    post_slug: &str,
+    reaction: &str,
+    user_ip_hash: &str,
+    user_agent: &str,
+    cf_country: Option<&str>,
+    cf_connecting_ip_hash: Option<&str>,
+    referrer: &Referrer,
+    hour_bucket: &str,
+) -> Result<LikeCounts, sqlx::Error> {
     let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    let referrer_id = referrer_id(&mut *tx, referrer).await?;
 
-    let result = sqlx::query!(
-        "SELECT COUNT(*) as count FROM blog_post_likes WHERE post_slug = $1",
+    sqlx::query!(
+        r#"
+        INSERT INTO blog_post_likes (post_slug, reaction, user_ip_hash, user_agent, cf_country, cf_connecting_ip_hash, referrer_id, liked_at, hour_bucket)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), $8)
+        "#,
+        post_slug,
+        reaction,
+        user_ip_hash,
+        user_agent,
+        cf_country,
+        cf_connecting_ip_hash,
+        referrer_id,
+        hour_bucket
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let total_likes = sqlx::query_scalar!(
+        "UPDATE blog_posts SET like_count = like_count + 1 WHERE slug = $1 RETURNING like_count",
         post_slug
     )
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
 
-    let count: i64 = result.count.unwrap_or(0);
+    let reaction_count = sqlx::query_scalar!(
+        r#"
+        INSERT INTO blog_post_reaction_counts (post_slug, reaction, count)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (post_slug, reaction)
+        DO UPDATE SET count = blog_post_reaction_counts.count + 1
+        RETURNING count
+        "#,
+        post_slug,
+        reaction
+    )
+    .fetch_one(&mut *tx)
+    .await?;
 
-    histogram!("blog_database_query_duration_ms", "query" => "get_like_count")
+    bump_source_count(&mut *tx, post_slug, LikeSource::Web, 1).await?;
+    let reactions = fetch_reaction_counts(&mut *tx, post_slug).await?;
+    let sources = fetch_source_counts(&mut *tx, post_slug).await?;
+
+    // Delivered to the subscribers once the transaction commits
+    let event = BackendEvent::LikeRecorded {
+        post_slug: post_slug.to_string(),
+        reaction: reaction.to_string(),
+        total_likes,
+        reaction_count,
+    };
+    EventBus::publish(&mut *tx, &event).await?;
+    crate::webhooks::like_recorded(&mut tx, post_slug, total_likes).await?;
+
+    tx.commit().await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "insert_like")
         .record(start_time.elapsed().as_millis() as f64);
 
-    Ok(count)
+    Ok(LikeCounts {
+        total: total_likes,
+        reactions,
+        sources,
+    })
 }
 
-fn extract_user_ip(headers: &HeaderMap) -> String {
-    // Try to get the real IP from Cloudflare headers first
-    if let Some(cf_connecting_ip) = headers.get("cf-connecting-ip") {
-        if let Ok(ip) = cf_connecting_ip.to_str() {
-            return ip.to_string();
-        }
+/// Read-through lookup of the like counts, the database is only hit on a cache miss
+#[instrument(skip(pool, cache))]
+async fn get_like_counts(
+    pool: &PgPool,
+    cache: &LikeCountCache,
+    post_slug: &str,
+) -> Result<LikeCounts, sqlx::Error> {
+    if let Some(counts) = cache.get(post_slug) {
+        return Ok(counts);
     }
 
-    // Fallback to other common headers
-    let ip_headers = [
-        "x-forwarded-for",
-        "x-real-ip",
-        "x-client-ip",
-        "cf-pseudo-ipv4",
-    ];
+    let start_time = std::time::Instant::now();
 
-    for header_name in &ip_headers {
-        if let Some(header_value) = headers.get(*header_name) {
-            if let Ok(ip_str) = header_value.to_str() {
-                // X-Forwarded-For can contain multiple IPs, take the first one
-                let ip = ip_str.split(',').next().unwrap_or(ip_str).trim();
-                if !ip.is_empty() {
-                    return ip.to_string();
-                }
-            }
-        }
-    }
+    let total = sqlx::query_scalar!(
+        "SELECT like_count FROM blog_posts WHERE slug = $1",
+        post_slug
+    )
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(0);
+    let reactions = fetch_reaction_counts(pool, post_slug).await?;
+    let sources = fetch_source_counts(pool, post_slug).await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "get_like_count")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    let counts = LikeCounts {
+        total,
+        reactions,
+        sources,
+    };
+    cache.insert(post_slug, counts.clone());
+
+    Ok(counts)
+}
+
+async fn fetch_reaction_counts<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    post_slug: &str,
+) -> Result<BTreeMap<String, i64>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT reaction, count FROM blog_post_reaction_counts WHERE post_slug = $1 AND count > 0",
+        post_slug
+    )
+    .fetch_all(executor)
+    .await?;
 
-    // Ultimate fallback
-    "unknown".to_string()
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.reaction, row.count))
+        .collect())
 }
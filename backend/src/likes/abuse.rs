@@ -0,0 +1,347 @@
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying the token issued by `GET /likes/:slug`
+pub const LIKE_TOKEN_HEADER: &str = "x-like-token";
+/// Header carrying the proof-of-work nonce for the like token
+pub const PROOF_OF_WORK_HEADER: &str = "x-like-pow";
+
+/// Substrings of user agents that are never allowed to like posts
+pub const BLOCKED_USER_AGENT_PATTERNS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "curl",
+    "wget",
+    "python-requests",
+    "python-urllib",
+    "aiohttp",
+    "httpx",
+    "go-http-client",
+    "okhttp",
+    "java/",
+    "libwww-perl",
+    "scrapy",
+    "node-fetch",
+    "axios",
+    "headlesschrome",
+    "phantomjs",
+];
+
+/// Whether the user agent is missing or looks like a script or crawler
+pub fn is_automated_user_agent(user_agent: Option<&str>) -> bool {
+    match user_agent.map(str::trim) {
+        Some(user_agent) if !user_agent.is_empty() => {
+            let user_agent = user_agent.to_lowercase();
+            BLOCKED_USER_AGENT_PATTERNS
+                .iter()
+                .any(|pattern| user_agent.contains(pattern))
+        }
+        _ => true,
+    }
+}
+
+/// Everything the abuse checks know about a like submission
+#[derive(Debug, Clone, Copy)]
+pub struct LikeAttempt<'a> {
+    pub post_slug: &'a str,
+    pub user_ip_hash: &'a str,
+    pub user_agent: Option<&'a str>,
+    pub token: Option<&'a str>,
+    pub proof_of_work: Option<&'a str>,
+    pub now: DateTime<Utc>,
+}
+
+/// Reason a like was refused - `reason` is used as a metrics label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+    pub reason: &'static str,
+    pub message: String,
+}
+
+impl Rejection {
+    fn new(reason: &'static str, message: &str) -> Self {
+        Self {
+            reason,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Single step of the abuse pipeline
+pub trait AbuseCheck: Send + Sync {
+    fn check(&self, attempt: &LikeAttempt<'_>) -> Result<(), Rejection>;
+}
+
+/// Rejects requests without a user agent or with one that looks like a script, see
+/// [`is_automated_user_agent`]
+#[derive(Debug, Clone, Default)]
+pub struct UserAgentCheck;
+
+impl AbuseCheck for UserAgentCheck {
+    fn check(&self, attempt: &LikeAttempt<'_>) -> Result<(), Rejection> {
+        if !is_automated_user_agent(attempt.user_agent) {
+            return Ok(());
+        }
+
+        let missing = attempt
+            .user_agent
+            .is_none_or(|user_agent| user_agent.trim().is_empty());
+        let reason = if missing {
+            "missing_user_agent"
+        } else {
+            "bot_user_agent"
+        };
+        Err(Rejection::new(reason, "Automated requests are not allowed"))
+    }
+}
+
+/// Issues and verifies short-lived tokens proving that the post page was loaded
+///
+/// The token has the form `<expires unix timestamp>.<hex hmac>` and is bound to the
+/// post slug and the hashed client IP
+#[derive(Clone)]
+pub struct LikeTokens {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl LikeTokens {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    /// Tokens are rounded down to the minute, so repeated requests get the same token
+    /// and `GET /likes/:slug` responses can be revalidated with their ETag
+    pub fn issue(&self, post_slug: &str, user_ip_hash: &str, now: DateTime<Utc>) -> String {
+        let expires = (now + self.ttl).timestamp();
+        let expires = expires - expires.rem_euclid(60);
+        let signature = self.sign(post_slug, user_ip_hash, expires);
+        format!("{expires}.{}", hex::encode(signature))
+    }
+
+    pub fn verify(
+        &self,
+        token: &str,
+        post_slug: &str,
+        user_ip_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), Rejection> {
+        let invalid = || Rejection::new("invalid_token", "Please reload the page and try again");
+
+        let (expires, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let expires: i64 = expires.parse().map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+
+        let mac = self.mac(post_slug, user_ip_hash, expires);
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        if now.timestamp() > expires {
+            return Err(Rejection::new(
+                "expired_token",
+                "Please reload the page and try again",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn sign(&self, post_slug: &str, user_ip_hash: &str, expires: i64) -> Vec<u8> {
+        self.mac(post_slug, user_ip_hash, expires)
+            .finalize()
+            .into_bytes()
+            .to_vec()
+    }
+
+    fn mac(&self, post_slug: &str, user_ip_hash: &str, expires: i64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC can take key of any size");
+        mac.update(format!("{post_slug}\n{user_ip_hash}\n{expires}").as_bytes());
+        mac
+    }
+}
+
+/// Requires a valid like token issued by `GET /likes/:slug`
+#[derive(Clone)]
+pub struct LikeTokenCheck {
+    tokens: LikeTokens,
+}
+
+impl LikeTokenCheck {
+    pub fn new(tokens: LikeTokens) -> Self {
+        Self { tokens }
+    }
+}
+
+impl AbuseCheck for LikeTokenCheck {
+    fn check(&self, attempt: &LikeAttempt<'_>) -> Result<(), Rejection> {
+        let token = attempt.token.ok_or_else(|| {
+            Rejection::new("missing_token", "Please reload the page and try again")
+        })?;
+        self.tokens
+            .verify(token, attempt.post_slug, attempt.user_ip_hash, attempt.now)
+    }
+}
+
+/// Requires `sha256("<token>:<nonce>")` to start with `difficulty` zero bits
+#[derive(Debug, Clone, Copy)]
+pub struct ProofOfWorkCheck {
+    difficulty: u8,
+}
+
+impl ProofOfWorkCheck {
+    pub fn new(difficulty: u8) -> Self {
+        Self { difficulty }
+    }
+
+    pub fn is_solution(&self, token: &str, nonce: &str) -> bool {
+        let digest = Sha256::digest(format!("{token}:{nonce}").as_bytes());
+        leading_zero_bits(&digest) >= u32::from(self.difficulty)
+    }
+}
+
+impl AbuseCheck for ProofOfWorkCheck {
+    fn check(&self, attempt: &LikeAttempt<'_>) -> Result<(), Rejection> {
+        let missing = || Rejection::new("missing_proof_of_work", "Please try again");
+
+        let token = attempt.token.ok_or_else(missing)?;
+        let nonce = attempt.proof_of_work.ok_or_else(missing)?;
+
+        if !self.is_solution(token, nonce) {
+            return Err(Rejection::new("invalid_proof_of_work", "Please try again"));
+        }
+
+        Ok(())
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+/// Configuration of the abuse checks, read from the environment
+#[derive(Debug, Clone)]
+pub struct AbuseConfig {
+    pub token_secret: String,
+    pub token_ttl: Duration,
+    pub require_token: bool,
+    pub check_user_agent: bool,
+    pub pow_difficulty: u8,
+}
+
+impl AbuseConfig {
+    pub fn from_env() -> Self {
+        let token_secret = std::env::var("LIKE_TOKEN_SECRET").unwrap_or_else(|_| {
+            warn!("LIKE_TOKEN_SECRET not set - like tokens will not survive a restart");
+            format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4())
+        });
+        let token_ttl = std::env::var("LIKE_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::seconds)
+            .unwrap_or_else(|| Duration::minutes(30));
+        let require_token = env_flag("LIKE_REQUIRE_TOKEN", true);
+        let check_user_agent = env_flag("LIKE_CHECK_USER_AGENT", true);
+        let pow_difficulty = std::env::var("LIKE_POW_DIFFICULTY")
+            .ok()
+            .and_then(|s| s.parse::<u8>().ok())
+            .unwrap_or(0)
+            .min(32);
+
+        Self {
+            token_secret,
+            token_ttl,
+            require_token,
+            check_user_agent,
+            pow_difficulty,
+        }
+    }
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    std::env::var(name)
+        .ok()
+        .and_then(|s| s.parse::<bool>().ok())
+        .unwrap_or(default)
+}
+
+/// Ordered list of checks every like has to pass before it is recorded
+pub struct AbusePipeline {
+    tokens: LikeTokens,
+    pow_difficulty: u8,
+    checks: Vec<Box<dyn AbuseCheck>>,
+}
+
+impl AbusePipeline {
+    /// An empty pipeline - tokens are issued but nothing is enforced
+    pub fn new(tokens: LikeTokens) -> Self {
+        Self {
+            tokens,
+            pow_difficulty: 0,
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn with_check(mut self, check: impl AbuseCheck + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    pub fn with_proof_of_work(mut self, difficulty: u8) -> Self {
+        self.pow_difficulty = difficulty;
+        self.with_check(ProofOfWorkCheck::new(difficulty))
+    }
+
+    pub fn from_config(config: &AbuseConfig) -> Self {
+        let tokens = LikeTokens::new(config.token_secret.as_bytes(), config.token_ttl);
+        let mut pipeline = Self::new(tokens.clone());
+
+        if config.check_user_agent {
+            pipeline = pipeline.with_check(UserAgentCheck);
+        }
+        if config.require_token {
+            pipeline = pipeline.with_check(LikeTokenCheck::new(tokens));
+        }
+        if config.pow_difficulty > 0 {
+            pipeline = pipeline.with_proof_of_work(config.pow_difficulty);
+        }
+
+        info!(
+            checks = pipeline.checks.len(),
+            pow_difficulty = config.pow_difficulty,
+            "Like abuse checks configured"
+        );
+
+        pipeline
+    }
+
+    /// Run every check in order, the first rejection wins
+    pub fn check(&self, attempt: &LikeAttempt<'_>) -> Result<(), Rejection> {
+        self.checks
+            .iter()
+            .try_for_each(|check| check.check(attempt))
+    }
+
+    pub fn issue_token(&self, post_slug: &str, user_ip_hash: &str) -> String {
+        self.tokens.issue(post_slug, user_ip_hash, Utc::now())
+    }
+
+    /// Number of leading zero bits the client has to find, 0 when disabled
+    pub fn pow_difficulty(&self) -> u8 {
+        self.pow_difficulty
+    }
+}
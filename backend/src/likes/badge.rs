@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, histogram};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{instrument, warn};
+
+use super::{cache::LikeCountCache, get_like_counts};
+use crate::og_image::regular_text_width;
+
+/// Label used when the query has none
+pub const DEFAULT_LABEL: &str = "likes";
+/// Longer labels are cut, the badge has to stay a badge
+const MAX_LABEL_CHARS: usize = 32;
+
+const FONT_SIZE: f32 = 11.0;
+/// Space left and right of each text
+const PADDING: f32 = 5.0;
+const LABEL_COLOR: &str = "#555";
+const VALUE_COLOR: &str = "#e05d44";
+
+/// Looks of the badge, named like the shields.io styles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BadgeStyle {
+    #[default]
+    Flat,
+    FlatSquare,
+    Plastic,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BadgeQuery {
+    #[serde(default)]
+    pub style: BadgeStyle,
+    /// Text of the left part, an empty one leaves only the count
+    pub label: Option<String>,
+}
+
+/// Like count of a post as a shields style SVG, `GET /badge/<slug>.svg`
+#[instrument(skip(pool, cache, headers))]
+pub async fn like_badge(
+    Path(file): Path<String>,
+    Query(query): Query<BadgeQuery>,
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    let start_time = std::time::Instant::now();
+    counter!("blog_likes_requests_total", "endpoint" => "badge").increment(1);
+
+    let post_slug = file.strip_suffix(".svg").ok_or(StatusCode::NOT_FOUND)?;
+    let counts = get_like_counts(&pool, &cache, post_slug)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, post_slug, "Database error getting like count for a badge");
+            counter!("blog_likes_errors_total", "reason" => "database_error").increment(1);
+            histogram!("blog_likes_request_duration_ms", "endpoint" => "badge", "status" => "error")
+                .record(start_time.elapsed().as_millis() as f64);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let label = query
+        .label
+        .as_deref()
+        .unwrap_or(DEFAULT_LABEL)
+        .trim()
+        .chars()
+        .take(MAX_LABEL_CHARS)
+        .collect::<String>();
+    let svg = render_badge(&label, &format_count(counts.total), query.style);
+
+    histogram!("blog_likes_request_duration_ms", "endpoint" => "badge", "status" => "success")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    // Unlike `/likes` the body is the same for everyone, so shared caches may keep it
+    let digest = Sha256::digest(&svg);
+    let etag =
+        HeaderValue::from_str(&format!("\"{}\"", hex::encode(&digest[..16]))).expect("valid etag");
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (
+            header::CACHE_CONTROL,
+            HeaderValue::from_str(&format!("public, max-age={}", cache.ttl().as_secs()))
+                .expect("valid header value"),
+        ),
+    ];
+
+    if headers.get(header::IF_NONE_MATCH) == Some(&etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    Ok((
+        cache_headers,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("image/svg+xml;charset=utf-8"),
+        )],
+        svg,
+    )
+        .into_response())
+}
+
+/// Counts as shown on the badge: `999`, `1.2k`, `12k`, `3.4M`
+pub fn format_count(count: i64) -> String {
+    let count = count.max(0);
+    let (scaled, suffix) = match count {
+        0..=999 => return count.to_string(),
+        1_000..=999_999 => (count as f64 / 1_000.0, "k"),
+        _ => (count as f64 / 1_000_000.0, "M"),
+    };
+    // Truncated rather than rounded, 999 999 likes are not a million yet
+    if scaled < 10.0 {
+        let tenths = (scaled * 10.0).floor() / 10.0;
+        format!("{}{suffix}", tenths)
+    } else {
+        format!("{}{suffix}", scaled.floor())
+    }
+}
+
+/// SVG of a badge with `label` on the left and `value` on the right
+pub fn render_badge(label: &str, value: &str, style: BadgeStyle) -> String {
+    let (height, radius, text_y) = match style {
+        BadgeStyle::Flat => (20.0, 3.0, 14.0),
+        BadgeStyle::FlatSquare => (20.0, 0.0, 14.0),
+        BadgeStyle::Plastic => (18.0, 4.0, 13.0),
+    };
+    let label_width = if label.is_empty() {
+        0.0
+    } else {
+        (regular_text_width(FONT_SIZE, label) + 2.0 * PADDING).round()
+    };
+    let value_width = (regular_text_width(FONT_SIZE, value) + 2.0 * PADDING).round();
+    let width = label_width + value_width;
+
+    let title = if label.is_empty() {
+        escape(value)
+    } else {
+        format!("{}: {}", escape(label), escape(value))
+    };
+    let gradient = match style {
+        BadgeStyle::Flat => concat!(
+            r##"<linearGradient id="s" x2="0" y2="100%">"##,
+            r##"<stop offset="0" stop-color="#bbb" stop-opacity=".1"/>"##,
+            r##"<stop offset="1" stop-opacity=".1"/></linearGradient>"##
+        ),
+        BadgeStyle::Plastic => concat!(
+            r##"<linearGradient id="s" x2="0" y2="100%">"##,
+            r##"<stop offset="0" stop-color="#fff" stop-opacity=".7"/>"##,
+            r##"<stop offset=".1" stop-color="#aaa" stop-opacity=".1"/>"##,
+            r##"<stop offset=".9" stop-opacity=".3"/>"##,
+            r##"<stop offset="1" stop-opacity=".5"/></linearGradient>"##
+        ),
+        BadgeStyle::FlatSquare => "",
+    };
+    let shine = if gradient.is_empty() {
+        String::new()
+    } else {
+        format!(r#"<rect width="{width}" height="{height}" fill="url(#s)"/>"#)
+    };
+
+    let mut texts = String::new();
+    for (text, x) in [
+        (label, label_width / 2.0),
+        (value, label_width + value_width / 2.0),
+    ] {
+        if text.is_empty() {
+            continue;
+        }
+        let text = escape(text);
+        // Shadow first, like the shields badges
+        texts.push_str(&format!(
+            r##"<text x="{x}" y="{}" fill="#010101" fill-opacity=".3">{text}</text><text x="{x}" y="{text_y}">{text}</text>"##,
+            text_y + 1.0
+        ));
+    }
+
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" role="img" aria-label="{title}">"#,
+            "<title>{title}</title>{gradient}",
+            r##"<clipPath id="r"><rect width="{width}" height="{height}" rx="{radius}" fill="#fff"/></clipPath>"##,
+            r#"<g clip-path="url(#r)"><rect width="{label_width}" height="{height}" fill="{label_color}"/>"#,
+            r#"<rect x="{label_width}" width="{value_width}" height="{height}" fill="{value_color}"/>{shine}</g>"#,
+            r##"<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="{font_size}">"##,
+            "{texts}</g></svg>"
+        ),
+        width = width,
+        height = height,
+        title = title,
+        gradient = gradient,
+        radius = radius,
+        label_width = label_width,
+        label_color = LABEL_COLOR,
+        value_width = value_width,
+        value_color = VALUE_COLOR,
+        shine = shine,
+        font_size = FONT_SIZE,
+        texts = texts,
+    )
+}
+
+/// Labels come from the query string, they must not break out of the markup
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use metrics::counter;
+
+use super::LikeCounts;
+use crate::events::{BackendEvent, EventSubscriber};
+
+/// Upper bound of cached posts - requests for random slugs must not grow the map forever
+const MAX_ENTRIES: usize = 10_000;
+
+/// In-process TTL cache of like counts sitting in front of the database
+///
+/// Entries are dropped when a like is recorded or the counts are reconciled by any
+/// instance, see the [`EventSubscriber`] impl.
+pub struct LikeCountCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (LikeCounts, Instant)>>,
+}
+
+impl LikeCountCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reads `LIKE_COUNT_CACHE_TTL_SECS`, 0 disables the cache
+    pub fn from_env() -> Self {
+        let ttl = std::env::var("LIKE_COUNT_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(10));
+        Self::new(ttl)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn get(&self, post_slug: &str) -> Option<LikeCounts> {
+        let entries = self.entries.lock().expect("like count cache poisoned");
+        let cached = entries
+            .get(post_slug)
+            .filter(|(_, stored_at)| stored_at.elapsed() < self.ttl)
+            .map(|(counts, _)| counts.clone());
+
+        let result = if cached.is_some() { "hit" } else { "miss" };
+        counter!("blog_like_count_cache_total", "result" => result).increment(1);
+
+        cached
+    }
+
+    pub fn insert(&self, post_slug: &str, counts: LikeCounts) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().expect("like count cache poisoned");
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(post_slug) {
+            entries.retain(|_, (_, stored_at)| stored_at.elapsed() < self.ttl);
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(post_slug.to_string(), (counts, Instant::now()));
+    }
+
+    pub fn invalidate(&self, post_slug: &str) {
+        self.entries
+            .lock()
+            .expect("like count cache poisoned")
+            .remove(post_slug);
+    }
+
+    pub fn invalidate_all(&self) {
+        self.entries
+            .lock()
+            .expect("like count cache poisoned")
+            .clear();
+    }
+}
+
+impl EventSubscriber for Arc<LikeCountCache> {
+    fn name(&self) -> &'static str {
+        "like_count_cache"
+    }
+
+    fn handle(&self, event: &BackendEvent) {
+        match event {
+            BackendEvent::LikeRecorded { post_slug, .. }
+            | BackendEvent::LikeRemoved { post_slug, .. } => self.invalidate(post_slug),
+            BackendEvent::LikeCountsReconciled { post_slugs } => {
+                for post_slug in post_slugs {
+                    self.invalidate(post_slug);
+                }
+            }
+            BackendEvent::PostIngested { .. } => {}
+        }
+    }
+}
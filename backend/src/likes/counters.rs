@@ -0,0 +1,266 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use axum::{extract::State, http::StatusCode, response::Json};
+use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{info, instrument, warn};
+
+use super::cache::LikeCountCache;
+use crate::{
+    events::{BackendEvent, EventBus, EventSubscriber},
+    jobs::{JobOptions, JobRunner, Schedule},
+};
+
+/// Keeps the `blog_post_likes_total` gauge in line with likes recorded by any instance
+pub struct LikeCountGauge;
+
+impl EventSubscriber for LikeCountGauge {
+    fn name(&self) -> &'static str {
+        "like_count_gauge"
+    }
+
+    fn handle(&self, event: &BackendEvent) {
+        if let BackendEvent::LikeRecorded {
+            post_slug,
+            total_likes,
+            ..
+        }
+        | BackendEvent::LikeRemoved {
+            post_slug,
+            total_likes,
+            ..
+        } = event
+        {
+            gauge!("blog_post_likes_total", "post_slug" => post_slug.clone())
+                .set(*total_likes as f64);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReconcileResponse {
+    pub success: bool,
+    pub posts_repaired: u64,
+    pub reaction_counts_repaired: u64,
+    pub source_counts_repaired: u64,
+}
+
+/// Recompute `blog_posts.like_count` from `blog_post_likes` where it drifted
+///
+/// Returns the number of posts whose counter had to be repaired
+#[instrument(skip(pool))]
+pub async fn reconcile_like_counts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    let repaired_posts = sqlx::query_scalar!(
+        r#"
+        UPDATE blog_posts
+        SET like_count = actual.count
+        FROM (
+            SELECT blog_posts.slug, COUNT(blog_post_likes.id) AS count
+            FROM blog_posts
+            LEFT JOIN blog_post_likes ON blog_post_likes.post_slug = blog_posts.slug
+            GROUP BY blog_posts.slug
+        ) AS actual
+        WHERE blog_posts.slug = actual.slug AND blog_posts.like_count <> actual.count
+        RETURNING blog_posts.slug
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let repaired = repaired_posts.len() as u64;
+    publish_reconciled(&mut tx, repaired_posts).await?;
+    tx.commit().await?;
+
+    if repaired > 0 {
+        warn!(
+            posts_repaired = repaired,
+            "Like counters drifted and were repaired"
+        );
+    } else {
+        info!("Like counters are consistent");
+    }
+    counter!("blog_like_counts_repaired_total").increment(repaired);
+    histogram!("blog_database_query_duration_ms", "query" => "reconcile_like_counts")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(repaired)
+}
+
+/// Recompute `blog_post_reaction_counts` from `blog_post_likes` where it drifted
+///
+/// Returns the number of reaction counters that had to be repaired
+#[instrument(skip(pool))]
+pub async fn reconcile_reaction_counts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    let upserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO blog_post_reaction_counts (post_slug, reaction, count)
+        SELECT post_slug, reaction, COUNT(*) FROM blog_post_likes GROUP BY post_slug, reaction
+        ON CONFLICT (post_slug, reaction) DO UPDATE SET count = excluded.count
+        WHERE blog_post_reaction_counts.count <> excluded.count
+        RETURNING post_slug
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let zeroed = sqlx::query_scalar!(
+        r#"
+        UPDATE blog_post_reaction_counts
+        SET count = 0
+        WHERE count <> 0 AND NOT EXISTS (
+            SELECT 1 FROM blog_post_likes
+            WHERE blog_post_likes.post_slug = blog_post_reaction_counts.post_slug
+                AND blog_post_likes.reaction = blog_post_reaction_counts.reaction
+        )
+        RETURNING post_slug
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let repaired = (upserted.len() + zeroed.len()) as u64;
+    publish_reconciled(&mut tx, upserted.into_iter().chain(zeroed)).await?;
+    tx.commit().await?;
+
+    if repaired > 0 {
+        warn!(
+            reaction_counts_repaired = repaired,
+            "Reaction counters drifted and were repaired"
+        );
+    }
+    counter!("blog_reaction_counts_repaired_total").increment(repaired);
+    histogram!("blog_database_query_duration_ms", "query" => "reconcile_reaction_counts")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(repaired)
+}
+
+/// Recompute `blog_post_source_counts` from `blog_post_likes` where it drifted
+///
+/// Returns the number of source counters that had to be repaired
+#[instrument(skip(pool))]
+pub async fn reconcile_source_counts(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    let upserted = sqlx::query_scalar!(
+        r#"
+        INSERT INTO blog_post_source_counts (post_slug, source, count)
+        SELECT post_slug, source, COUNT(*) FROM blog_post_likes GROUP BY post_slug, source
+        ON CONFLICT (post_slug, source) DO UPDATE SET count = excluded.count
+        WHERE blog_post_source_counts.count <> excluded.count
+        RETURNING post_slug
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let zeroed = sqlx::query_scalar!(
+        r#"
+        UPDATE blog_post_source_counts
+        SET count = 0
+        WHERE count <> 0 AND NOT EXISTS (
+            SELECT 1 FROM blog_post_likes
+            WHERE blog_post_likes.post_slug = blog_post_source_counts.post_slug
+                AND blog_post_likes.source = blog_post_source_counts.source
+        )
+        RETURNING post_slug
+        "#
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let repaired = (upserted.len() + zeroed.len()) as u64;
+    publish_reconciled(&mut tx, upserted.into_iter().chain(zeroed)).await?;
+    tx.commit().await?;
+
+    if repaired > 0 {
+        warn!(
+            source_counts_repaired = repaired,
+            "Source counters drifted and were repaired"
+        );
+    }
+    counter!("blog_source_counts_repaired_total").increment(repaired);
+    histogram!("blog_database_query_duration_ms", "query" => "reconcile_source_counts")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(repaired)
+}
+
+/// Let every instance drop the cached counts of the repaired posts once `tx` commits
+async fn publish_reconciled(
+    tx: &mut Transaction<'_, Postgres>,
+    post_slugs: impl IntoIterator<Item = String>,
+) -> Result<(), sqlx::Error> {
+    let post_slugs: BTreeSet<_> = post_slugs.into_iter().collect();
+    if post_slugs.is_empty() {
+        return Ok(());
+    }
+    let event = BackendEvent::LikeCountsReconciled {
+        post_slugs: post_slugs.into_iter().collect(),
+    };
+    EventBus::publish(&mut **tx, &event).await
+}
+
+/// Run [`reconcile_like_counts`], [`reconcile_reaction_counts`] and
+/// [`reconcile_source_counts`] as the `like_counts_reconcile` job on `schedule`
+pub fn schedule_reconcile(jobs: &mut JobRunner, pool: PgPool, schedule: Schedule) {
+    info!(?schedule, "Scheduling like counter reconciliation job");
+
+    jobs.register("like_counts_reconcile", JobOptions::default(), move |_| {
+        let pool = pool.clone();
+        async move {
+            async {
+                reconcile_like_counts(&pool).await?;
+                reconcile_reaction_counts(&pool).await?;
+                reconcile_source_counts(&pool).await
+            }
+            .await
+            .inspect_err(|_| counter!("blog_like_counts_reconcile_errors_total").increment(1))?;
+            Ok(())
+        }
+    });
+    jobs.schedule("like_counts_reconcile", schedule);
+}
+
+/// Manual trigger of the reconciliation from the admin API
+#[instrument(skip(pool, cache))]
+pub async fn reconcile_now(
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+) -> Result<Json<ReconcileResponse>, StatusCode> {
+    let reconcile_error = |e: sqlx::Error| {
+        warn!(error = %e, "Manual like counter reconciliation failed");
+        counter!("blog_like_counts_reconcile_errors_total").increment(1);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let posts_repaired = reconcile_like_counts(&pool)
+        .await
+        .map_err(reconcile_error)?;
+    let reaction_counts_repaired = reconcile_reaction_counts(&pool)
+        .await
+        .map_err(reconcile_error)?;
+    let source_counts_repaired = reconcile_source_counts(&pool)
+        .await
+        .map_err(reconcile_error)?;
+
+    // The events invalidate the repaired posts too, but only once they made the round trip
+    // through Postgres, the caller should see the repaired counts right away
+    if posts_repaired + reaction_counts_repaired + source_counts_repaired > 0 {
+        cache.invalidate_all();
+    }
+
+    Ok(Json(ReconcileResponse {
+        success: true,
+        posts_repaired,
+        reaction_counts_repaired,
+        source_counts_repaired,
+    }))
+}
@@ -0,0 +1,65 @@
+use tracing::warn;
+
+/// Used when `LIKE_REACTIONS` is not set, `heart` is what `/like` always recorded
+pub const DEFAULT_REACTIONS: &str = "heart,rocket,thinking,learned-something";
+
+/// Longest reaction name the `reaction` column can hold
+const MAX_REACTION_LEN: usize = 32;
+
+/// Kinds of reactions visitors can leave on a post
+///
+/// The first one is the default reaction recorded by `POST /like/:post_slug`
+#[derive(Debug, Clone)]
+pub struct Reactions {
+    kinds: Vec<String>,
+}
+
+impl Reactions {
+    /// Invalid names (anything but lowercase letters, digits and `-`) are dropped
+    pub fn new<I, S>(kinds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut valid: Vec<String> = Vec::new();
+        for kind in kinds {
+            let kind = kind.as_ref().trim();
+            if kind.is_empty() || valid.iter().any(|k| k == kind) {
+                continue;
+            }
+            if is_reaction_name(kind) {
+                valid.push(kind.to_string());
+            } else {
+                warn!(reaction = kind, "Ignoring invalid reaction name");
+            }
+        }
+
+        if valid.is_empty() {
+            warn!("No valid reactions configured, using the defaults");
+            return Self::new(DEFAULT_REACTIONS.split(','));
+        }
+        Self { kinds: valid }
+    }
+
+    /// Reads the comma separated `LIKE_REACTIONS`
+    pub fn from_env() -> Self {
+        let kinds =
+            std::env::var("LIKE_REACTIONS").unwrap_or_else(|_| DEFAULT_REACTIONS.to_string());
+        Self::new(kinds.split(','))
+    }
+
+    pub fn default_reaction(&self) -> &str {
+        &self.kinds[0]
+    }
+
+    pub fn is_allowed(&self, reaction: &str) -> bool {
+        self.kinds.iter().any(|kind| kind == reaction)
+    }
+}
+
+fn is_reaction_name(name: &str) -> bool {
+    name.len() <= MAX_REACTION_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use metrics::{counter, histogram};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::{fetch_reaction_counts, LikeCounts};
+use crate::events::{BackendEvent, EventBus};
+
+/// Reaction of a fediverse favourite (`Like` activity)
+pub const FEDIVERSE_FAVOURITE: &str = "favourite";
+/// Reaction of a fediverse boost (`Announce` activity)
+pub const FEDIVERSE_BOOST: &str = "boost";
+
+/// Where a like came from, stored in `blog_post_likes.source`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LikeSource {
+    /// Liked on the blog itself
+    Web,
+    /// Favourite or boost of the federated post
+    Fediverse,
+}
+
+impl LikeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LikeSource::Web => "web",
+            LikeSource::Fediverse => "fediverse",
+        }
+    }
+}
+
+pub(crate) async fn fetch_source_counts<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    post_slug: &str,
+) -> Result<BTreeMap<String, i64>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT source, count FROM blog_post_source_counts WHERE post_slug = $1 AND count > 0",
+        post_slug
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.source, row.count))
+        .collect())
+}
+
+/// Record a favourite or boost of `remote_actor` and bump the counters, like
+/// [`super::like_post`] does for likes on the blog
+///
+/// Returns `None` when the actor already left this reaction, servers deliver the
+/// same activity more than once.
+#[instrument(skip(pool))]
+pub async fn record_fediverse_like(
+    pool: &PgPool,
+    post_slug: &str,
+    reaction: &str,
+    remote_actor: &str,
+) -> Result<Option<LikeCounts>, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+    let hour_bucket = Utc::now().format("%Y-%m-%d %H").to_string();
+
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO blog_post_likes (post_slug, reaction, source, remote_actor, liked_at, hour_bucket)
+        VALUES ($1, $2, $3, $4, NOW(), $5)
+        ON CONFLICT (post_slug, reaction, remote_actor) WHERE remote_actor IS NOT NULL DO NOTHING
+        "#,
+        post_slug,
+        reaction,
+        LikeSource::Fediverse.as_str(),
+        remote_actor,
+        hour_bucket
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if inserted == 0 {
+        tx.commit().await?;
+        return Ok(None);
+    }
+
+    let counts = update_counters(&mut tx, post_slug, reaction, 1).await?;
+    let event = BackendEvent::LikeRecorded {
+        post_slug: post_slug.to_string(),
+        reaction: reaction.to_string(),
+        total_likes: counts.total,
+        reaction_count: counts.reactions.get(reaction).copied().unwrap_or_default(),
+    };
+    EventBus::publish(&mut *tx, &event).await?;
+    crate::webhooks::like_recorded(&mut tx, post_slug, counts.total).await?;
+    tx.commit().await?;
+
+    counter!("blog_likes_successful_total", "reaction" => reaction.to_string()).increment(1);
+    histogram!("blog_database_query_duration_ms", "query" => "record_fediverse_like")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(Some(counts))
+}
+
+/// Remove an undone favourite or boost, `None` when it was never recorded
+#[instrument(skip(pool))]
+pub async fn remove_fediverse_like(
+    pool: &PgPool,
+    post_slug: &str,
+    reaction: &str,
+    remote_actor: &str,
+) -> Result<Option<LikeCounts>, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM blog_post_likes
+        WHERE post_slug = $1 AND reaction = $2 AND remote_actor = $3
+        "#,
+        post_slug,
+        reaction,
+        remote_actor
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if deleted == 0 {
+        tx.commit().await?;
+        return Ok(None);
+    }
+
+    let counts = update_counters(&mut tx, post_slug, reaction, -1).await?;
+    let event = BackendEvent::LikeRemoved {
+        post_slug: post_slug.to_string(),
+        reaction: reaction.to_string(),
+        total_likes: counts.total,
+        reaction_count: counts.reactions.get(reaction).copied().unwrap_or_default(),
+    };
+    EventBus::publish(&mut *tx, &event).await?;
+    tx.commit().await?;
+
+    counter!("blog_likes_removed_total", "reaction" => reaction.to_string()).increment(1);
+    histogram!("blog_database_query_duration_ms", "query" => "remove_fediverse_like")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(Some(counts))
+}
+
+/// Move the post, reaction and fediverse counters by `delta`, returns the new counts
+async fn update_counters(
+    tx: &mut sqlx::PgConnection,
+    post_slug: &str,
+    reaction: &str,
+    delta: i64,
+) -> Result<LikeCounts, sqlx::Error> {
+    let total = sqlx::query_scalar!(
+        "UPDATE blog_posts SET like_count = GREATEST(like_count + $2, 0) WHERE slug = $1 RETURNING like_count",
+        post_slug,
+        delta
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO blog_post_reaction_counts (post_slug, reaction, count)
+        VALUES ($1, $2, GREATEST($3::BIGINT, 0))
+        ON CONFLICT (post_slug, reaction)
+        DO UPDATE SET count = GREATEST(blog_post_reaction_counts.count + $3, 0)
+        "#,
+        post_slug,
+        reaction,
+        delta
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    bump_source_count(&mut *tx, post_slug, LikeSource::Fediverse, delta).await?;
+
+    Ok(LikeCounts {
+        total,
+        reactions: fetch_reaction_counts(&mut *tx, post_slug).await?,
+        sources: fetch_source_counts(&mut *tx, post_slug).await?,
+    })
+}
+
+pub(crate) async fn bump_source_count<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    post_slug: &str,
+    source: LikeSource,
+    delta: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO blog_post_source_counts (post_slug, source, count)
+        VALUES ($1, $2, GREATEST($3::BIGINT, 0))
+        ON CONFLICT (post_slug, source)
+        DO UPDATE SET count = GREATEST(blog_post_source_counts.count + $3, 0)
+        "#,
+        post_slug,
+        source.as_str(),
+        delta
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use metrics::{counter, gauge};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tracing::{instrument, warn};
+
+use super::{cache::LikeCountCache, get_like_counts};
+use crate::events::{BackendEvent, EventBus};
+
+/// Payload of the `likes` events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LikeCountChanged {
+    pub post_slug: String,
+    pub total_likes: i64,
+    pub reactions: BTreeMap<String, i64>,
+}
+
+/// Keeps the gauge of open streams accurate, dropped together with the stream
+struct OpenStream;
+
+impl OpenStream {
+    fn new() -> Self {
+        gauge!("blog_like_streams_open").increment(1);
+        Self
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        gauge!("blog_like_streams_open").decrement(1);
+    }
+}
+
+/// Server-Sent Events stream of the like count of a post
+///
+/// Sends the current count right away and then every change as a `likes` event
+#[instrument(skip(pool, cache, events))]
+pub async fn stream_likes(
+    Path(post_slug): Path<String>,
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+    State(events): State<EventBus>,
+) -> Result<Response, StatusCode> {
+    counter!("blog_likes_requests_total", "endpoint" => "stream_likes").increment(1);
+
+    // Subscribe before reading the count so no like falls in between
+    let changes = BroadcastStream::new(events.subscribe());
+    let counts = get_like_counts(&pool, &cache, &post_slug)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, post_slug = %post_slug, "Database error getting like count");
+            counter!("blog_likes_errors_total", "reason" => "database_error").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let mut current = LikeCountChanged {
+        post_slug: post_slug.clone(),
+        total_likes: counts.total,
+        reactions: counts.reactions,
+    };
+    let initial = current.clone();
+    let open_stream = OpenStream::new();
+    let changes = changes.filter_map(move |event| {
+        let _open_stream = &open_stream;
+        // Events carry the count of a single reaction, the others are kept from before.
+        // Lagging subscribers skip the missed changes, the next one fixes the counts.
+        match event {
+            Ok(
+                BackendEvent::LikeRecorded {
+                    post_slug,
+                    reaction,
+                    total_likes,
+                    reaction_count,
+                }
+                | BackendEvent::LikeRemoved {
+                    post_slug,
+                    reaction,
+                    total_likes,
+                    reaction_count,
+                },
+            ) if post_slug == current.post_slug => {
+                current.total_likes = total_likes;
+                current.reactions.insert(reaction, reaction_count);
+                Some(current.clone())
+            }
+            _ => None,
+        }
+    });
+
+    let stream = tokio_stream::once(initial)
+        .chain(changes)
+        .map(|change| likes_event(&change));
+
+    let mut response = Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
+        .into_response();
+    // Otherwise nginx buffers the events
+    response.headers_mut().insert(
+        HeaderName::from_static("x-accel-buffering"),
+        HeaderValue::from_static("no"),
+    );
+    Ok(response)
+}
+
+fn likes_event(change: &LikeCountChanged) -> Result<Event, Infallible> {
+    Ok(Event::default()
+        .event("likes")
+        .json_data(change)
+        .expect("like count serializes"))
+}
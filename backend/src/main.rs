@@ -1,7 +1,7 @@
 use crate::hugo_posts::HugoBlogPost;
 use error::Error;
 use sqlx::PgPool;
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
     middleware,
@@ -11,12 +11,30 @@ use axum::{
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing::{info, instrument};
 
+mod activitypub;
+mod admin;
+mod analytics;
+mod bluesky;
 mod correlation;
 mod database;
 mod error;
+mod events;
 mod hugo_posts;
+mod jobs;
 mod likes;
 mod observability;
+mod og_image;
+mod plausible_import;
+mod privacy;
+mod referrers;
+mod retention;
+mod search;
+mod state;
+mod subscriptions;
+mod syndication;
+mod trusted_proxies;
+mod views;
+mod webhooks;
 
 #[tokio::main]
 #[instrument]
@@ -42,9 +60,45 @@ async fn main() -> Result<(), Error> {
     sqlx::migrate!().run(&pool).await?;
     info!("Migrations run");
 
+    if std::env::args().nth(1).as_deref() == Some("import-plausible") {
+        let export_path = std::env::args()
+            .nth(2)
+            .expect("Usage: backend import-plausible <export.zip>");
+        plausible_import::run(&pool, &export_path).await?;
+        observability::shutdown_observability();
+        return Ok(());
+    }
+
+    // Started before ingesting the posts so their events reach the subscribers
+    let events = events::EventBus::start(pool.clone()).await?;
+    let like_counts = Arc::new(likes::cache::LikeCountCache::from_env());
+    events.register(like_counts.clone());
+    events.register(likes::counters::LikeCountGauge);
+
+    let newsletter = Arc::new(subscriptions::Newsletter::from_env()?);
+    let digest_delay = std::env::var("NEWSLETTER_DIGEST_DELAY_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(std::time::Duration::from_secs)
+        .unwrap_or_else(|| std::time::Duration::from_secs(60));
+    events.register(subscriptions::digest::DigestTrigger::new(
+        pool.clone(),
+        digest_delay,
+    ));
+
+    let federation = Arc::new(activitypub::Federation::from_env(&pool).await?);
+    let bluesky = bluesky::BlueskyConfig::from_env()?
+        .map(bluesky::BlueskyClient::new)
+        .transpose()?
+        .map(Arc::new);
+
     let posts_path = std::env::args().nth(1).expect("No posts file given");
     let blog_repo = hugo_posts::BlogRepository { db: pool.clone() };
-    let blog_posts = HugoBlogPost::load_new_posts(posts_path).expect("Failed to load blog posts");
+    let validation = hugo_posts::validation::PostValidation::from_env()?;
+    let blog_posts = HugoBlogPost::load_new_posts(posts_path, &validation).map_err(|e| {
+        tracing::error!("Failed to load blog posts: {}", e);
+        e
+    })?;
 
     for blog_post in blog_posts {
         info!("Processing: {}", blog_post.slug);
@@ -53,12 +107,83 @@ async fn main() -> Result<(), Error> {
 
     info!("Blog posts processed successfully");
 
+    let trusted_proxies = Arc::new(trusted_proxies::TrustedProxies::from_env()?);
+    let abuse_config = likes::abuse::AbuseConfig::from_env();
+    let retention_config = retention::RetentionConfig::from_env();
+
+    let mut jobs = jobs::JobRunner::new(pool.clone(), jobs::JobsConfig::from_env());
+    retention::schedule_retention(
+        &mut jobs,
+        pool.clone(),
+        retention_config.clone(),
+        jobs::Schedule::from_env("LIKES_RETENTION_SCHEDULE", retention_config.interval),
+    );
+
+    let reconcile_interval = std::env::var("LIKE_COUNT_RECONCILE_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map(std::time::Duration::from_secs)
+        .unwrap_or_else(|| std::time::Duration::from_secs(60 * 60));
+    likes::counters::schedule_reconcile(
+        &mut jobs,
+        pool.clone(),
+        jobs::Schedule::from_env("LIKE_COUNT_RECONCILE_SCHEDULE", reconcile_interval),
+    );
+    webhooks::WebhookSender::from_env(pool.clone())?.register(&mut jobs);
+    syndication::Syndicator::from_env(pool.clone(), federation.clone(), bluesky)
+        .register(&mut jobs);
+    subscriptions::digest::register(&mut jobs, pool.clone(), newsletter.clone());
+    let og_images = Arc::new(og_image::OgImages::from_env());
+    og_image::schedule_cache_prune(&mut jobs, og_images.clone());
+    let jobs = jobs.start();
+
+    let state = state::AppState {
+        pool,
+        abuse: Arc::new(likes::abuse::AbusePipeline::from_config(&abuse_config)),
+        like_counts,
+        reactions: Arc::new(likes::reactions::Reactions::from_env()),
+        events,
+        retention: retention_config,
+        visitors: Arc::new(views::VisitorHasher::from_env()),
+        analytics: Arc::new(analytics::AnalyticsConfig::from_env()),
+        newsletter,
+        federation,
+        og_images,
+    };
+
     // Create the Axum app with routes and middleware
     let app = Router::new()
         .route("/like/:post_slug", post(likes::like_post))
+        .route("/react/:post_slug/:reaction", post(likes::like_post))
         .route("/likes/:post_slug", get(likes::get_likes))
+        .route("/likes/:post_slug/token", get(likes::get_like_token))
+        .route("/likes/:post_slug/stream", get(likes::stream::stream_likes))
+        .route("/views/:post_slug", post(views::record_view))
+        .route("/search", get(search::search_posts))
+        .route("/badge/:file", get(likes::badge::like_badge))
+        .route("/og/:file", get(og_image::og_image))
+        .route("/api/event", post(analytics::ingest_event))
+        .route("/subscribe", post(subscriptions::subscribe))
+        .route("/subscribe/confirm", get(subscriptions::confirm))
+        .route(
+            "/unsubscribe",
+            get(subscriptions::unsubscribe).post(subscriptions::unsubscribe),
+        )
+        .route(
+            "/privacy/likes",
+            get(privacy::export_my_likes).delete(privacy::erase_my_likes),
+        )
+        .route("/.well-known/webfinger", get(activitypub::webfinger))
+        .route("/activitypub/actor", get(activitypub::actor))
+        .route("/activitypub/inbox", post(activitypub::inbox))
+        .route("/activitypub/outbox", get(activitypub::outbox))
+        .route("/activitypub/followers", get(activitypub::followers))
+        .route("/activitypub/posts/:slug", get(activitypub::post_article))
         .route("/health", get(health_check))
-        .layer(middleware::from_fn(correlation::correlation_middleware))
+        .layer(middleware::from_fn_with_state(
+            trusted_proxies.clone(),
+            correlation::correlation_middleware,
+        ))
         .layer(CorsLayer::permissive()) // Allow CORS for frontend
         .layer(
             TraceLayer::new_for_http()
@@ -87,7 +212,16 @@ async fn main() -> Result<(), Error> {
                     },
                 ),
         )
-        .with_state(pool);
+        .with_state(state.clone());
+
+    // Admin API is only reachable from the machine itself
+    let admin_app = admin::router()
+        .layer(middleware::from_fn_with_state(
+            trusted_proxies,
+            correlation::correlation_middleware,
+        ))
+        .layer(TraceLayer::new_for_http())
+        .with_state(state);
 
     // Create separate metrics server without any tracing instrumentation
     let metrics_app = prometheus_handle
@@ -102,7 +236,22 @@ async fn main() -> Result<(), Error> {
 
     // Setup graceful shutdown
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    let server = axum::serve(listener, app).with_graceful_shutdown(shutdown_signal());
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal());
+
+    let admin_address =
+        std::env::var("ADMIN_BIND_ADDRESS").unwrap_or_else(|_| "127.0.0.1:9091".to_string());
+    let admin_addr: SocketAddr = admin_address.parse().expect("Invalid admin bind address");
+    let admin_listener = tokio::net::TcpListener::bind(admin_addr).await?;
+    info!("Starting admin server on {}", admin_addr);
+    let admin_server = axum::serve(
+        admin_listener,
+        admin_app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal());
 
     // Start metrics server if available
     let metrics_server = if let Some(metrics_app) = metrics_app {
@@ -114,15 +263,21 @@ async fn main() -> Result<(), Error> {
         None
     };
 
-    // Run both servers concurrently
-    if let Some(metrics_server) = metrics_server {
-        if let Err(e) = tokio::try_join!(server, metrics_server) {
-            tracing::error!("Server error: {}", e);
+    let metrics_server = async move {
+        match metrics_server {
+            Some(metrics_server) => metrics_server.await,
+            None => Ok(()),
         }
-    } else if let Err(e) = server.await {
+    };
+
+    // Run all servers concurrently
+    if let Err(e) = tokio::try_join!(server, admin_server, metrics_server) {
         tracing::error!("Server error: {}", e);
     }
 
+    // The servers stopped on the shutdown signal, let the running jobs finish
+    jobs.shutdown().await;
+
     // Shutdown observability providers
     observability::shutdown_observability();
 
@@ -0,0 +1,482 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    jobs::{JobOptions, JobRunner, Schedule},
+    likes::badge::format_count,
+};
+
+/// Size recommended for Open Graph images
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+/// Bumped when the layout changes, so cached cards are rendered again
+const RENDER_VERSION: u32 = 1;
+
+static REGULAR: LazyLock<FontRef<'static>> = LazyLock::new(|| {
+    FontRef::try_from_slice(include_bytes!("../assets/fonts/DejaVuSans.ttf"))
+        .expect("bundled font parses")
+});
+static BOLD: LazyLock<FontRef<'static>> = LazyLock::new(|| {
+    FontRef::try_from_slice(include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf"))
+        .expect("bundled font parses")
+});
+
+const BACKGROUND: [u8; 3] = [0x21, 0x21, 0x21];
+const ACCENT: [u8; 3] = [0x42, 0xa5, 0xf5];
+const TEXT: [u8; 3] = [0xfa, 0xfa, 0xfa];
+const MUTED: [u8; 3] = [0xb0, 0xb0, 0xb0];
+
+const MARGIN: f32 = 80.0;
+const TITLE_SIZE: f32 = 68.0;
+const TITLE_MAX_LINES: usize = 4;
+
+/// What a card shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardData {
+    pub title: String,
+    pub date: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub likes: i64,
+}
+
+impl CardData {
+    /// Hex SHA-256 of everything drawn on the card, names its file in the cache
+    pub fn content_hash(&self, brand: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(RENDER_VERSION.to_be_bytes());
+        for part in [brand, &self.title, &self.date.to_rfc3339()] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        for tag in &self.tags {
+            hasher.update(tag.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(likes_label(self.likes).as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Like count as drawn on the card: exact below 10, then rounded down to 1, 2 or 5
+/// times a power of ten, `50+ likes`, `1k+ likes`
+///
+/// Every change of the label renders a new card and busts the caches of the sites
+/// showing it, so it must not change with every like.
+pub fn likes_label(likes: i64) -> String {
+    match likes.max(0) {
+        1 => "\u{2665} 1 like".to_string(),
+        n @ 0..=9 => format!("\u{2665} {n} likes"),
+        n => {
+            let mut power = 10;
+            while power * 10 <= n {
+                power *= 10;
+            }
+            let step = [5, 2, 1]
+                .into_iter()
+                .map(|m| m * power)
+                .find(|&step| step <= n)
+                .unwrap_or(power);
+            format!("\u{2665} {}+ likes", format_count(step))
+        }
+    }
+}
+
+/// Renders the social cards and keeps them on disk
+pub struct OgImages {
+    /// Rendered cards, named by their content hash
+    pub cache_dir: PathBuf,
+    /// Shown in the top left corner of every card
+    pub brand: String,
+    /// Cards not read for this long are deleted from the cache
+    pub max_age: Duration,
+}
+
+impl OgImages {
+    /// Reads `OG_IMAGE_CACHE_DIR`, `OG_IMAGE_BRAND` and `OG_IMAGE_CACHE_DAYS`
+    pub fn from_env() -> Self {
+        let cache_dir = std::env::var("OG_IMAGE_CACHE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("blog-og-images"));
+        let brand = std::env::var("OG_IMAGE_BRAND").unwrap_or_else(|_| "flakm.com".to_string());
+        let max_age = std::env::var("OG_IMAGE_CACHE_DAYS")
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60))
+            .unwrap_or_else(|| Duration::from_secs(7 * 24 * 60 * 60));
+
+        Self {
+            cache_dir,
+            brand,
+            max_age,
+        }
+    }
+
+    /// PNG of the card from the cache, rendered and stored on a miss
+    async fn card(&self, card: CardData, hash: &str) -> std::io::Result<Vec<u8>> {
+        let path = self.cache_dir.join(format!("{hash}.png"));
+        match tokio::fs::read(&path).await {
+            Ok(png) => {
+                counter!("blog_og_images_total", "cache" => "hit").increment(1);
+                // Keeps the card from being pruned while it is requested
+                let _ = std::fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|f| f.set_modified(std::time::SystemTime::now()));
+                return Ok(png);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let start_time = std::time::Instant::now();
+        let brand = self.brand.clone();
+        let png = tokio::task::spawn_blocking(move || render_card(&card, &brand))
+            .await
+            .map_err(std::io::Error::other)?;
+        histogram!("blog_og_image_render_duration_ms")
+            .record(start_time.elapsed().as_millis() as f64);
+        counter!("blog_og_images_total", "cache" => "miss").increment(1);
+
+        // Written under a temporary name so concurrent requests never read half a file
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        let partial = self
+            .cache_dir
+            .join(format!("{hash}.{}.tmp", uuid::Uuid::new_v4()));
+        tokio::fs::write(&partial, &png).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(png)
+    }
+
+    /// Delete cards that were not read for [`Self::max_age`], returns how many
+    pub fn prune_cache(&self) -> std::io::Result<u64> {
+        let entries = match std::fs::read_dir(&self.cache_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let entry = entry?;
+            let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+            if age > self.max_age {
+                std::fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Delete stale cards as the daily `og_image_cache_prune` job
+pub fn schedule_cache_prune(jobs: &mut JobRunner, og_images: Arc<OgImages>) {
+    jobs.register("og_image_cache_prune", JobOptions::default(), move |_| {
+        let og_images = og_images.clone();
+        async move {
+            let removed = tokio::task::spawn_blocking(move || og_images.prune_cache()).await??;
+            info!(removed, "Social card cache pruned");
+            Ok(())
+        }
+    });
+    jobs.schedule(
+        "og_image_cache_prune",
+        Schedule::Every(Duration::from_secs(24 * 60 * 60)),
+    );
+}
+
+/// Social card of a post for `og:image`, `GET /og/<slug>.png`
+#[instrument(skip(pool, og_images, headers))]
+pub async fn og_image(
+    Path(file): Path<String>,
+    State(pool): State<PgPool>,
+    State(og_images): State<Arc<OgImages>>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(post_slug) = file.strip_suffix(".png") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let card = match load_card(&pool, post_slug).await {
+        Ok(Some(card)) => card,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            warn!(error = %e, post_slug, "Database error loading a social card");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let hash = card.content_hash(&og_images.brand);
+    let etag = HeaderValue::from_str(&format!("\"{}\"", &hash[..32])).expect("valid etag");
+    let cache_control = HeaderValue::from_static("public, max-age=3600");
+    if headers.get(header::IF_NONE_MATCH) == Some(&etag) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response();
+    }
+
+    match og_images.card(card, &hash).await {
+        Ok(png) => (
+            [
+                (header::CONTENT_TYPE, HeaderValue::from_static("image/png")),
+                (header::ETAG, etag),
+                (header::CACHE_CONTROL, cache_control),
+            ],
+            png,
+        )
+            .into_response(),
+        Err(e) => {
+            warn!(error = %e, post_slug, "Failed to render a social card");
+            counter!("blog_og_image_errors_total").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn load_card(pool: &PgPool, post_slug: &str) -> Result<Option<CardData>, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let row = sqlx::query!(
+        "SELECT title, date, tags, like_count FROM blog_posts WHERE slug = $1 AND NOT draft",
+        post_slug
+    )
+    .fetch_optional(pool)
+    .await?;
+    histogram!("blog_database_query_duration_ms", "query" => "load_card")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(row.map(|row| CardData {
+        title: row.title,
+        date: row.date,
+        tags: row
+            .tags
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+        likes: row.like_count,
+    }))
+}
+
+/// PNG of the card: the brand, the title and a line with the date, likes and tags
+pub fn render_card(card: &CardData, brand: &str) -> Vec<u8> {
+    let mut canvas = Canvas::new(WIDTH, HEIGHT, BACKGROUND);
+    canvas.fill_rect(0, 0, 16, HEIGHT, ACCENT);
+
+    canvas.draw_text(&BOLD, 40.0, MARGIN, MARGIN + 30.0, brand, ACCENT);
+
+    let max_width = WIDTH as f32 - 2.0 * MARGIN;
+    let lines = wrap_text(&BOLD, TITLE_SIZE, &card.title, max_width, TITLE_MAX_LINES);
+    let line_height = TITLE_SIZE * 1.2;
+    let mut baseline = MARGIN + 150.0;
+    for line in &lines {
+        canvas.draw_text(&BOLD, TITLE_SIZE, MARGIN, baseline, line, TEXT);
+        baseline += line_height;
+    }
+
+    let footer = format!(
+        "{}   {}",
+        card.date.format("%B %-d, %Y"),
+        likes_label(card.likes)
+    );
+    let footer_baseline = HEIGHT as f32 - MARGIN;
+    let footer_width = canvas.draw_text(&REGULAR, 32.0, MARGIN, footer_baseline, &footer, MUTED);
+
+    // Tags fill the rest of the footer line, as many as fit
+    let tags_start = MARGIN + footer_width + 48.0;
+    let tags = card
+        .tags
+        .iter()
+        .map(|tag| format!("#{}", tag.replace(' ', "")))
+        .collect::<Vec<_>>();
+    let mut shown = String::new();
+    for tag in tags {
+        let candidate = if shown.is_empty() {
+            tag
+        } else {
+            format!("{shown}  {tag}")
+        };
+        if tags_start + text_width(&REGULAR, 32.0, &candidate) > WIDTH as f32 - MARGIN {
+            break;
+        }
+        shown = candidate;
+    }
+    canvas.draw_text(&REGULAR, 32.0, tags_start, footer_baseline, &shown, ACCENT);
+
+    canvas.encode_png()
+}
+
+/// Width of `text` in pixels
+pub fn text_width(font: &FontRef<'_>, size: f32, text: &str) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    width
+}
+
+/// Width of `text` in the regular font, for layouts outside of the cards
+pub fn regular_text_width(size: f32, text: &str) -> f32 {
+    text_width(&REGULAR, size, text)
+}
+
+/// Break `text` into lines no wider than `max_width` at spaces, words that do not
+/// fit on a line are broken anywhere. Text beyond `max_lines` is cut with an ellipsis.
+pub fn wrap_text(
+    font: &FontRef<'_>,
+    size: f32,
+    text: &str,
+    max_width: f32,
+    max_lines: usize,
+) -> Vec<String> {
+    let fits = |line: &str| text_width(font, size, line) <= max_width;
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{line} {word}")
+        };
+        if fits(&candidate) {
+            line = candidate;
+            continue;
+        }
+        if !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+        }
+        for c in word.chars() {
+            line.push(c);
+            if !fits(&line) {
+                line.pop();
+                lines.push(std::mem::replace(&mut line, c.to_string()));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = lines.last_mut().expect("max_lines is not zero");
+        while !last.is_empty() && !fits(&format!("{last}\u{2026}")) {
+            last.pop();
+        }
+        *last = format!("{}\u{2026}", last.trim_end());
+    }
+    lines
+}
+
+/// RGB image the card is drawn on
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: [u8; 3]) -> Self {
+        let pixels = background.repeat((width * height) as usize);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Mix `color` into the pixel with `coverage` between 0 and 1
+    fn blend(&mut self, x: i64, y: i64, color: [u8; 3], coverage: f32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let offset = (y as usize * self.width as usize + x as usize) * 3;
+        let coverage = coverage.clamp(0.0, 1.0);
+        for (channel, value) in self.pixels[offset..offset + 3].iter_mut().zip(color) {
+            *channel = (*channel as f32 * (1.0 - coverage) + value as f32 * coverage).round() as u8;
+        }
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 3]) {
+        for py in y..(y + height).min(self.height) {
+            for px in x..(x + width).min(self.width) {
+                self.blend(px as i64, py as i64, color, 1.0);
+            }
+        }
+    }
+
+    /// Draw `text` with its baseline at `y`, returns its width
+    fn draw_text(
+        &mut self,
+        font: &FontRef<'_>,
+        size: f32,
+        x: f32,
+        y: f32,
+        text: &str,
+        color: [u8; 3],
+    ) -> f32 {
+        let scale = PxScale::from(size);
+        let scaled = font.as_scaled(scale);
+        let mut caret = x;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(scale, point(caret, y));
+            if let Some(outlined) = font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    self.blend(
+                        bounds.min.x as i64 + gx as i64,
+                        bounds.min.y as i64 + gy as i64,
+                        color,
+                        coverage,
+                    );
+                });
+            }
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+        }
+        caret - x
+    }
+
+    fn encode_png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_compression(png::Compression::Best);
+        let mut writer = encoder.write_header().expect("writing to a Vec");
+        writer
+            .write_image_data(&self.pixels)
+            .expect("pixels match the size");
+        writer.finish().expect("writing to a Vec");
+        png
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Seek},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::NaiveDate;
+use sqlx::PgPool;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+/// Days a row of the export covers - a single day for dated rows, the whole exported
+/// period for the aggregated files of the dashboard export
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub visitors: i64,
+    pub pageviews: i64,
+}
+
+impl std::ops::AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        self.visitors += other.visitors;
+        self.pageviews += other.pageviews;
+    }
+}
+
+/// Contents of a Plausible CSV export
+///
+/// Both the dashboard export (`visitors.csv`, `pages.csv`, ...) and the site export
+/// (`imported_visitors_<from>_<to>.csv`, ...) are understood. Rows for the same key
+/// and range are summed, ie. the regions of a country in the site export.
+#[derive(Debug, Default, PartialEq)]
+pub struct PlausibleExport {
+    pub visitors: BTreeMap<NaiveDate, Counts>,
+    pub pages: BTreeMap<(String, DateRange), Counts>,
+    pub sources: BTreeMap<(String, DateRange), Counts>,
+    pub countries: BTreeMap<(String, DateRange), Counts>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileKind {
+    Visitors,
+    Pages,
+    Sources,
+    Countries,
+}
+
+impl FileKind {
+    /// `visitors.csv`, `imported_pages_20230101_20231231.csv`, ...
+    fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.rsplit('/').next().unwrap_or(name);
+        let name = name.strip_suffix(".csv")?;
+        let name = name.strip_prefix("imported_").unwrap_or(name);
+        match name.split('_').next()? {
+            "visitors" => Some(FileKind::Visitors),
+            "pages" => Some(FileKind::Pages),
+            "sources" => Some(FileKind::Sources),
+            "countries" | "locations" => Some(FileKind::Countries),
+            _ => None,
+        }
+    }
+
+    /// Header of the column holding the breakdown key in either export format
+    fn key_columns(&self) -> &'static [&'static str] {
+        match self {
+            FileKind::Visitors => &[],
+            FileKind::Pages => &["page", "name"],
+            FileKind::Sources => &["source", "name"],
+            FileKind::Countries => &["country", "name"],
+        }
+    }
+}
+
+struct Row {
+    date: Option<NaiveDate>,
+    key: String,
+    counts: Counts,
+}
+
+/// Read a Plausible export zip
+pub fn read_export(reader: impl Read + Seek) -> anyhow::Result<PlausibleExport> {
+    let mut archive = zip::ZipArchive::new(reader).context("Not a zip archive")?;
+    let mut files = Vec::new();
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let Some(kind) = FileKind::from_file_name(file.name()) else {
+            info!(file = file.name(), "Skipping file of the Plausible export");
+            continue;
+        };
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| format!("Reading {}", file.name()))?;
+        let rows =
+            read_rows(kind, &contents).with_context(|| format!("Parsing {}", file.name()))?;
+        files.push((kind, rows));
+    }
+
+    let mut export = PlausibleExport::default();
+    for (kind, rows) in &files {
+        if *kind == FileKind::Visitors {
+            for row in rows {
+                let date = row
+                    .date
+                    .ok_or_else(|| anyhow!("visitors file without dates"))?;
+                *export.visitors.entry(date).or_default() += row.counts;
+            }
+        }
+    }
+
+    // Aggregated files cover the period of the daily visitors
+    let exported = match (export.visitors.keys().next(), export.visitors.keys().last()) {
+        (Some(&start), Some(&end)) => Some(DateRange { start, end }),
+        _ => None,
+    };
+
+    for (kind, rows) in files {
+        let breakdown = match kind {
+            FileKind::Visitors => continue,
+            FileKind::Pages => &mut export.pages,
+            FileKind::Sources => &mut export.sources,
+            FileKind::Countries => &mut export.countries,
+        };
+        for row in rows {
+            let range = match row.date {
+                Some(date) => DateRange {
+                    start: date,
+                    end: date,
+                },
+                None => exported.ok_or_else(|| {
+                    anyhow!("Export without visitors.csv, the period of the other files is unknown")
+                })?,
+            };
+            *breakdown.entry((row.key, range)).or_default() += row.counts;
+        }
+    }
+
+    Ok(export)
+}
+
+fn read_rows(kind: FileKind, contents: &str) -> anyhow::Result<Vec<Row>> {
+    let mut reader = csv::Reader::from_reader(contents.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| headers.iter().position(|header| header == *name))
+    };
+
+    let date_column = column(&["date"]);
+    let visitors_column = column(&["visitors"]).ok_or_else(|| anyhow!("No visitors column"))?;
+    let pageviews_column = column(&["pageviews"]);
+    let key_column = match kind {
+        FileKind::Visitors => None,
+        _ => Some(column(kind.key_columns()).ok_or_else(|| anyhow!("No {kind:?} column"))?),
+    };
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let date = date_column
+            .and_then(|column| record.get(column))
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .with_context(|| format!("Invalid date {date}"))
+            })
+            .transpose()?;
+        let key = key_column
+            .and_then(|column| record.get(column))
+            .map(|key| key.trim().to_string())
+            .unwrap_or_default();
+        let counts = Counts {
+            visitors: parse_count(record.get(visitors_column)),
+            pageviews: parse_count(pageviews_column.and_then(|column| record.get(column))),
+        };
+        rows.push(Row { date, key, counts });
+    }
+    Ok(rows)
+}
+
+/// Counts are integers, but tolerate empty cells and decimals
+fn parse_count(value: Option<&str>) -> i64 {
+    let value = value.unwrap_or_default().trim();
+    value
+        .parse::<i64>()
+        .or_else(|_| value.parse::<f64>().map(|v| v.round() as i64))
+        .unwrap_or(0)
+}
+
+/// Maps paths of pages to the posts they show
+pub struct PostPaths {
+    slugs: HashMap<String, String>,
+}
+
+impl PostPaths {
+    /// `posts` are `(slug, url)` pairs as stored in `blog_posts`
+    pub fn new(posts: impl IntoIterator<Item = (String, String)>) -> Self {
+        let slugs = posts
+            .into_iter()
+            .filter_map(|(slug, url)| {
+                let url = Url::parse(&url).ok()?;
+                Some((normalize_path(url.path()), slug))
+            })
+            .collect();
+        Self { slugs }
+    }
+
+    pub fn slug_for(&self, page: &str) -> Option<&str> {
+        let path = page.split(['?', '#']).next().unwrap_or(page);
+        self.slugs.get(&normalize_path(path)).map(String::as_str)
+    }
+}
+
+fn normalize_path(path: &str) -> String {
+    format!("/{}", path.trim_matches('/'))
+}
+
+/// What an import stored
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub days: usize,
+    pub pages: usize,
+    pub unmatched_pages: usize,
+    pub unmatched_pageviews: i64,
+    pub sources: usize,
+    pub countries: usize,
+}
+
+/// Load a Plausible export zip into the `imported_*` tables
+///
+/// Pages are attached to posts by matching their path with `blog_posts.url`, pages
+/// that are not posts are only reported. Importing the same data again replaces it.
+#[instrument(skip(pool))]
+pub async fn import_file(pool: &PgPool, path: &Path) -> anyhow::Result<ImportReport> {
+    let file = std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let export = read_export(std::io::BufReader::new(file))?;
+
+    let posts = sqlx::query!("SELECT slug, url FROM blog_posts")
+        .fetch_all(pool)
+        .await?;
+    let post_paths = PostPaths::new(posts.into_iter().map(|post| (post.slug, post.url)));
+
+    let mut report = ImportReport {
+        days: export.visitors.len(),
+        sources: export.sources.len(),
+        countries: export.countries.len(),
+        ..Default::default()
+    };
+
+    // Several paths can lead to the same post, ie. with and without the trailing slash
+    let mut pages: BTreeMap<(String, DateRange), Counts> = BTreeMap::new();
+    for ((page, range), counts) in &export.pages {
+        match post_paths.slug_for(page) {
+            Some(slug) => *pages.entry((slug.to_string(), *range)).or_default() += *counts,
+            None => {
+                report.unmatched_pages += 1;
+                report.unmatched_pageviews += counts.pageviews;
+            }
+        }
+    }
+    report.pages = pages.len();
+
+    let mut tx = pool.begin().await?;
+
+    let (days, visitors, pageviews) = columns(export.visitors.iter().map(|(day, c)| (*day, *c)));
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_daily_visitors (day, visitors, pageviews)
+        SELECT * FROM UNNEST($1::date[], $2::bigint[], $3::bigint[])
+        ON CONFLICT (day) DO UPDATE
+        SET visitors = excluded.visitors, pageviews = excluded.pageviews
+        "#,
+        &days,
+        &visitors,
+        &pageviews
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let (slugs, starts, ends, visitors, pageviews) = breakdown_columns(&pages);
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_page_stats (post_slug, period_start, period_end, visitors, pageviews)
+        SELECT * FROM UNNEST($1::varchar[], $2::date[], $3::date[], $4::bigint[], $5::bigint[])
+        ON CONFLICT (post_slug, period_start, period_end) DO UPDATE
+        SET visitors = excluded.visitors, pageviews = excluded.pageviews
+        "#,
+        &slugs,
+        &starts,
+        &ends,
+        &visitors,
+        &pageviews
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let (sources, starts, ends, visitors, _) = breakdown_columns(&export.sources);
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_source_stats (source, period_start, period_end, visitors)
+        SELECT * FROM UNNEST($1::varchar[], $2::date[], $3::date[], $4::bigint[])
+        ON CONFLICT (source, period_start, period_end) DO UPDATE
+        SET visitors = excluded.visitors
+        "#,
+        &sources,
+        &starts,
+        &ends,
+        &visitors
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let (countries, starts, ends, visitors, _) = breakdown_columns(&export.countries);
+    sqlx::query!(
+        r#"
+        INSERT INTO imported_country_stats (country, period_start, period_end, visitors)
+        SELECT * FROM UNNEST($1::varchar[], $2::date[], $3::date[], $4::bigint[])
+        ON CONFLICT (country, period_start, period_end) DO UPDATE
+        SET visitors = excluded.visitors
+        "#,
+        &countries,
+        &starts,
+        &ends,
+        &visitors
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if report.unmatched_pages > 0 {
+        warn!(
+            unmatched_pages = report.unmatched_pages,
+            unmatched_pageviews = report.unmatched_pageviews,
+            "Pages of the Plausible export that are not blog posts were skipped"
+        );
+    }
+    info!(
+        days = report.days,
+        pages = report.pages,
+        sources = report.sources,
+        countries = report.countries,
+        "Plausible export imported"
+    );
+
+    Ok(report)
+}
+
+fn columns(
+    rows: impl Iterator<Item = (NaiveDate, Counts)>,
+) -> (Vec<NaiveDate>, Vec<i64>, Vec<i64>) {
+    let mut days = Vec::new();
+    let mut visitors = Vec::new();
+    let mut pageviews = Vec::new();
+    for (day, counts) in rows {
+        days.push(day);
+        visitors.push(counts.visitors);
+        pageviews.push(counts.pageviews);
+    }
+    (days, visitors, pageviews)
+}
+
+type BreakdownColumns = (
+    Vec<String>,
+    Vec<NaiveDate>,
+    Vec<NaiveDate>,
+    Vec<i64>,
+    Vec<i64>,
+);
+
+fn breakdown_columns(rows: &BTreeMap<(String, DateRange), Counts>) -> BreakdownColumns {
+    let mut columns = BreakdownColumns::default();
+    for ((key, range), counts) in rows {
+        columns.0.push(key.clone());
+        columns.1.push(range.start);
+        columns.2.push(range.end);
+        columns.3.push(counts.visitors);
+        columns.4.push(counts.pageviews);
+    }
+    columns
+}
+
+/// Entry point of `backend import-plausible <export.zip>`
+pub async fn run(pool: &PgPool, path: &str) -> anyhow::Result<()> {
+    let report = import_file(pool, Path::new(path)).await?;
+    if report.days == 0 && report.pages == 0 {
+        bail!("Nothing to import in {path}");
+    }
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::{
+    correlation::CorrelationContext,
+    events::{BackendEvent, EventBus},
+    likes::{cache::LikeCountCache, client_ip_hash},
+};
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+/// Whose likes are exported or erased
+#[derive(Debug, Clone, Copy)]
+pub enum LikeOwner<'a> {
+    /// Visitor of the blog, by the hash of their IP
+    IpHash(&'a str),
+    /// Fediverse account that favourited or boosted posts, by its actor id
+    RemoteActor(&'a str),
+}
+
+impl LikeOwner<'_> {
+    fn ip_hash(&self) -> Option<&str> {
+        match self {
+            LikeOwner::IpHash(hash) => Some(hash),
+            LikeOwner::RemoteActor(_) => None,
+        }
+    }
+
+    fn remote_actor(&self) -> Option<&str> {
+        match self {
+            LikeOwner::IpHash(_) => None,
+            LikeOwner::RemoteActor(actor) => Some(actor),
+        }
+    }
+}
+
+/// Everything we store about the likes of a single visitor or fediverse account
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LikesExport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_ip_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_actor: Option<String>,
+    pub exported_at: DateTime<Utc>,
+    pub likes: Vec<ExportedLike>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedLike {
+    pub post_slug: String,
+    pub reaction: String,
+    pub liked_at: Option<DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub cf_country: Option<String>,
+}
+
+/// Fediverse account of the admin export and erasure
+#[derive(Debug, Deserialize)]
+pub struct ActorQuery {
+    pub actor: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErasureResponse {
+    pub success: bool,
+    pub message: String,
+    pub likes_erased: i64,
+}
+
+/// Who asked for the erasure - stored in the audit log
+#[derive(Debug, Clone, Copy)]
+pub enum ErasureRequester {
+    Visitor,
+    Admin,
+}
+
+impl ErasureRequester {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErasureRequester::Visitor => "visitor",
+            ErasureRequester::Admin => "admin",
+        }
+    }
+}
+
+/// Export all likes associated with the hashed IP of the caller
+#[instrument(skip(pool, correlation_ctx))]
+pub async fn export_my_likes(
+    State(pool): State<PgPool>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> Result<Json<LikesExport>, StatusCode> {
+    let user_ip_hash = client_ip_hash(&correlation_ctx);
+    export(&pool, LikeOwner::IpHash(&user_ip_hash), &correlation_ctx).await
+}
+
+/// Admin side export of the likes of a fediverse account - ie. requested by a message
+#[instrument(skip(pool, correlation_ctx))]
+pub async fn admin_export_actor_likes(
+    Query(query): Query<ActorQuery>,
+    State(pool): State<PgPool>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> Result<Json<LikesExport>, StatusCode> {
+    if !is_actor_id(&query.actor) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    export(
+        &pool,
+        LikeOwner::RemoteActor(&query.actor),
+        &correlation_ctx,
+    )
+    .await
+}
+
+async fn export(
+    pool: &PgPool,
+    owner: LikeOwner<'_>,
+    correlation_ctx: &CorrelationContext,
+) -> Result<Json<LikesExport>, StatusCode> {
+    let start_time = std::time::Instant::now();
+
+    let likes = sqlx::query_as!(
+        ExportedLike,
+        r#"
+        SELECT post_slug, reaction, liked_at, user_agent, cf_country
+        FROM blog_post_likes
+        WHERE user_ip_hash = $1 OR cf_connecting_ip_hash = $1 OR remote_actor = $2
+        ORDER BY liked_at
+        "#,
+        owner.ip_hash(),
+        owner.remote_actor()
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| {
+        warn!(
+            error = %e,
+            correlation_id = %correlation_ctx.correlation_id,
+            "Database error exporting likes"
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    info!(
+        likes = likes.len(),
+        correlation_id = %correlation_ctx.correlation_id,
+        request_id = %correlation_ctx.request_id,
+        "Likes exported"
+    );
+    counter!("blog_privacy_exports_total").increment(1);
+    histogram!("blog_database_query_duration_ms", "query" => "export_likes")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(Json(LikesExport {
+        user_ip_hash: owner.ip_hash().map(str::to_string),
+        remote_actor: owner.remote_actor().map(str::to_string),
+        exported_at: Utc::now(),
+        likes,
+    }))
+}
+
+/// Erase all likes associated with the hashed IP of the caller
+#[instrument(skip(pool, cache, correlation_ctx))]
+pub async fn erase_my_likes(
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> Result<Json<ErasureResponse>, StatusCode> {
+    let user_ip_hash = client_ip_hash(&correlation_ctx);
+    erase(
+        &pool,
+        &cache,
+        LikeOwner::IpHash(&user_ip_hash),
+        ErasureRequester::Visitor,
+        &correlation_ctx,
+    )
+    .await
+}
+
+/// Admin side erasure of the likes of a given hash - ie. requested by email
+#[instrument(skip(pool, cache, correlation_ctx))]
+pub async fn admin_erase_likes(
+    Path(user_ip_hash): Path<String>,
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> Result<Json<ErasureResponse>, StatusCode> {
+    if !is_ip_hash(&user_ip_hash) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    erase(
+        &pool,
+        &cache,
+        LikeOwner::IpHash(&user_ip_hash.to_lowercase()),
+        ErasureRequester::Admin,
+        &correlation_ctx,
+    )
+    .await
+}
+
+/// Admin side erasure of the favourites and boosts of a fediverse account
+#[instrument(skip(pool, cache, correlation_ctx))]
+pub async fn admin_erase_actor_likes(
+    Query(query): Query<ActorQuery>,
+    State(pool): State<PgPool>,
+    State(cache): State<Arc<LikeCountCache>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+) -> Result<Json<ErasureResponse>, StatusCode> {
+    if !is_actor_id(&query.actor) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    erase(
+        &pool,
+        &cache,
+        LikeOwner::RemoteActor(&query.actor),
+        ErasureRequester::Admin,
+        &correlation_ctx,
+    )
+    .await
+}
+
+async fn erase(
+    pool: &PgPool,
+    cache: &LikeCountCache,
+    owner: LikeOwner<'_>,
+    requested_by: ErasureRequester,
+    correlation_ctx: &CorrelationContext,
+) -> Result<Json<ErasureResponse>, StatusCode> {
+    let likes_erased = erase_likes(pool, owner, requested_by, correlation_ctx)
+        .await
+        .map_err(|e| {
+            warn!(
+                error = %e,
+                correlation_id = %correlation_ctx.correlation_id,
+                "Database error erasing likes"
+            );
+            counter!("blog_privacy_erasures_errors_total").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // The events invalidate the affected posts too, but only once they made the round trip
+    // through Postgres, the caller should see the erasure right away
+    if likes_erased > 0 {
+        cache.invalidate_all();
+    }
+
+    Ok(Json(ErasureResponse {
+        success: true,
+        message: format!("Erased {likes_erased} likes"),
+        likes_erased,
+    }))
+}
+
+/// Delete the likes and record the erasure in the audit log in one transaction
+#[instrument(skip(pool, correlation_ctx))]
+pub async fn erase_likes(
+    pool: &PgPool,
+    owner: LikeOwner<'_>,
+    requested_by: ErasureRequester,
+    correlation_ctx: &CorrelationContext,
+) -> Result<i64, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    // Like, reaction and source counters of the affected posts are decremented in the same
+    // statement, which returns the new counts of every post and reaction that lost likes
+    let removed = sqlx::query!(
+        r#"
+        WITH deleted AS (
+            DELETE FROM blog_post_likes
+            WHERE user_ip_hash = $1 OR cf_connecting_ip_hash = $1 OR remote_actor = $2
+            RETURNING post_slug, reaction, source
+        ), per_post AS (
+            SELECT post_slug, COUNT(*) AS count FROM deleted GROUP BY post_slug
+        ), updated AS (
+            UPDATE blog_posts
+            SET like_count = GREATEST(blog_posts.like_count - per_post.count, 0)
+            FROM per_post
+            WHERE blog_posts.slug = per_post.post_slug
+            RETURNING blog_posts.slug, blog_posts.like_count
+        ), per_reaction AS (
+            SELECT post_slug, reaction, COUNT(*) AS count FROM deleted GROUP BY post_slug, reaction
+        ), updated_reactions AS (
+            UPDATE blog_post_reaction_counts
+            SET count = GREATEST(blog_post_reaction_counts.count - per_reaction.count, 0)
+            FROM per_reaction
+            WHERE blog_post_reaction_counts.post_slug = per_reaction.post_slug
+                AND blog_post_reaction_counts.reaction = per_reaction.reaction
+            RETURNING blog_post_reaction_counts.post_slug, blog_post_reaction_counts.reaction,
+                blog_post_reaction_counts.count
+        ), per_source AS (
+            SELECT post_slug, source, COUNT(*) AS count FROM deleted GROUP BY post_slug, source
+        ), updated_sources AS (
+            UPDATE blog_post_source_counts
+            SET count = GREATEST(blog_post_source_counts.count - per_source.count, 0)
+            FROM per_source
+            WHERE blog_post_source_counts.post_slug = per_source.post_slug
+                AND blog_post_source_counts.source = per_source.source
+        )
+        SELECT per_reaction.post_slug AS "post_slug!", per_reaction.reaction AS "reaction!",
+            per_reaction.count AS "erased!", updated.like_count AS "total_likes!",
+            COALESCE(updated_reactions.count, 0) AS "reaction_count!"
+        FROM per_reaction
+        JOIN updated ON updated.slug = per_reaction.post_slug
+        LEFT JOIN updated_reactions USING (post_slug, reaction)
+        ORDER BY 1, 2
+        "#,
+        owner.ip_hash(),
+        owner.remote_actor()
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let deleted: i64 = removed.iter().map(|row| row.erased).sum();
+
+    // Counts on open pages and in the caches of every instance follow the erasure
+    for row in removed {
+        let event = BackendEvent::LikeRemoved {
+            post_slug: row.post_slug,
+            reaction: row.reaction,
+            total_likes: row.total_likes,
+            reaction_count: row.reaction_count,
+        };
+        EventBus::publish(&mut *tx, &event).await?;
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO like_erasures (requested_by, likes_erased, correlation_id, request_id)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        requested_by.as_str(),
+        deleted,
+        correlation_ctx.correlation_id,
+        correlation_ctx.request_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    info!(
+        audit = true,
+        requested_by = requested_by.as_str(),
+        likes_erased = deleted,
+        correlation_id = %correlation_ctx.correlation_id,
+        request_id = %correlation_ctx.request_id,
+        "Likes erased"
+    );
+    counter!("blog_privacy_erasures_total", "requested_by" => requested_by.as_str()).increment(1);
+    counter!("blog_privacy_likes_erased_total").increment(deleted as u64);
+    histogram!("blog_database_query_duration_ms", "query" => "erase_likes")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(deleted)
+}
+
+fn is_ip_hash(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_actor_id(value: &str) -> bool {
+    Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
@@ -0,0 +1,258 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use metrics::histogram;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use tracing::{instrument, warn};
+use url::Url;
+
+/// Longest UTM value that is stored, longer ones are cut
+const MAX_UTM_LEN: usize = 100;
+/// Longest DNS name and the size of `referrers.host`, longer hosts are not real referrers
+const MAX_HOST_LEN: usize = 253;
+
+/// Subdomains that lead to the same site, `old.reddit.com` counts as `reddit.com`
+const ALIAS_PREFIXES: &[&str] = &["www.", "m.", "mobile.", "old."];
+
+/// Query parameter carrying `document.referrer` of the post page
+///
+/// The `Referer` header of the like and view requests is the post page itself, so
+/// the page passes on where the reader came from
+#[derive(Debug, Default, Deserialize)]
+pub struct ReferrerQuery {
+    #[serde(rename = "ref")]
+    pub referrer: Option<String>,
+}
+
+/// Where a like or view came from, normalized
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Referrer {
+    /// Host of the referring page, without `www.` and similar prefixes
+    pub host: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+}
+
+impl Referrer {
+    /// Referrer of a request from the post page
+    ///
+    /// UTM parameters are read from the page URL in the `Referer` header. The host is
+    /// taken from `document_referrer`, or the `Referer` header when it is absent, and
+    /// dropped when it is the site itself or too long to be a host name.
+    pub fn from_request(headers: &HeaderMap, document_referrer: Option<&str>) -> Self {
+        let page = headers
+            .get(header::REFERER)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|referer| Url::parse(referer).ok());
+        let own_host = headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .map(|host| normalize_host(host.split(':').next().unwrap_or(host)));
+
+        let source = match document_referrer.filter(|r| !r.is_empty()) {
+            Some(referrer) => Url::parse(referrer).ok(),
+            None => page.clone(),
+        };
+        let host = source
+            .as_ref()
+            .and_then(Url::host_str)
+            .map(normalize_host)
+            .filter(|host| host.len() <= MAX_HOST_LEN)
+            .filter(|host| Some(host) != own_host.as_ref());
+
+        let utm = |name: &str| {
+            page.as_ref()?
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| normalize_utm(&value))
+                .filter(|value| !value.is_empty())
+        };
+
+        Self {
+            host,
+            utm_source: utm("utm_source"),
+            utm_medium: utm("utm_medium"),
+            utm_campaign: utm("utm_campaign"),
+        }
+    }
+
+    /// Nothing known about the origin, ie. typed in or bookmarked
+    pub fn is_direct(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    let mut host = host.trim_end_matches('.').to_lowercase();
+    while let Some(stripped) = ALIAS_PREFIXES
+        .iter()
+        .find_map(|prefix| host.strip_prefix(prefix))
+        .filter(|stripped| stripped.contains('.'))
+    {
+        host = stripped.to_string();
+    }
+    host
+}
+
+fn normalize_utm(value: &str) -> String {
+    value
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_UTM_LEN)
+        .collect()
+}
+
+/// Id of the `referrers` row, created on first use, `None` for direct traffic
+pub async fn referrer_id(
+    executor: impl PgExecutor<'_>,
+    referrer: &Referrer,
+) -> Result<Option<i32>, sqlx::Error> {
+    if referrer.is_direct() {
+        return Ok(None);
+    }
+
+    // DO UPDATE instead of DO NOTHING so the existing row is returned
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO referrers (host, utm_source, utm_medium, utm_campaign)
+        VALUES (COALESCE($1, ''), COALESCE($2, ''), COALESCE($3, ''), COALESCE($4, ''))
+        ON CONFLICT (host, utm_source, utm_medium, utm_campaign)
+        DO UPDATE SET host = excluded.host
+        RETURNING id
+        "#,
+        referrer.host,
+        referrer.utm_source,
+        referrer.utm_medium,
+        referrer.utm_campaign
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(Some(id))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TopReferrersQuery {
+    /// Number of days, including today, the aggregate covers
+    pub days: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReferrerStats {
+    /// `utm_source` when the link was tagged, the referring host otherwise
+    pub source: String,
+    pub views: i64,
+    pub likes: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopReferrers {
+    pub post_slug: String,
+    pub since: NaiveDate,
+    pub referrers: Vec<ReferrerStats>,
+    /// Views and likes without a known origin
+    pub direct: ReferrerStats,
+}
+
+/// Sources that brought the most views and likes to a post, from the admin API
+#[instrument(skip(pool))]
+pub async fn top_referrers(
+    Path(post_slug): Path<String>,
+    Query(query): Query<TopReferrersQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<TopReferrers>, StatusCode> {
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let since = Utc::now().date_naive() - Duration::days(days - 1);
+
+    let stats = load_top_referrers(&pool, &post_slug, since, limit)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, post_slug = %post_slug, "Database error loading top referrers");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(stats))
+}
+
+async fn load_top_referrers(
+    pool: &PgPool,
+    post_slug: &str,
+    since: NaiveDate,
+    limit: i64,
+) -> Result<TopReferrers, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    let rows = sqlx::query!(
+        r#"
+        WITH referred AS (
+            SELECT v.referrer_id, v.views, 0::bigint AS likes
+            FROM post_referrer_views v
+            WHERE v.post_slug = $1 AND v.day >= $2
+            UNION ALL
+            SELECT l.referrer_id, 0::bigint, 1::bigint
+            FROM blog_post_likes l
+            WHERE l.post_slug = $1 AND l.liked_at >= $2::date AND l.referrer_id IS NOT NULL
+        )
+        SELECT
+            COALESCE(NULLIF(r.utm_source, ''), NULLIF(r.host, ''), 'unknown') AS "source!",
+            SUM(referred.views)::bigint AS "views!",
+            SUM(referred.likes)::bigint AS "likes!"
+        FROM referred JOIN referrers r ON r.id = referred.referrer_id
+        GROUP BY 1
+        ORDER BY 2 DESC, 3 DESC, 1
+        "#,
+        post_slug,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COALESCE(SUM(views), 0) FROM post_views
+             WHERE post_slug = $1 AND day >= $2)::bigint AS "views!",
+            (SELECT COUNT(*) FROM blog_post_likes
+             WHERE post_slug = $1 AND liked_at >= $2::date)::bigint AS "likes!"
+        "#,
+        post_slug,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "top_referrers")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    // Views are only broken down when the origin is known, the rest came directly
+    let referred_views: i64 = rows.iter().map(|row| row.views).sum();
+    let referred_likes: i64 = rows.iter().map(|row| row.likes).sum();
+    let direct = ReferrerStats {
+        source: "direct".to_string(),
+        views: (totals.views - referred_views).max(0),
+        likes: (totals.likes - referred_likes).max(0),
+    };
+
+    Ok(TopReferrers {
+        post_slug: post_slug.to_string(),
+        since,
+        referrers: rows
+            .into_iter()
+            .take(limit as usize)
+            .map(|row| ReferrerStats {
+                source: row.source,
+                views: row.views,
+                likes: row.likes,
+            })
+            .collect(),
+        direct,
+    })
+}
@@ -0,0 +1,197 @@
+use axum::{extract::State, http::StatusCode, response::Json};
+use chrono::{DateTime, Duration, Utc};
+use metrics::{counter, gauge, histogram};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{info, instrument, warn};
+
+use crate::jobs::{JobOptions, JobRunner, Schedule};
+
+/// How long personal metadata of likes and analytics events (user agent, hashed IPs) is kept
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Rows older than this are stripped of their metadata
+    pub period: Duration,
+    /// How often the background task runs
+    pub interval: std::time::Duration,
+    /// Rows updated per statement, keeps the transactions short
+    pub batch_size: i64,
+}
+
+impl RetentionConfig {
+    pub fn from_env() -> Self {
+        let period = std::env::var("LIKES_RETENTION_DAYS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::days)
+            .unwrap_or_else(|| Duration::days(90));
+        let interval = std::env::var("LIKES_RETENTION_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(std::time::Duration::from_secs)
+            .unwrap_or_else(|| std::time::Duration::from_secs(60 * 60));
+
+        Self {
+            period,
+            interval,
+            batch_size: 1000,
+        }
+    }
+
+    /// Likes from before this moment should no longer carry personal metadata
+    pub fn cutoff(&self) -> DateTime<Utc> {
+        Utc::now() - self.period
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PruneResponse {
+    pub success: bool,
+    pub rows_processed: u64,
+    pub cutoff: DateTime<Utc>,
+}
+
+/// Null the personal metadata of likes and analytics events from before `cutoff`
+///
+/// Returns the number of rows that were stripped
+#[instrument(skip(pool))]
+pub async fn prune(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<u64, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    let likes = prune_likes(pool, cutoff, batch_size).await?;
+    let events = prune_analytics_events(pool, cutoff, batch_size).await?;
+    let rows_processed = likes + events;
+
+    info!(likes, events, cutoff = %cutoff, "Metadata retention applied");
+    gauge!("blog_retention_last_run_timestamp").set(Utc::now().timestamp() as f64);
+    histogram!("blog_retention_duration_ms").record(start_time.elapsed().as_millis() as f64);
+
+    Ok(rows_processed)
+}
+
+async fn prune_likes(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<u64, sqlx::Error> {
+    let mut rows_processed = 0;
+
+    loop {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE blog_post_likes
+            SET user_agent = NULL, user_ip_hash = NULL, cf_connecting_ip_hash = NULL,
+                cf_country = NULL, remote_actor = NULL
+            WHERE id IN (
+                SELECT id FROM blog_post_likes
+                WHERE liked_at < $1 AND (
+                    user_agent IS NOT NULL
+                    OR user_ip_hash IS NOT NULL
+                    OR cf_connecting_ip_hash IS NOT NULL
+                    OR cf_country IS NOT NULL
+                    OR remote_actor IS NOT NULL
+                )
+                LIMIT $2
+            )
+            "#,
+            cutoff,
+            batch_size
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        rows_processed += updated;
+        counter!("blog_retention_rows_processed_total", "table" => "blog_post_likes")
+            .increment(updated);
+
+        if updated < batch_size as u64 {
+            return Ok(rows_processed);
+        }
+    }
+}
+
+async fn prune_analytics_events(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    batch_size: i64,
+) -> Result<u64, sqlx::Error> {
+    let mut rows_processed = 0;
+
+    loop {
+        let updated = sqlx::query!(
+            r#"
+            UPDATE analytics_events
+            SET user_ip_hash = NULL
+            WHERE id IN (
+                SELECT id FROM analytics_events
+                WHERE occurred_at < $1 AND user_ip_hash IS NOT NULL
+                LIMIT $2
+            )
+            "#,
+            cutoff,
+            batch_size
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        rows_processed += updated;
+        counter!("blog_retention_rows_processed_total", "table" => "analytics_events")
+            .increment(updated);
+
+        if updated < batch_size as u64 {
+            return Ok(rows_processed);
+        }
+    }
+}
+
+/// Run [`prune`] as the `retention_prune` job on `schedule`
+pub fn schedule_retention(
+    jobs: &mut JobRunner,
+    pool: PgPool,
+    config: RetentionConfig,
+    schedule: Schedule,
+) {
+    info!(
+        period_days = config.period.num_days(),
+        ?schedule,
+        "Scheduling like metadata retention job"
+    );
+
+    jobs.register("retention_prune", JobOptions::default(), move |_| {
+        let pool = pool.clone();
+        let config = config.clone();
+        async move {
+            prune(&pool, config.cutoff(), config.batch_size)
+                .await
+                .inspect_err(|_| counter!("blog_retention_errors_total").increment(1))?;
+            Ok(())
+        }
+    });
+    jobs.schedule("retention_prune", schedule);
+}
+
+/// Manual trigger of the retention job from the admin API
+#[instrument(skip(pool, config))]
+pub async fn prune_now(
+    State(pool): State<PgPool>,
+    State(config): State<RetentionConfig>,
+) -> Result<Json<PruneResponse>, StatusCode> {
+    let cutoff = config.cutoff();
+    let rows_processed = prune(&pool, cutoff, config.batch_size).await.map_err(|e| {
+        warn!(error = %e, "Manual like metadata retention failed");
+        counter!("blog_retention_errors_total").increment(1);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(PruneResponse {
+        success: true,
+        rows_processed,
+        cutoff,
+    }))
+}
@@ -0,0 +1,201 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{instrument, warn};
+
+/// Longest query accepted, in characters
+pub const MAX_QUERY_CHARS: usize = 200;
+/// Results returned when the query has no `limit`
+const DEFAULT_LIMIT: i64 = 10;
+const MAX_LIMIT: i64 = 50;
+
+/// Marks Postgres puts around matches in the snippets, replaced by `<mark>` once the
+/// rest of the snippet is escaped. Private use characters never appear in a post.
+const MATCH_START: char = '\u{e000}';
+const MATCH_END: char = '\u{e001}';
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchQuery {
+    /// Words to look for, with the web search syntax: `"a phrase"`, `or`, `-excluded`
+    #[serde(default)]
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResults {
+    pub query: String,
+    /// Number of matching posts, `results` holds at most `limit` of them
+    pub total: i64,
+    pub results: Vec<SearchResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    pub slug: String,
+    pub title: String,
+    pub url: String,
+    pub date: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub rank: f32,
+    /// HTML fragments around the matches, with the matched words in `<mark>`
+    pub snippet: String,
+}
+
+/// Check the search words, `Err` carries the reason used as a metrics label
+pub fn validate_query(q: &str) -> Result<&str, &'static str> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Err("empty_query");
+    }
+    if q.chars().count() > MAX_QUERY_CHARS {
+        return Err("query_too_long");
+    }
+    Ok(q)
+}
+
+/// Full-text search over the posts, `GET /search?q=rust+nix&limit=10`
+///
+/// Results are ordered by rank, a match in the title counts more than one in the tags,
+/// the description or the body of the post.
+#[instrument(skip(pool))]
+pub async fn search_posts(
+    Query(query): Query<SearchQuery>,
+    State(pool): State<PgPool>,
+) -> Response {
+    let start_time = std::time::Instant::now();
+    counter!("blog_search_requests_total").increment(1);
+
+    let q = match validate_query(&query.q) {
+        Ok(q) => q,
+        Err(reason) => {
+            counter!("blog_search_rejected_total", "reason" => reason).increment(1);
+            return (StatusCode::BAD_REQUEST, "invalid search query").into_response();
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    match search(&pool, q, limit, offset).await {
+        Ok(results) => {
+            histogram!("blog_search_request_duration_ms")
+                .record(start_time.elapsed().as_millis() as f64);
+            if results.total == 0 {
+                counter!("blog_search_empty_total").increment(1);
+            }
+            Json(results).into_response()
+        }
+        Err(e) => {
+            warn!(error = %e, "Database error searching posts");
+            counter!("blog_search_errors_total", "reason" => "database_error").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Posts matching `q` with the best ranked first
+pub async fn search(
+    pool: &PgPool,
+    q: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<SearchResults, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let headline_options = format!(
+        "StartSel={MATCH_START}, StopSel={MATCH_END}, MaxWords=35, MinWords=15, \
+         MaxFragments=2, FragmentDelimiter=\" \u{2026} \""
+    );
+
+    // The total is counted over every match, also when the page is past the last one
+    let rows = sqlx::query!(
+        r#"
+        WITH query AS (SELECT websearch_to_tsquery('english', $1) AS tsquery),
+        matches AS (
+            SELECT slug, title, url, date, tags, description, content,
+                ts_rank_cd(search_vector, query.tsquery) AS rank
+            FROM blog_posts, query
+            WHERE search_vector @@ query.tsquery AND NOT draft
+        ),
+        total AS (SELECT COUNT(*) AS total FROM matches),
+        page AS (
+            SELECT * FROM matches
+            ORDER BY rank DESC, date DESC
+            LIMIT $2 OFFSET $3
+        )
+        SELECT
+            total.total AS "total!",
+            page.slug AS "slug?", page.title AS "title?", page.url AS "url?",
+            page.date AS "date?", page.tags, page.rank AS "rank?",
+            ts_headline(
+                'english',
+                concat_ws(' ', page.description, page.content),
+                query.tsquery,
+                $4
+            ) AS "snippet?"
+        FROM total CROSS JOIN query LEFT JOIN page ON true
+        ORDER BY page.rank DESC, page.date DESC
+        "#,
+        q,
+        limit,
+        offset,
+        headline_options
+    )
+    .fetch_all(pool)
+    .await?;
+    histogram!("blog_database_query_duration_ms", "query" => "search_posts")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    let total = rows.first().map(|row| row.total).unwrap_or(0);
+    // An empty page is a single row with the total only
+    let results = rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(SearchResult {
+                slug: row.slug?,
+                title: row.title?,
+                url: row.url?,
+                date: row.date?,
+                tags: row
+                    .tags
+                    .iter()
+                    .flat_map(|tags| tags.split(','))
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                rank: row.rank?,
+                snippet: highlight(&row.snippet?),
+            })
+        })
+        .collect();
+
+    Ok(SearchResults {
+        query: q.to_string(),
+        total,
+        results,
+    })
+}
+
+/// Escape the snippet for HTML and turn the match markers into `<mark>` elements
+pub fn highlight(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    for c in snippet.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
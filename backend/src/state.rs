@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use sqlx::PgPool;
+
+use crate::{
+    activitypub::Federation,
+    analytics::AnalyticsConfig,
+    events::EventBus,
+    likes::{abuse::AbusePipeline, cache::LikeCountCache, reactions::Reactions},
+    og_image::OgImages,
+    retention::RetentionConfig,
+    subscriptions::Newsletter,
+    views::VisitorHasher,
+};
+
+/// State shared by all the HTTP handlers
+///
+/// Handlers extract only the parts they need thanks to the [`FromRef`] impls
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub abuse: Arc<AbusePipeline>,
+    pub like_counts: Arc<LikeCountCache>,
+    pub reactions: Arc<Reactions>,
+    pub events: EventBus,
+    pub retention: RetentionConfig,
+    pub visitors: Arc<VisitorHasher>,
+    pub analytics: Arc<AnalyticsConfig>,
+    pub newsletter: Arc<Newsletter>,
+    pub federation: Arc<Federation>,
+    pub og_images: Arc<OgImages>,
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<AbusePipeline> {
+    fn from_ref(state: &AppState) -> Self {
+        state.abuse.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<LikeCountCache> {
+    fn from_ref(state: &AppState) -> Self {
+        state.like_counts.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Reactions> {
+    fn from_ref(state: &AppState) -> Self {
+        state.reactions.clone()
+    }
+}
+
+impl FromRef<AppState> for EventBus {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for RetentionConfig {
+    fn from_ref(state: &AppState) -> Self {
+        state.retention.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<VisitorHasher> {
+    fn from_ref(state: &AppState) -> Self {
+        state.visitors.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<AnalyticsConfig> {
+    fn from_ref(state: &AppState) -> Self {
+        state.analytics.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Newsletter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.newsletter.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Federation> {
+    fn from_ref(state: &AppState) -> Self {
+        state.federation.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<OgImages> {
+    fn from_ref(state: &AppState) -> Self {
+        state.og_images.clone()
+    }
+}
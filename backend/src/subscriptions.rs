@@ -0,0 +1,426 @@
+use crate::likes::abuse::is_automated_user_agent;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    Address, Message,
+};
+use mailer::{ListUnsubscribe, ListUnsubscribePost, MailTransport};
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+pub mod digest;
+pub mod mailer;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Answer to every accepted subscription, whether the address was known or not
+const CHECK_INBOX: &str = "Check your inbox to confirm the subscription";
+
+/// Confirmation emails are not sent again to the same address more often than this
+const RESEND_INTERVAL_MINUTES: i64 = 10;
+
+/// Signed links of the subscription emails
+///
+/// Confirmation tokens have the form `<subscriber id>.<expires unix timestamp>.<hex hmac>`,
+/// unsubscribe tokens `<subscriber id>.<hex hmac>` and never expire
+#[derive(Clone)]
+pub struct SubscriptionTokens {
+    secret: Vec<u8>,
+    confirmation_ttl: Duration,
+}
+
+impl SubscriptionTokens {
+    pub fn new(secret: impl Into<Vec<u8>>, confirmation_ttl: Duration) -> Self {
+        Self {
+            secret: secret.into(),
+            confirmation_ttl,
+        }
+    }
+
+    pub fn confirmation(&self, subscriber_id: i32, now: DateTime<Utc>) -> String {
+        let expires = (now + self.confirmation_ttl).timestamp();
+        let signature = self.mac("confirm", subscriber_id, expires).finalize();
+        format!(
+            "{subscriber_id}.{expires}.{}",
+            hex::encode(signature.into_bytes())
+        )
+    }
+
+    /// Subscriber the token confirms, `None` when it is forged or expired
+    pub fn verify_confirmation(&self, token: &str, now: DateTime<Utc>) -> Option<i32> {
+        let mut parts = token.splitn(3, '.');
+        let subscriber_id: i32 = parts.next()?.parse().ok()?;
+        let expires: i64 = parts.next()?.parse().ok()?;
+        let signature = hex::decode(parts.next()?).ok()?;
+
+        self.mac("confirm", subscriber_id, expires)
+            .verify_slice(&signature)
+            .ok()?;
+        (now.timestamp() <= expires).then_some(subscriber_id)
+    }
+
+    pub fn unsubscribe(&self, subscriber_id: i32) -> String {
+        let signature = self.mac("unsubscribe", subscriber_id, 0).finalize();
+        format!("{subscriber_id}.{}", hex::encode(signature.into_bytes()))
+    }
+
+    /// Subscriber the token unsubscribes, `None` when it is forged
+    pub fn verify_unsubscribe(&self, token: &str) -> Option<i32> {
+        let (subscriber_id, signature) = token.split_once('.')?;
+        let subscriber_id: i32 = subscriber_id.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+
+        self.mac("unsubscribe", subscriber_id, 0)
+            .verify_slice(&signature)
+            .ok()?;
+        Some(subscriber_id)
+    }
+
+    fn mac(&self, purpose: &str, subscriber_id: i32, expires: i64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC can take key of any size");
+        mac.update(format!("{purpose}\n{subscriber_id}\n{expires}").as_bytes());
+        mac
+    }
+}
+
+/// Builds and sends the newsletter emails
+pub struct Newsletter {
+    pub tokens: SubscriptionTokens,
+    /// Public URL the backend is reachable on, the links of the emails point there
+    pub public_url: String,
+    pub from: Mailbox,
+    transport: Box<dyn MailTransport>,
+}
+
+impl Newsletter {
+    pub fn new(
+        tokens: SubscriptionTokens,
+        public_url: impl Into<String>,
+        from: Mailbox,
+        transport: Box<dyn MailTransport>,
+    ) -> Self {
+        Self {
+            tokens,
+            public_url: public_url.into().trim_end_matches('/').to_string(),
+            from,
+            transport,
+        }
+    }
+
+    /// Reads `SUBSCRIPTIONS_SECRET`, `SUBSCRIPTIONS_PUBLIC_URL`, `NEWSLETTER_FROM` and
+    /// `SMTP_URL`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let secret = std::env::var("SUBSCRIPTIONS_SECRET").unwrap_or_else(|_| {
+            warn!("SUBSCRIPTIONS_SECRET not set - confirmation and unsubscribe links will not survive a restart");
+            format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4())
+        });
+        let public_url = std::env::var("SUBSCRIPTIONS_PUBLIC_URL")
+            .unwrap_or_else(|_| "https://blog.flakm.com/api".to_string());
+        let from = std::env::var("NEWSLETTER_FROM")
+            .unwrap_or_else(|_| "newsletter@blog.flakm.com".to_string())
+            .parse()?;
+
+        Ok(Self::new(
+            SubscriptionTokens::new(secret, Duration::days(2)),
+            public_url,
+            from,
+            mailer::from_env()?,
+        ))
+    }
+
+    pub async fn send(&self, message: Message) -> anyhow::Result<()> {
+        self.transport.send(message).await
+    }
+
+    pub fn confirmation_email(
+        &self,
+        to: Mailbox,
+        subscriber_id: i32,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<Message> {
+        let link = format!(
+            "{}/subscribe/confirm?token={}",
+            self.public_url,
+            self.tokens.confirmation(subscriber_id, now)
+        );
+        let body = format!(
+            "Please confirm that you want to receive an email when a new post is published:\n\n\
+             {link}\n\n\
+             The link is valid for {} hours. If you did not subscribe, ignore this email.\n",
+            self.tokens.confirmation_ttl.num_hours()
+        );
+
+        Ok(Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject("Confirm your subscription")
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?)
+    }
+
+    /// Email with the unsubscribe link in the footer and the `List-Unsubscribe` headers
+    pub fn subscriber_email(
+        &self,
+        to: Mailbox,
+        subscriber_id: i32,
+        subject: &str,
+        body: &str,
+    ) -> anyhow::Result<Message> {
+        let unsubscribe = format!(
+            "{}/unsubscribe?token={}",
+            self.public_url,
+            self.tokens.unsubscribe(subscriber_id)
+        );
+        let body = format!("{body}\n--\nYou get this email because you subscribed to new posts.\nUnsubscribe: {unsubscribe}\n");
+
+        Ok(Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ListUnsubscribe(unsubscribe))
+            .header(ListUnsubscribePost)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)?)
+    }
+}
+
+/// Address the subscription is stored under, `None` when it is not a valid address
+pub fn normalize_email(email: &str) -> Option<Address> {
+    let email = email.trim().to_lowercase();
+    if email.len() > 254 {
+        return None;
+    }
+    email.parse().ok()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscribeResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+impl SubscribeResponse {
+    fn new(success: bool, message: impl Into<String>) -> Json<Self> {
+        Json(Self {
+            success,
+            message: message.into(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    pub token: String,
+}
+
+/// Start a subscription, the address receives a link confirming it
+///
+/// The answer does not tell whether the address was already subscribed
+#[instrument(skip(pool, newsletter, headers, request))]
+pub async fn subscribe(
+    State(pool): State<PgPool>,
+    State(newsletter): State<Arc<Newsletter>>,
+    headers: HeaderMap,
+    Json(request): Json<SubscribeRequest>,
+) -> Result<Json<SubscribeResponse>, StatusCode> {
+    let start_time = std::time::Instant::now();
+    counter!("blog_subscriptions_requests_total", "endpoint" => "subscribe").increment(1);
+
+    let user_agent = headers.get("user-agent").and_then(|h| h.to_str().ok());
+    if is_automated_user_agent(user_agent) {
+        counter!("blog_subscriptions_rejected_total", "reason" => "bot_user_agent").increment(1);
+        return Ok(SubscribeResponse::new(
+            false,
+            "Automated requests are not allowed",
+        ));
+    }
+
+    let Some(email) = normalize_email(&request.email) else {
+        counter!("blog_subscriptions_rejected_total", "reason" => "invalid_email").increment(1);
+        return Ok(SubscribeResponse::new(false, "Invalid email address"));
+    };
+
+    let claimed = claim_confirmation(&pool, email.as_ref())
+        .await
+        .map_err(|e| {
+            warn!(error = %e, "Database error storing subscription");
+            counter!("blog_subscriptions_errors_total", "reason" => "database_error").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    // Confirmed or recently sent a confirmation
+    let Some(subscriber_id) = claimed else {
+        return Ok(SubscribeResponse::new(true, CHECK_INBOX));
+    };
+
+    let sent = async {
+        let message =
+            newsletter.confirmation_email(Mailbox::new(None, email), subscriber_id, Utc::now())?;
+        newsletter.send(message).await
+    }
+    .await;
+
+    if let Err(e) = sent {
+        warn!(error = %e, subscriber_id, "Failed to send confirmation email");
+        counter!("blog_newsletter_emails_total", "kind" => "confirmation", "status" => "failed")
+            .increment(1);
+        // Allow trying again right away
+        sqlx::query!(
+            "UPDATE subscribers SET confirmation_sent_at = NULL WHERE id = $1",
+            subscriber_id
+        )
+        .execute(&pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        return Ok(SubscribeResponse::new(
+            false,
+            "Could not send the confirmation email, please try again later",
+        ));
+    }
+
+    info!(subscriber_id, "Confirmation email sent");
+    counter!("blog_newsletter_emails_total", "kind" => "confirmation", "status" => "sent")
+        .increment(1);
+    histogram!("blog_subscriptions_request_duration_ms")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(SubscribeResponse::new(true, CHECK_INBOX))
+}
+
+/// Store the address as pending and return its id when a confirmation should be
+/// sent now
+///
+/// Addresses that unsubscribed start over, the resend interval keeps the endpoint
+/// from flooding an inbox
+async fn claim_confirmation(pool: &PgPool, email: &str) -> Result<Option<i32>, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    let subscriber_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO subscribers (email) VALUES ($1)
+        ON CONFLICT (email) DO UPDATE SET
+            status = CASE WHEN subscribers.status = 'unsubscribed' THEN 'pending' ELSE subscribers.status END
+        RETURNING id
+        "#,
+        email
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let claimed = sqlx::query_scalar!(
+        r#"
+        UPDATE subscribers SET confirmation_sent_at = NOW()
+        WHERE id = $1 AND status = 'pending'
+            AND (confirmation_sent_at IS NULL OR confirmation_sent_at < NOW() - make_interval(mins => $2))
+        RETURNING id
+        "#,
+        subscriber_id,
+        RESEND_INTERVAL_MINUTES as i32
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "claim_confirmation")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(claimed)
+}
+
+/// Target of the link in the confirmation email
+#[instrument(skip(pool, newsletter, query))]
+pub async fn confirm(
+    Query(query): Query<TokenQuery>,
+    State(pool): State<PgPool>,
+    State(newsletter): State<Arc<Newsletter>>,
+) -> (StatusCode, &'static str) {
+    counter!("blog_subscriptions_requests_total", "endpoint" => "confirm").increment(1);
+    let invalid = (
+        StatusCode::BAD_REQUEST,
+        "This confirmation link is invalid or expired",
+    );
+
+    let Some(subscriber_id) = newsletter
+        .tokens
+        .verify_confirmation(&query.token, Utc::now())
+    else {
+        return invalid;
+    };
+
+    let confirmed = sqlx::query_scalar!(
+        r#"
+        UPDATE subscribers SET status = 'confirmed', confirmed_at = COALESCE(confirmed_at, NOW())
+        WHERE id = $1 AND status <> 'unsubscribed'
+        RETURNING id
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&pool)
+    .await;
+
+    match confirmed {
+        Ok(Some(_)) => {
+            info!(subscriber_id, "Subscription confirmed");
+            counter!("blog_subscriptions_confirmed_total").increment(1);
+            (StatusCode::OK, "Subscription confirmed, thanks!")
+        }
+        Ok(None) => invalid,
+        Err(e) => {
+            warn!(error = %e, subscriber_id, "Database error confirming subscription");
+            (StatusCode::INTERNAL_SERVER_ERROR, "error")
+        }
+    }
+}
+
+/// Target of the unsubscribe link, also accepts the one-click POST of mail clients
+#[instrument(skip(pool, newsletter, query))]
+pub async fn unsubscribe(
+    Query(query): Query<TokenQuery>,
+    State(pool): State<PgPool>,
+    State(newsletter): State<Arc<Newsletter>>,
+) -> (StatusCode, &'static str) {
+    counter!("blog_subscriptions_requests_total", "endpoint" => "unsubscribe").increment(1);
+
+    let Some(subscriber_id) = newsletter.tokens.verify_unsubscribe(&query.token) else {
+        return (StatusCode::BAD_REQUEST, "This unsubscribe link is invalid");
+    };
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscribers SET status = 'unsubscribed', unsubscribed_at = NOW()
+        WHERE id = $1 AND status <> 'unsubscribed'
+        "#,
+        subscriber_id
+    )
+    .execute(&pool)
+    .await;
+
+    match result {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                info!(subscriber_id, "Unsubscribed");
+                counter!("blog_subscriptions_unsubscribed_total").increment(1);
+            }
+            (StatusCode::OK, "You have been unsubscribed")
+        }
+        Err(e) => {
+            warn!(error = %e, subscriber_id, "Database error unsubscribing");
+            (StatusCode::INTERNAL_SERVER_ERROR, "error")
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use lettre::message::Mailbox;
+use metrics::{counter, histogram};
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::{info, instrument, warn};
+
+use super::Newsletter;
+use crate::{
+    events::{BackendEvent, EventSubscriber},
+    jobs::{self, JobOptions, JobRunner},
+};
+
+/// Post listed in a digest
+#[derive(Debug, Clone)]
+pub struct DigestPost {
+    pub title: String,
+    pub description: Option<String>,
+    pub url: String,
+}
+
+/// Subject and body of the digest announcing `posts`
+pub fn digest_text(posts: &[DigestPost]) -> (String, String) {
+    let subject = match posts {
+        [post] => format!("New post: {}", post.title),
+        _ => format!("{} new posts", posts.len()),
+    };
+    let body = posts
+        .iter()
+        .map(|post| match &post.description {
+            Some(description) => format!("{}\n{}\n{}\n", post.title, description, post.url),
+            None => format!("{}\n{}\n", post.title, post.url),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    (subject, body)
+}
+
+/// Kind of the job sending the digest of the posts not announced yet
+pub const DIGEST_JOB: &str = "newsletter_digest";
+
+/// Queues the digest when posts are ingested
+///
+/// The job runs `delay` after the first ingested post, so posts published together end
+/// up in the same email. Every ingested post triggers it, the job only announces posts
+/// that were not announced before.
+pub struct DigestTrigger {
+    pool: PgPool,
+    delay: Duration,
+}
+
+impl DigestTrigger {
+    pub fn new(pool: PgPool, delay: Duration) -> Self {
+        Self { pool, delay }
+    }
+}
+
+impl EventSubscriber for DigestTrigger {
+    fn name(&self) -> &'static str {
+        "newsletter_digest"
+    }
+
+    fn handle(&self, event: &BackendEvent) {
+        if let BackendEvent::PostIngested { .. } = event {
+            let pool = self.pool.clone();
+            let run_at = Utc::now() + chrono::Duration::from_std(self.delay).unwrap_or_default();
+            tokio::spawn(async move {
+                // A pending digest will also announce this post
+                let queued =
+                    jobs::enqueue_unless_pending(&pool, DIGEST_JOB, &json!({}), run_at).await;
+                if let Err(e) = queued {
+                    warn!(error = %e, "Failed to queue the newsletter digest");
+                    counter!("blog_newsletter_digest_errors_total").increment(1);
+                }
+            });
+        }
+    }
+}
+
+/// Send the digests as `newsletter_digest` jobs, retried by the job runner
+pub fn register(jobs: &mut JobRunner, pool: PgPool, newsletter: Arc<Newsletter>) {
+    jobs.register(DIGEST_JOB, JobOptions::default(), move |_| {
+        let pool = pool.clone();
+        let newsletter = newsletter.clone();
+        async move {
+            send_digest(&pool, &newsletter).await?;
+            Ok(())
+        }
+    });
+}
+
+/// Announce the posts that were not announced yet to the confirmed subscribers
+///
+/// Returns the number of announced posts. Posts are claimed in the transaction
+/// that creates the deliveries, so each is announced once even with several
+/// instances running.
+#[instrument(skip(pool, newsletter))]
+pub async fn send_digest(pool: &PgPool, newsletter: &Newsletter) -> anyhow::Result<usize> {
+    let mut tx = pool.begin().await?;
+
+    let slugs = sqlx::query_scalar!(
+        "UPDATE blog_posts SET announced = true WHERE NOT announced AND NOT draft RETURNING slug"
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if slugs.is_empty() {
+        tx.commit().await?;
+        return Ok(0);
+    }
+
+    let digest_id = sqlx::query_scalar!(
+        "INSERT INTO newsletter_digests (post_slugs) VALUES ($1) RETURNING id",
+        &slugs
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let subscribers = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_deliveries (digest_id, subscriber_id)
+        SELECT $1, id FROM subscribers WHERE status = 'confirmed'
+        "#,
+        digest_id
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    info!(
+        digest_id,
+        posts = slugs.len(),
+        subscribers,
+        "Newsletter digest created"
+    );
+
+    deliver_pending(pool, newsletter).await?;
+    Ok(slugs.len())
+}
+
+/// Send the pending deliveries of all digests, one row lock at a time so concurrent
+/// instances never send the same email
+///
+/// Failed deliveries keep their error and are not tried again.
+pub async fn deliver_pending(pool: &PgPool, newsletter: &Newsletter) -> anyhow::Result<()> {
+    let mut posts_of_digest: HashMap<i32, Vec<DigestPost>> = HashMap::new();
+
+    loop {
+        let mut tx = pool.begin().await?;
+
+        let Some(delivery) = sqlx::query!(
+            r#"
+            SELECT d.digest_id, d.subscriber_id, s.email, g.post_slugs
+            FROM newsletter_deliveries d
+            JOIN subscribers s ON s.id = d.subscriber_id
+            JOIN newsletter_digests g ON g.id = d.digest_id
+            WHERE d.status = 'pending' AND s.status = 'confirmed'
+            ORDER BY d.digest_id, d.subscriber_id
+            LIMIT 1
+            FOR UPDATE OF d SKIP LOCKED
+            "#
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            tx.commit().await?;
+            return Ok(());
+        };
+
+        let start_time = std::time::Instant::now();
+
+        if let Entry::Vacant(entry) = posts_of_digest.entry(delivery.digest_id) {
+            let posts = sqlx::query_as!(
+                DigestPost,
+                "SELECT title, description, url FROM blog_posts WHERE slug = ANY($1) ORDER BY date",
+                &delivery.post_slugs
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            entry.insert(posts);
+        }
+        let (subject, body) = digest_text(&posts_of_digest[&delivery.digest_id]);
+
+        let sent = async {
+            let message = newsletter.subscriber_email(
+                Mailbox::new(None, delivery.email.parse()?),
+                delivery.subscriber_id,
+                &subject,
+                &body,
+            )?;
+            newsletter.send(message).await
+        }
+        .await;
+
+        let (status, error) = match &sent {
+            Ok(()) => ("sent", None),
+            Err(e) => {
+                warn!(
+                    error = %e,
+                    digest_id = delivery.digest_id,
+                    subscriber_id = delivery.subscriber_id,
+                    "Failed to deliver newsletter digest"
+                );
+                ("failed", Some(e.to_string()))
+            }
+        };
+
+        sqlx::query!(
+            r#"
+            UPDATE newsletter_deliveries
+            SET status = $3, attempts = attempts + 1, last_error = $4, updated_at = NOW()
+            WHERE digest_id = $1 AND subscriber_id = $2
+            "#,
+            delivery.digest_id,
+            delivery.subscriber_id,
+            status,
+            error
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        counter!("blog_newsletter_emails_total", "kind" => "digest", "status" => status)
+            .increment(1);
+        histogram!("blog_newsletter_delivery_duration_ms")
+            .record(start_time.elapsed().as_millis() as f64);
+    }
+}
@@ -0,0 +1,457 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{PgConnection, PgPool};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    activitypub::{delivery::federate_post, Federation},
+    bluesky::{syndication::syndicate_post, BlueskyClient},
+    jobs::{self, JobOptions, JobRunner},
+};
+
+/// Where posts are cross-posted to, stored in `syndication_outbox.target`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyndicationTarget {
+    /// `Create` activity delivered to the fediverse followers
+    ActivityPub,
+    /// Post with a link card on Bluesky
+    Bluesky,
+}
+
+impl SyndicationTarget {
+    /// Every ingested post gets a job for each of these
+    pub const ALL: [SyndicationTarget; 2] =
+        [SyndicationTarget::ActivityPub, SyndicationTarget::Bluesky];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyndicationTarget::ActivityPub => "activitypub",
+            SyndicationTarget::Bluesky => "bluesky",
+        }
+    }
+
+    pub fn parse(target: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.as_str() == target)
+    }
+}
+
+/// Kind of the job publishing one entry of the outbox
+pub const SYNDICATION_JOB: &str = "syndication";
+
+/// Queue the cross-posts of a post, a job per target
+///
+/// Runs in the transaction ingesting the post. Posts ingested again keep their jobs,
+/// so a post is only published once per target.
+pub async fn enqueue(tx: &mut PgConnection, post_slug: &str) -> Result<(), sqlx::Error> {
+    let targets: Vec<String> = SyndicationTarget::ALL
+        .iter()
+        .map(|target| target.as_str().to_string())
+        .collect();
+
+    let ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO syndication_outbox (post_slug, target)
+        SELECT $1, target FROM UNNEST($2::VARCHAR[]) AS target
+        ON CONFLICT (post_slug, target) DO NOTHING
+        RETURNING id
+        "#,
+        post_slug,
+        &targets
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for id in ids {
+        queue_job(&mut *tx, id).await?;
+    }
+    Ok(())
+}
+
+/// Queue a `syndication` job for the entry, which counts its attempts from now on
+async fn queue_job(tx: &mut PgConnection, outbox_id: i32) -> Result<(), sqlx::Error> {
+    let job_id = jobs::enqueue(
+        &mut *tx,
+        SYNDICATION_JOB,
+        &json!({ "outbox_id": outbox_id }),
+        Utc::now(),
+    )
+    .await?;
+    sqlx::query!(
+        "UPDATE syndication_outbox SET job_id = $2 WHERE id = $1",
+        outbox_id,
+        job_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+/// Mark the entry of a `syndication` job failed once the runner gives up on it
+fn give_up<'c>(
+    tx: &'c mut PgConnection,
+    payload: &'c Value,
+    error: &'c str,
+) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'c>> {
+    Box::pin(async move {
+        let Some(outbox_id) = outbox_id(payload) else {
+            return Ok(());
+        };
+        let target = sqlx::query_scalar!(
+            r#"
+            UPDATE syndication_outbox
+            SET status = 'failed', last_error = $2, updated_at = NOW()
+            WHERE id = $1 AND status = 'pending'
+            RETURNING target
+            "#,
+            outbox_id,
+            error
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(target) = target {
+            counter!("blog_syndication_jobs_total", "target" => target, "status" => "failed")
+                .increment(1);
+        }
+        Ok(())
+    })
+}
+
+fn outbox_id(payload: &Value) -> Option<i32> {
+    payload["outbox_id"]
+        .as_i64()
+        .and_then(|id| i32::try_from(id).ok())
+}
+
+/// Publishes the entries of the outbox
+pub struct Syndicator {
+    pool: PgPool,
+    federation: Arc<Federation>,
+    /// `None` when Bluesky is not configured, its jobs are skipped
+    bluesky: Option<Arc<BlueskyClient>>,
+    max_attempts: i32,
+}
+
+/// What became of a job
+enum JobOutcome {
+    /// Published, with what the target returned
+    Done(Option<String>),
+    /// The target is not configured
+    Skipped,
+}
+
+impl Syndicator {
+    /// Reads `SYNDICATION_MAX_ATTEMPTS`
+    pub fn from_env(
+        pool: PgPool,
+        federation: Arc<Federation>,
+        bluesky: Option<Arc<BlueskyClient>>,
+    ) -> Self {
+        let max_attempts = std::env::var("SYNDICATION_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(8);
+        Self::new(pool, federation, bluesky, max_attempts)
+    }
+
+    /// Gives up on an entry after `max_attempts` failed attempts
+    pub fn new(
+        pool: PgPool,
+        federation: Arc<Federation>,
+        bluesky: Option<Arc<BlueskyClient>>,
+        max_attempts: i32,
+    ) -> Self {
+        Self {
+            pool,
+            federation,
+            bluesky,
+            max_attempts,
+        }
+    }
+
+    /// Publish the entries as `syndication` jobs, retried by the job runner
+    pub fn register(self, jobs: &mut JobRunner) {
+        let options = JobOptions {
+            concurrency: 1,
+            max_attempts: self.max_attempts,
+        };
+        let syndicator = Arc::new(self);
+        jobs.register(SYNDICATION_JOB, options, move |payload| {
+            let syndicator = syndicator.clone();
+            async move {
+                let outbox_id = outbox_id(&payload)
+                    .ok_or_else(|| anyhow::anyhow!("Job without an outbox id"))?;
+                syndicator.run(outbox_id).await
+            }
+        });
+        jobs.on_give_up(SYNDICATION_JOB, give_up);
+    }
+
+    /// Publish one attempt of an entry and record its outcome
+    ///
+    /// The job runner makes sure only one instance runs the job, counts the attempts
+    /// and retries failed ones. No transaction is held while the target is called.
+    #[instrument(skip(self))]
+    async fn run(&self, outbox_id: i32) -> anyhow::Result<()> {
+        let Some(job) = sqlx::query!(
+            "SELECT post_slug, target FROM syndication_outbox WHERE id = $1 AND status = 'pending'",
+            outbox_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            // Done before, or given up and not retried yet
+            return Ok(());
+        };
+
+        let start_time = std::time::Instant::now();
+        let outcome = match SyndicationTarget::parse(&job.target) {
+            Some(target) => self.publish(target, &job.post_slug).await,
+            None => Err(anyhow::anyhow!("Unknown target {}", job.target)),
+        };
+        histogram!("blog_syndication_duration_ms", "target" => job.target.clone())
+            .record(start_time.elapsed().as_millis() as f64);
+
+        let (status, error) = match outcome {
+            Ok(JobOutcome::Done(result)) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE syndication_outbox
+                    SET status = 'done', result = $2, last_error = NULL,
+                        updated_at = NOW(), completed_at = NOW()
+                    WHERE id = $1
+                    "#,
+                    outbox_id,
+                    result
+                )
+                .execute(&self.pool)
+                .await?;
+                info!(post_slug = %job.post_slug, target = %job.target, "Post syndicated");
+                ("done", None)
+            }
+            Ok(JobOutcome::Skipped) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE syndication_outbox
+                    SET status = 'skipped', updated_at = NOW(), completed_at = NOW()
+                    WHERE id = $1
+                    "#,
+                    outbox_id
+                )
+                .execute(&self.pool)
+                .await?;
+                ("skipped", None)
+            }
+            // Recorded by the job, which is retried or given up
+            Err(e) => ("error", Some(e)),
+        };
+
+        counter!("blog_syndication_jobs_total", "target" => job.target, "status" => status)
+            .increment(1);
+        match error {
+            None => Ok(()),
+            Some(e) => Err(e.context(format!("Failed to syndicate {}", job.post_slug))),
+        }
+    }
+
+    async fn publish(
+        &self,
+        target: SyndicationTarget,
+        post_slug: &str,
+    ) -> anyhow::Result<JobOutcome> {
+        match target {
+            SyndicationTarget::ActivityPub => {
+                federate_post(&self.pool, &self.federation, post_slug).await?;
+                Ok(JobOutcome::Done(None))
+            }
+            SyndicationTarget::Bluesky => {
+                let Some(client) = &self.bluesky else {
+                    return Ok(JobOutcome::Skipped);
+                };
+                let record = syndicate_post(&self.pool, client, post_slug).await?;
+                Ok(JobOutcome::Done(Some(record.uri)))
+            }
+        }
+    }
+}
+
+/// Status of the cross-post of a post to one target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyndicationJob {
+    pub target: String,
+    /// `pending`, `done`, `failed` or `skipped`
+    pub status: String,
+    /// Attempts of the latest job, unknown once finished jobs were deleted
+    pub attempts: Option<i32>,
+    /// When a pending job is tried next
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// What the target returned, ie. the `at://` URI of the Bluesky post
+    pub result: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Cross-posts of a post, by target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostSyndication {
+    pub post_slug: String,
+    pub targets: Vec<SyndicationJob>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SyndicationQuery {
+    /// Only posts with a job in this status
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Per target status of the latest posts, from the admin API
+#[instrument(skip(pool))]
+pub async fn list_syndication(
+    Query(query): Query<SyndicationQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<PostSyndication>>, StatusCode> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 500);
+    load_syndication(&pool, None, query.status.as_deref(), limit)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            warn!(error = %e, "Database error loading the syndication status");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Per target status of one post, from the admin API
+#[instrument(skip(pool))]
+pub async fn post_syndication(
+    Path(post_slug): Path<String>,
+    State(pool): State<PgPool>,
+) -> Result<Json<PostSyndication>, StatusCode> {
+    let posts = load_syndication(&pool, Some(&post_slug), None, 1)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, post_slug = %post_slug, "Database error loading the syndication status");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    posts
+        .into_iter()
+        .next()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Queue a failed or skipped job again with fresh attempts, from the admin API
+///
+/// A pending entry is queued again too when its job is gone, ie. given up before the
+/// outbox followed the jobs.
+#[instrument(skip(pool))]
+pub async fn retry_syndication(
+    Path((post_slug, target)): Path<(String, String)>,
+    State(pool): State<PgPool>,
+) -> Result<Json<PostSyndication>, StatusCode> {
+    let target = SyndicationTarget::parse(&target).ok_or(StatusCode::NOT_FOUND)?;
+    let database_error = |e: sqlx::Error| {
+        warn!(error = %e, post_slug = %post_slug, "Database error retrying syndication");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    let mut tx = pool.begin().await.map_err(database_error)?;
+    let Some(id) = sqlx::query_scalar!(
+        r#"
+        UPDATE syndication_outbox o
+        SET status = 'pending', last_error = NULL, updated_at = NOW(), completed_at = NULL
+        WHERE post_slug = $1 AND target = $2 AND (
+            status IN ('failed', 'skipped')
+            OR (status = 'pending' AND NOT EXISTS (
+                SELECT 1 FROM jobs
+                WHERE jobs.id = o.job_id AND jobs.status IN ('pending', 'running')
+            ))
+        )
+        RETURNING id
+        "#,
+        post_slug,
+        target.as_str()
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(database_error)?
+    else {
+        // Unknown, done, or its job is still going
+        return Err(StatusCode::CONFLICT);
+    };
+    queue_job(&mut tx, id).await.map_err(database_error)?;
+    tx.commit().await.map_err(database_error)?;
+
+    counter!("blog_syndication_retries_total", "target" => target.as_str()).increment(1);
+    post_syndication(Path(post_slug), State(pool)).await
+}
+
+async fn load_syndication(
+    pool: &PgPool,
+    post_slug: Option<&str>,
+    status: Option<&str>,
+    limit: i64,
+) -> Result<Vec<PostSyndication>, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    let rows = sqlx::query!(
+        r#"
+        WITH posts AS (
+            SELECT p.slug, p.date FROM blog_posts p
+            WHERE ($1::VARCHAR IS NULL OR p.slug = $1)
+              AND EXISTS (
+                SELECT 1 FROM syndication_outbox o
+                WHERE o.post_slug = p.slug AND ($2::VARCHAR IS NULL OR o.status = $2)
+              )
+            ORDER BY p.date DESC
+            LIMIT $3
+        )
+        SELECT o.post_slug, o.target, o.status, jobs.attempts AS "attempts?",
+            CASE WHEN o.status = 'pending' THEN jobs.run_at END AS next_attempt_at,
+            COALESCE(jobs.last_error, o.last_error) AS last_error,
+            o.result, o.updated_at, o.completed_at
+        FROM posts JOIN syndication_outbox o ON o.post_slug = posts.slug
+        LEFT JOIN jobs ON jobs.id = o.job_id
+        ORDER BY posts.date DESC, o.post_slug, o.target
+        "#,
+        post_slug,
+        status,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "load_syndication")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    let mut posts: Vec<PostSyndication> = Vec::new();
+    for row in rows {
+        let job = SyndicationJob {
+            target: row.target,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt_at: row.next_attempt_at,
+            last_error: row.last_error,
+            result: row.result,
+            updated_at: row.updated_at,
+            completed_at: row.completed_at,
+        };
+        match posts.last_mut() {
+            Some(post) if post.post_slug == row.post_slug => post.targets.push(job),
+            _ => posts.push(PostSyndication {
+                post_slug: row.post_slug,
+                targets: vec![job],
+            }),
+        }
+    }
+    Ok(posts)
+}
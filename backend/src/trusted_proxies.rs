@@ -0,0 +1,176 @@
+use std::{fs, net::IpAddr, path::Path, str::FromStr};
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use ipnetwork::IpNetwork;
+use tracing::info;
+
+/// Networks that are allowed to tell us who the real client is
+///
+/// The default covers only loopback, which is where nginx lives in production
+pub const DEFAULT_TRUSTED_PROXIES: &str = "127.0.0.1/32,::1/128";
+
+/// Set of proxy networks whose forwarding headers (`x-forwarded-for`, `x-real-ip`)
+/// are honoured when resolving the client IP address.
+///
+/// Requests coming from any other TCP peer are attributed to the peer itself, so
+/// hitting the backend directly does not allow spoofing the address. The `cf-*`
+/// headers are only honoured when the client connected to a Cloudflare edge.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNetwork>,
+    /// Cloudflare edges, trusted like `networks` and the only proxies that set `cf-*` headers
+    cloudflare: Vec<IpNetwork>,
+}
+
+/// Where the walk of the forwarding chain stopped
+enum Walk {
+    /// First untrusted address and the trusted proxy it connected to
+    Client { client: IpAddr, edge: IpAddr },
+    /// Every hop is a trusted proxy, `edge` is the farthest one
+    Trusted { edge: IpAddr },
+    /// Garbage in the chain - nothing before `edge` can be trusted
+    Broken { edge: IpAddr },
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNetwork>) -> Self {
+        Self {
+            networks,
+            cloudflare: Vec::new(),
+        }
+    }
+
+    /// Also trust the Cloudflare `networks` and the `cf-*` headers of requests they forward
+    pub fn with_cloudflare(mut self, networks: Vec<IpNetwork>) -> Self {
+        self.cloudflare = networks;
+        self
+    }
+
+    /// Load the configuration from `TRUSTED_PROXIES` (comma-separated CIDR list) and
+    /// the optional `TRUSTED_PROXIES_FILE` (Cloudflare ranges, one CIDR per line)
+    pub fn from_env() -> Result<Self> {
+        let list = std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_else(|_| DEFAULT_TRUSTED_PROXIES.to_string());
+        let networks = parse_networks(&list, ',')?;
+
+        let cloudflare = match std::env::var("TRUSTED_PROXIES_FILE") {
+            Ok(path) if !path.is_empty() => load_networks_file(&path)?,
+            _ => Vec::new(),
+        };
+
+        info!(
+            networks = networks.len(),
+            cloudflare = cloudflare.len(),
+            "Trusted proxies loaded"
+        );
+
+        Ok(Self::new(networks).with_cloudflare(cloudflare))
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks
+            .iter()
+            .chain(&self.cloudflare)
+            .any(|network| network.contains(ip))
+    }
+
+    pub fn is_cloudflare(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.cloudflare.iter().any(|network| network.contains(ip))
+    }
+
+    /// Resolve the address of the client that originated the request.
+    ///
+    /// Forwarding headers are only looked at when `peer` is trusted. The
+    /// `x-forwarded-for` chain (or `x-real-ip` when it is missing) is walked from the
+    /// closest hop and the first untrusted address is the client. `cf-connecting-ip`
+    /// is only used when every hop is a trusted proxy and the farthest one is Cloudflare.
+    pub fn resolve_client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        match self.walk(peer, headers) {
+            Walk::Client { client, .. } => client,
+            Walk::Broken { edge } => edge,
+            Walk::Trusted { edge } => Some(edge)
+                .filter(|edge| self.is_cloudflare(*edge))
+                .and_then(|_| header_str(headers, "cf-connecting-ip"))
+                .and_then(parse_ip)
+                .unwrap_or(edge),
+        }
+    }
+
+    /// Whether the client connected to a Cloudflare edge, ie. the `cf-*` headers were
+    /// set by Cloudflare rather than by the client
+    ///
+    /// The chain is walked like in [`Self::resolve_client_ip`], the proxy the client
+    /// connected to has to be in the Cloudflare ranges.
+    pub fn via_cloudflare(&self, peer: IpAddr, headers: &HeaderMap) -> bool {
+        if !self.is_trusted(peer) {
+            return false;
+        }
+
+        match self.walk(peer, headers) {
+            Walk::Client { edge, .. } | Walk::Trusted { edge } => self.is_cloudflare(edge),
+            Walk::Broken { .. } => false,
+        }
+    }
+
+    fn walk(&self, peer: IpAddr, headers: &HeaderMap) -> Walk {
+        let hops: Vec<Option<IpAddr>> = match header_str(headers, "x-forwarded-for") {
+            Some(forwarded_for) => forwarded_for
+                .split(',')
+                .map(|hop| parse_ip(hop.trim()))
+                .collect(),
+            None => header_str(headers, "x-real-ip")
+                .map(parse_ip)
+                .into_iter()
+                .collect(),
+        };
+
+        let mut closest = peer;
+        for hop in hops.into_iter().rev() {
+            match hop {
+                Some(ip) if !self.is_trusted(ip) => {
+                    return Walk::Client {
+                        client: ip,
+                        edge: closest,
+                    }
+                }
+                Some(ip) => closest = ip,
+                None => return Walk::Broken { edge: closest },
+            }
+        }
+        Walk::Trusted { edge: closest }
+    }
+}
+
+/// Parse a list of CIDRs (or bare addresses) separated by `separator`
+pub fn parse_networks(list: &str, separator: char) -> Result<Vec<IpNetwork>> {
+    list.split(separator)
+        .map(|entry| entry.split('#').next().unwrap_or_default().trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            IpNetwork::from_str(entry).with_context(|| format!("Invalid trusted proxy: {entry}"))
+        })
+        .collect()
+}
+
+/// Read a newline separated list of CIDRs, lines starting with `#` are ignored
+pub fn load_networks_file(path: impl AsRef<Path>) -> Result<Vec<IpNetwork>> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read trusted proxies file {}", path.display()))?;
+    parse_networks(&content, '\n')
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    IpAddr::from_str(value).ok().map(|ip| ip.to_canonical())
+}
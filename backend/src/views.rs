@@ -0,0 +1,299 @@
+use crate::{
+    correlation::CorrelationContext,
+    likes::{abuse::is_automated_user_agent, client_ip_hash},
+    referrers::{referrer_id, Referrer, ReferrerQuery},
+};
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Json,
+};
+use chrono::{Duration, NaiveDate, Utc};
+use hmac::{Hmac, Mac};
+use hyperloglog::HyperLogLog;
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tracing::{instrument, warn};
+
+pub mod hyperloglog;
+
+/// Maps visitors to the hashes counted by the unique visitor sketches
+///
+/// The hashed client IP is keyed with a secret salt, so the hashes can not be
+/// recomputed from a list of IPs without it
+#[derive(Clone)]
+pub struct VisitorHasher {
+    salt: String,
+}
+
+impl VisitorHasher {
+    pub fn new(salt: impl Into<String>) -> Self {
+        Self { salt: salt.into() }
+    }
+
+    /// Reads `VIEWS_SALT`
+    pub fn from_env() -> Self {
+        let salt = std::env::var("VIEWS_SALT").unwrap_or_else(|_| {
+            warn!("VIEWS_SALT not set - visitors are counted again after a restart");
+            format!("{}{}", uuid::Uuid::new_v4(), uuid::Uuid::new_v4())
+        });
+        Self::new(salt)
+    }
+
+    pub fn hash(&self, user_ip_hash: &str) -> u64 {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.salt.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(user_ip_hash.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        u64::from_be_bytes(digest[..8].try_into().expect("digest has 32 bytes"))
+    }
+
+    /// Visitor of the analytics events on `day`
+    ///
+    /// Changes every day, so the id outlives the hashed IP without linking the visits
+    /// of different days. Periods longer than a day count a returning visitor once per
+    /// day, like Plausible does.
+    pub fn daily_id(&self, day: NaiveDate, user_ip_hash: &str) -> i64 {
+        self.hash(&format!("{day}:{user_ip_hash}")) as i64
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewStatsQuery {
+    /// Number of days, including today, the stats cover
+    pub days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DailyViews {
+    pub day: NaiveDate,
+    pub views: i64,
+    pub unique_visitors: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ViewStats {
+    pub post_slug: String,
+    pub since: NaiveDate,
+    pub views: i64,
+    /// Estimated distinct visitors over the whole period
+    pub unique_visitors: u64,
+    pub likes: i64,
+    /// Likes divided by views, absent without views
+    pub likes_per_view: Option<f64>,
+    pub daily: Vec<DailyViews>,
+}
+
+/// Beacon sent by the post page when it is read
+///
+/// Always answers `204` for existing posts, views from scripts and crawlers are
+/// silently ignored
+#[instrument(skip(pool, visitors, correlation_ctx, headers))]
+pub async fn record_view(
+    Path(post_slug): Path<String>,
+    Query(referrer_query): Query<ReferrerQuery>,
+    State(pool): State<PgPool>,
+    State(visitors): State<Arc<VisitorHasher>>,
+    Extension(correlation_ctx): Extension<CorrelationContext>,
+    headers: HeaderMap,
+) -> StatusCode {
+    let start_time = std::time::Instant::now();
+    counter!("blog_views_requests_total").increment(1);
+
+    let user_agent = headers.get("user-agent").and_then(|h| h.to_str().ok());
+    if is_automated_user_agent(user_agent) {
+        counter!("blog_views_ignored_total", "reason" => "bot_user_agent").increment(1);
+        return StatusCode::NO_CONTENT;
+    }
+
+    let visitor = visitors.hash(&client_ip_hash(&correlation_ctx));
+    let referrer = Referrer::from_request(&headers, referrer_query.referrer.as_deref());
+    let result = insert_view(
+        &pool,
+        &post_slug,
+        Utc::now().date_naive(),
+        visitor,
+        &referrer,
+    )
+    .await;
+
+    let status = match result {
+        Ok(()) => {
+            counter!("blog_views_recorded_total").increment(1);
+            StatusCode::NO_CONTENT
+        }
+        Err(sqlx::Error::Database(db_err)) if db_err.is_foreign_key_violation() => {
+            counter!("blog_views_errors_total", "reason" => "post_not_found").increment(1);
+            StatusCode::NOT_FOUND
+        }
+        Err(e) => {
+            warn!(
+                error = %e,
+                post_slug = %post_slug,
+                correlation_id = %correlation_ctx.correlation_id,
+                "Database error recording view"
+            );
+            counter!("blog_views_errors_total", "reason" => "database_error").increment(1);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+
+    histogram!("blog_views_request_duration_ms", "status" => status.as_str().to_string())
+        .record(start_time.elapsed().as_millis() as f64);
+    status
+}
+
+/// Count the view and add the visitor to the sketch of the day, views with a known
+/// origin are also counted per referrer
+///
+/// The row is locked while its sketch is updated, concurrent views of the same post
+/// wait for each other
+#[instrument(skip(pool))]
+pub async fn insert_view(
+    pool: &PgPool,
+    post_slug: &str,
+    day: NaiveDate,
+    visitor: u64,
+    referrer: &Referrer,
+) -> Result<(), sqlx::Error> {
+    let start_time = std::time::Instant::now();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+        "INSERT INTO post_views (post_slug, day) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        post_slug,
+        day
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let stored = sqlx::query_scalar!(
+        "SELECT visitors FROM post_views WHERE post_slug = $1 AND day = $2 FOR UPDATE",
+        post_slug,
+        day
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut sketch = HyperLogLog::from_bytes(&stored);
+    if sketch.insert(visitor) {
+        sqlx::query!(
+            "UPDATE post_views SET views = views + 1, visitors = $3 WHERE post_slug = $1 AND day = $2",
+            post_slug,
+            day,
+            sketch.as_bytes()
+        )
+        .execute(&mut *tx)
+        .await?;
+    } else {
+        sqlx::query!(
+            "UPDATE post_views SET views = views + 1 WHERE post_slug = $1 AND day = $2",
+            post_slug,
+            day
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    if let Some(referrer_id) = referrer_id(&mut *tx, referrer).await? {
+        sqlx::query!(
+            r#"
+            INSERT INTO post_referrer_views (post_slug, day, referrer_id, views)
+            VALUES ($1, $2, $3, 1)
+            ON CONFLICT (post_slug, day, referrer_id)
+            DO UPDATE SET views = post_referrer_views.views + 1
+            "#,
+            post_slug,
+            day,
+            referrer_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "insert_view")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    Ok(())
+}
+
+/// Views, unique visitors and likes of a post over the last days, from the admin API
+#[instrument(skip(pool))]
+pub async fn view_stats(
+    Path(post_slug): Path<String>,
+    Query(query): Query<ViewStatsQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<ViewStats>, StatusCode> {
+    let days = query.days.unwrap_or(30).clamp(1, 366);
+    let since = Utc::now().date_naive() - Duration::days(days - 1);
+
+    let stats = load_view_stats(&pool, &post_slug, since)
+        .await
+        .map_err(|e| {
+            warn!(error = %e, post_slug = %post_slug, "Database error loading view stats");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(stats))
+}
+
+async fn load_view_stats(
+    pool: &PgPool,
+    post_slug: &str,
+    since: NaiveDate,
+) -> Result<ViewStats, sqlx::Error> {
+    let start_time = std::time::Instant::now();
+
+    let rows = sqlx::query!(
+        "SELECT day, views, visitors FROM post_views WHERE post_slug = $1 AND day >= $2 ORDER BY day",
+        post_slug,
+        since
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let likes = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM blog_post_likes
+        WHERE post_slug = $1 AND liked_at >= $2::date
+        "#,
+        post_slug,
+        since
+    )
+    .fetch_one(pool)
+    .await?;
+
+    histogram!("blog_database_query_duration_ms", "query" => "view_stats")
+        .record(start_time.elapsed().as_millis() as f64);
+
+    // Sketches of all days merged give the distinct visitors of the whole period
+    let mut period = HyperLogLog::default();
+    let daily: Vec<DailyViews> = rows
+        .into_iter()
+        .map(|row| {
+            let sketch = HyperLogLog::from_bytes(&row.visitors);
+            period.merge(&sketch);
+            DailyViews {
+                day: row.day,
+                views: row.views,
+                unique_visitors: sketch.estimate(),
+            }
+        })
+        .collect();
+    let views = daily.iter().map(|day| day.views).sum();
+
+    Ok(ViewStats {
+        post_slug: post_slug.to_string(),
+        since,
+        views,
+        unique_visitors: period.estimate(),
+        likes,
+        likes_per_view: (views > 0).then(|| likes as f64 / views as f64),
+        daily,
+    })
+}
@@ -0,0 +1,78 @@
+/// Bits of the hash used to pick a register
+pub const PRECISION: u32 = 10;
+/// Number of one-byte registers, standard error of the estimate is about 1.04 / sqrt(REGISTERS)
+pub const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog sketch estimating the number of distinct 64-bit hashes
+///
+/// Only the register maxima are kept, so the hashes that went in can not be recovered.
+/// The registers are stored as they are in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// Registers of a stored sketch, anything but `REGISTERS` bytes gives an empty sketch
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        if bytes.len() != REGISTERS {
+            return Self::default();
+        }
+        Self {
+            registers: bytes.to_vec(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Add a hash to the sketch, returns whether any register changed
+    pub fn insert(&mut self, hash: u64) -> bool {
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // Position of the first set bit in the remaining bits, capped when all are zero
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Union with another sketch
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimated number of distinct hashes inserted
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&register| 2f64.powi(-(register as i32)))
+            .sum();
+        let raw = alpha * m * m / sum;
+
+        // Small cardinalities are estimated better by counting the empty registers
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Json,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use metrics::{counter, histogram};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{PgConnection, PgPool};
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use crate::{
+    hugo_posts::HugoBlogPost,
+    jobs::{self, JobOptions, JobRunner},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Kind of the jobs sending the deliveries
+pub const DELIVERY_JOB: &str = "webhook_delivery";
+/// Longest response body kept in the delivery log
+const MAX_RESPONSE_BODY: usize = 1000;
+
+/// Events endpoints can subscribe to, stored in `webhook_endpoints.events`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    /// A post was ingested for the first time
+    PostPublished,
+    /// An ingested post changed
+    PostUpdated,
+    /// The likes of a post reached a multiple of the endpoint's `like_milestone_every`
+    LikeMilestone,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 3] = [
+        WebhookEvent::PostPublished,
+        WebhookEvent::PostUpdated,
+        WebhookEvent::LikeMilestone,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::PostPublished => "post.published",
+            WebhookEvent::PostUpdated => "post.updated",
+            WebhookEvent::LikeMilestone => "like.milestone",
+        }
+    }
+
+    pub fn parse(event: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.as_str() == event)
+    }
+}
+
+/// `X-Webhook-Signature` of a delivery: `sha256=<hex HMAC-SHA256 of "<timestamp>.<body>">`
+///
+/// The timestamp is sent in `X-Webhook-Timestamp` so receivers can reject replays.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queue a delivery of `event` to every enabled endpoint subscribed to it
+///
+/// Runs in the transaction of the change, so deliveries exist exactly when the change
+/// does. With a `dedupe_key` an endpoint gets the event only once. `total_likes` is
+/// matched against the milestones of the endpoints.
+async fn enqueue(
+    tx: &mut PgConnection,
+    event: WebhookEvent,
+    dedupe_key: Option<&str>,
+    total_likes: Option<i64>,
+    data: Value,
+) -> Result<usize, sqlx::Error> {
+    let ids = sqlx::query_scalar!(
+        r#"
+        INSERT INTO webhook_deliveries (endpoint_id, event, dedupe_key, payload)
+        SELECT id, $1::VARCHAR, $2, $3 FROM webhook_endpoints
+        WHERE enabled AND $1::VARCHAR = ANY(events)
+          AND ($4::BIGINT IS NULL OR $4 % like_milestone_every = 0)
+        ON CONFLICT (endpoint_id, dedupe_key) DO NOTHING
+        RETURNING id
+        "#,
+        event.as_str(),
+        dedupe_key,
+        data,
+        total_likes
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for &id in &ids {
+        let job_id = jobs::enqueue(
+            &mut *tx,
+            DELIVERY_JOB,
+            &json!({ "delivery_id": id }),
+            Utc::now(),
+        )
+        .await?;
+        sqlx::query!(
+            "UPDATE webhook_deliveries SET job_id = $2 WHERE id = $1",
+            id,
+            job_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    if !ids.is_empty() {
+        counter!("blog_webhook_deliveries_queued_total", "event" => event.as_str())
+            .increment(ids.len() as u64);
+    }
+    Ok(ids.len())
+}
+
+/// Queue `post.published` or `post.updated` for an ingested post that is new or changed
+pub async fn post_changed(
+    tx: &mut PgConnection,
+    post: &HugoBlogPost,
+    published: bool,
+) -> Result<usize, sqlx::Error> {
+    let data = json!({
+        "slug": post.slug,
+        "title": post.title,
+        "description": post.description,
+        "date": post.date,
+        "tags": post.tags,
+        "url": post.url.as_str(),
+    });
+    if published {
+        let dedupe_key = format!("post.published:{}", post.slug);
+        enqueue(
+            tx,
+            WebhookEvent::PostPublished,
+            Some(&dedupe_key),
+            None,
+            data,
+        )
+        .await
+    } else {
+        enqueue(tx, WebhookEvent::PostUpdated, None, None, data).await
+    }
+}
+
+/// Queue `like.milestone` when `total_likes` is a milestone of an endpoint
+///
+/// Each milestone of a post is announced once, even when likes are removed and the
+/// post reaches it again.
+pub async fn like_recorded(
+    tx: &mut PgConnection,
+    post_slug: &str,
+    total_likes: i64,
+) -> Result<usize, sqlx::Error> {
+    if total_likes <= 0 {
+        return Ok(0);
+    }
+    let dedupe_key = format!("like.milestone:{post_slug}:{total_likes}");
+    let data = json!({ "post_slug": post_slug, "total_likes": total_likes });
+    enqueue(
+        tx,
+        WebhookEvent::LikeMilestone,
+        Some(&dedupe_key),
+        Some(total_likes),
+        data,
+    )
+    .await
+}
+
+/// Mark the delivery of a `webhook_delivery` job failed once the runner gives up on it
+fn give_up<'c>(
+    tx: &'c mut PgConnection,
+    payload: &'c Value,
+    _error: &'c str,
+) -> Pin<Box<dyn Future<Output = Result<(), sqlx::Error>> + Send + 'c>> {
+    Box::pin(async move {
+        let Some(delivery_id) = payload["delivery_id"].as_i64() else {
+            return Ok(());
+        };
+        let event = sqlx::query_scalar!(
+            r#"
+            UPDATE webhook_deliveries SET status = 'failed', updated_at = NOW()
+            WHERE id = $1 AND status = 'pending'
+            RETURNING event
+            "#,
+            delivery_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(event) = event {
+            counter!("blog_webhook_deliveries_total", "event" => event, "status" => "failed")
+                .increment(1);
+        }
+        Ok(())
+    })
+}
+
+/// Sends the queued deliveries
+pub struct WebhookSender {
+    pool: PgPool,
+    http: reqwest::Client,
+    max_attempts: i32,
+}
+
+impl WebhookSender {
+    /// Reads `WEBHOOK_TIMEOUT_SECS` and `WEBHOOK_MAX_ATTEMPTS`
+    pub fn from_env(pool: PgPool) -> anyhow::Result<Self> {
+        let timeout = std::env::var("WEBHOOK_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(10));
+        let max_attempts = std::env::var("WEBHOOK_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(8);
+        Self::new(pool, timeout, max_attempts)
+    }
+
+    /// Gives up on a delivery after `max_attempts` failed attempts
+    pub fn new(pool: PgPool, timeout: Duration, max_attempts: i32) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .user_agent("blog-backend")
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+
+        Ok(Self {
+            pool,
+            http,
+            max_attempts,
+        })
+    }
+
+    /// Send the deliveries as `webhook_delivery` jobs, retried by the job runner
+    pub fn register(self, jobs: &mut JobRunner) {
+        let options = JobOptions {
+            concurrency: 4,
+            max_attempts: self.max_attempts,
+        };
+        let sender = Arc::new(self);
+        jobs.register(DELIVERY_JOB, options, move |payload| {
+            let sender = sender.clone();
+            async move {
+                let delivery_id = payload["delivery_id"]
+                    .as_i64()
+                    .ok_or_else(|| anyhow::anyhow!("Job without a delivery id"))?;
+                sender.deliver(delivery_id).await
+            }
+        });
+        jobs.on_give_up(DELIVERY_JOB, give_up);
+    }
+
+    /// Send one attempt of a delivery and log its outcome
+    ///
+    /// The job counts the attempts, a failed one leaves the delivery pending until the
+    /// job is retried or given up.
+    #[instrument(skip(self))]
+    async fn deliver(&self, delivery_id: i64) -> anyhow::Result<()> {
+        let Some(delivery) = sqlx::query!(
+            r#"
+            SELECT d.event, d.payload, d.status, d.created_at, e.url, e.secret
+            FROM webhook_deliveries d JOIN webhook_endpoints e ON e.id = d.endpoint_id
+            WHERE d.id = $1
+            "#,
+            delivery_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            // The endpoint was deleted with its deliveries
+            return Ok(());
+        };
+        if delivery.status != "pending" {
+            return Ok(());
+        }
+
+        let body = json!({
+            "id": delivery_id,
+            "event": delivery.event,
+            "created_at": delivery.created_at,
+            "data": delivery.payload,
+        })
+        .to_string();
+        let timestamp = Utc::now().timestamp();
+
+        let start_time = std::time::Instant::now();
+        let response = self
+            .http
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("x-webhook-id", delivery_id.to_string())
+            .header("x-webhook-event", &delivery.event)
+            .header("x-webhook-timestamp", timestamp.to_string())
+            .header(
+                "x-webhook-signature",
+                signature(&delivery.secret, timestamp, &body),
+            )
+            .body(body)
+            .send()
+            .await;
+        let duration_ms = start_time.elapsed().as_millis() as i64;
+        histogram!("blog_webhook_delivery_duration_ms").record(duration_ms as f64);
+
+        let (response_status, response_body, error) = match response {
+            Ok(response) => {
+                let status = response.status();
+                let text: String = response
+                    .text()
+                    .await
+                    .unwrap_or_default()
+                    .chars()
+                    .take(MAX_RESPONSE_BODY)
+                    .collect();
+                let error = (!status.is_success()).then(|| format!("Responded with {status}"));
+                (Some(status.as_u16() as i32), Some(text), error)
+            }
+            Err(e) => (None, None, Some(e.to_string())),
+        };
+
+        let status = if error.is_none() {
+            "delivered"
+        } else {
+            "pending"
+        };
+        sqlx::query!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $2::VARCHAR, response_status = $3, response_body = $4,
+                last_error = $5, duration_ms = $6, updated_at = NOW(),
+                delivered_at = CASE WHEN $2::VARCHAR = 'delivered' THEN NOW() END
+            WHERE id = $1
+            "#,
+            delivery_id,
+            status,
+            response_status,
+            response_body,
+            error,
+            duration_ms
+        )
+        .execute(&self.pool)
+        .await?;
+
+        counter!("blog_webhook_deliveries_total", "event" => delivery.event, "status" => status)
+            .increment(1);
+        match error {
+            None => {
+                info!(url = %delivery.url, "Webhook delivered");
+                Ok(())
+            }
+            Some(error) => Err(anyhow::anyhow!(
+                "Webhook to {} failed: {error}",
+                delivery.url
+            )),
+        }
+    }
+}
+
+/// Endpoint as listed by the admin API, without its secret
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEndpoint {
+    pub id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub like_milestone_every: i32,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    /// Every event when absent
+    pub events: Option<Vec<String>>,
+    pub like_milestone_every: Option<i32>,
+    /// Generated when absent
+    pub secret: Option<String>,
+}
+
+/// Created endpoint with the secret, the only time it is shown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    pub secret: String,
+}
+
+/// Endpoints, from the admin API
+#[instrument(skip(pool))]
+pub async fn list_webhooks(
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<WebhookEndpoint>>, StatusCode> {
+    let endpoints = sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+        SELECT id, url, events AS "events!: Vec<String>", like_milestone_every, enabled, created_at
+        FROM webhook_endpoints ORDER BY id
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error listing webhooks");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(endpoints))
+}
+
+/// Check a new endpoint, returns the events it subscribes to
+pub fn validate_webhook(request: &CreateWebhook) -> Result<Vec<String>, &'static str> {
+    let url = Url::parse(&request.url).map_err(|_| "invalid_url")?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("invalid_url");
+    }
+    if request.like_milestone_every.is_some_and(|every| every < 1) {
+        return Err("invalid_milestone");
+    }
+    if request.secret.as_ref().is_some_and(|s| s.len() < 16) {
+        return Err("secret_too_short");
+    }
+
+    match &request.events {
+        None => Ok(WebhookEvent::ALL
+            .iter()
+            .map(|e| e.as_str().to_string())
+            .collect()),
+        Some(events) if events.is_empty() => Err("no_events"),
+        Some(events) => events
+            .iter()
+            .map(|event| {
+                WebhookEvent::parse(event)
+                    .map(|e| e.as_str().to_string())
+                    .ok_or("unknown_event")
+            })
+            .collect(),
+    }
+}
+
+/// Add an endpoint, from the admin API
+#[instrument(skip(pool, request), fields(url = %request.url))]
+pub async fn create_webhook(
+    State(pool): State<PgPool>,
+    Json(request): Json<CreateWebhook>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), (StatusCode, &'static str)> {
+    let events = validate_webhook(&request).map_err(|reason| {
+        counter!("blog_webhook_rejections_total", "reason" => reason).increment(1);
+        (StatusCode::BAD_REQUEST, reason)
+    })?;
+    let secret = request
+        .secret
+        .unwrap_or_else(|| hex::encode(rand::random::<[u8; 32]>()));
+
+    let endpoint = sqlx::query_as!(
+        WebhookEndpoint,
+        r#"
+        INSERT INTO webhook_endpoints (url, secret, events, like_milestone_every)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, url, events AS "events!: Vec<String>", like_milestone_every, enabled, created_at
+        "#,
+        request.url,
+        secret,
+        &events as &[String],
+        request.like_milestone_every.unwrap_or(100)
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error creating a webhook");
+        (StatusCode::INTERNAL_SERVER_ERROR, "database_error")
+    })?;
+
+    info!(id = endpoint.id, "Webhook created");
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook { endpoint, secret }),
+    ))
+}
+
+/// Remove an endpoint and its delivery log, from the admin API
+#[instrument(skip(pool))]
+pub async fn delete_webhook(Path(id): Path<i32>, State(pool): State<PgPool>) -> StatusCode {
+    match sqlx::query!("DELETE FROM webhook_endpoints WHERE id = $1", id)
+        .execute(&pool)
+        .await
+    {
+        Ok(result) if result.rows_affected() > 0 => StatusCode::NO_CONTENT,
+        Ok(_) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            warn!(error = %e, "Database error deleting a webhook");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Delivery as listed by the admin API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint_id: i32,
+    pub event: String,
+    pub payload: Value,
+    /// `pending`, `delivered` or `failed`
+    pub status: String,
+    /// Attempts of its job, unknown once finished jobs were deleted
+    pub attempts: Option<i32>,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub last_error: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    pub endpoint_id: Option<i32>,
+    pub event: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
+/// Latest deliveries, from the admin API
+#[instrument(skip(pool))]
+pub async fn list_deliveries(
+    Query(query): Query<DeliveriesQuery>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<WebhookDelivery>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let deliveries = sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT d.id, d.endpoint_id, d.event, d.payload, d.status, jobs.attempts AS "attempts?",
+            d.response_status, d.response_body, d.last_error, d.duration_ms, d.created_at,
+            d.delivered_at
+        FROM webhook_deliveries d LEFT JOIN jobs ON jobs.id = d.job_id
+        WHERE ($1::INTEGER IS NULL OR d.endpoint_id = $1)
+          AND ($2::VARCHAR IS NULL OR d.event = $2)
+          AND ($3::VARCHAR IS NULL OR d.status = $3)
+        ORDER BY d.id DESC
+        LIMIT $4
+        "#,
+        query.endpoint_id,
+        query.event,
+        query.status,
+        limit
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        warn!(error = %e, "Database error listing webhook deliveries");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(deliveries))
+}
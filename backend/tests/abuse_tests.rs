@@ -0,0 +1,153 @@
+use backend::likes::abuse::{
+    AbuseConfig, AbusePipeline, LikeAttempt, LikeTokens, ProofOfWorkCheck, UserAgentCheck,
+};
+use chrono::{Duration, Utc};
+
+const BROWSER_UA: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:131.0) Gecko/20100101 Firefox/131.0";
+
+fn attempt<'a>(token: Option<&'a str>, user_agent: Option<&'a str>) -> LikeAttempt<'a> {
+    LikeAttempt {
+        post_slug: "test-post",
+        user_ip_hash: "abc",
+        user_agent,
+        token,
+        proof_of_work: None,
+        now: Utc::now(),
+    }
+}
+
+fn config() -> AbuseConfig {
+    AbuseConfig {
+        token_secret: "secret".to_string(),
+        token_ttl: Duration::minutes(10),
+        require_token: true,
+        check_user_agent: true,
+        pow_difficulty: 0,
+    }
+}
+
+#[test]
+fn test_like_token_roundtrip() {
+    let tokens = LikeTokens::new("secret", Duration::minutes(10));
+    let now = Utc::now();
+    let token = tokens.issue("test-post", "abc", now);
+
+    assert!(tokens.verify(&token, "test-post", "abc", now).is_ok());
+
+    // Bound to the post and the client
+    let other_post = tokens.verify(&token, "other-post", "abc", now);
+    assert_eq!(other_post.unwrap_err().reason, "invalid_token");
+    let other_client = tokens.verify(&token, "test-post", "def", now);
+    assert_eq!(other_client.unwrap_err().reason, "invalid_token");
+
+    // Signed with a different secret
+    let foreign = LikeTokens::new("other", Duration::minutes(10)).issue("test-post", "abc", now);
+    assert!(tokens.verify(&foreign, "test-post", "abc", now).is_err());
+}
+
+#[test]
+fn test_like_token_expires() {
+    let tokens = LikeTokens::new("secret", Duration::minutes(10));
+    let issued = Utc::now();
+    let token = tokens.issue("test-post", "abc", issued);
+
+    let rejection = tokens
+        .verify(&token, "test-post", "abc", issued + Duration::minutes(11))
+        .unwrap_err();
+    assert_eq!(rejection.reason, "expired_token");
+}
+
+#[test]
+fn test_user_agent_heuristics() {
+    let pipeline = AbusePipeline::new(LikeTokens::new("secret", Duration::minutes(1)))
+        .with_check(UserAgentCheck);
+
+    assert!(pipeline.check(&attempt(None, Some(BROWSER_UA))).is_ok());
+    assert_eq!(
+        pipeline.check(&attempt(None, None)).unwrap_err().reason,
+        "missing_user_agent"
+    );
+    assert_eq!(
+        pipeline
+            .check(&attempt(None, Some("  ")))
+            .unwrap_err()
+            .reason,
+        "missing_user_agent"
+    );
+    assert_eq!(
+        pipeline
+            .check(&attempt(None, Some("curl/8.4.0")))
+            .unwrap_err()
+            .reason,
+        "bot_user_agent"
+    );
+    assert_eq!(
+        pipeline
+            .check(&attempt(
+                None,
+                Some("Mozilla/5.0 (compatible; Googlebot/2.1)")
+            ))
+            .unwrap_err()
+            .reason,
+        "bot_user_agent"
+    );
+}
+
+#[test]
+fn test_pipeline_requires_token_from_get_likes() {
+    let pipeline = AbusePipeline::from_config(&config());
+
+    let rejection = pipeline
+        .check(&attempt(None, Some(BROWSER_UA)))
+        .unwrap_err();
+    assert_eq!(rejection.reason, "missing_token");
+
+    let token = pipeline.issue_token("test-post", "abc");
+    assert!(pipeline
+        .check(&attempt(Some(&token), Some(BROWSER_UA)))
+        .is_ok());
+
+    // User agent check runs first
+    let rejection = pipeline
+        .check(&attempt(Some(&token), Some("wget")))
+        .unwrap_err();
+    assert_eq!(rejection.reason, "bot_user_agent");
+}
+
+#[test]
+fn test_proof_of_work() {
+    let pipeline = AbusePipeline::from_config(&AbuseConfig {
+        pow_difficulty: 8,
+        ..config()
+    });
+    assert_eq!(pipeline.pow_difficulty(), 8);
+
+    let token = pipeline.issue_token("test-post", "abc");
+    let rejection = pipeline
+        .check(&attempt(Some(&token), Some(BROWSER_UA)))
+        .unwrap_err();
+    assert_eq!(rejection.reason, "missing_proof_of_work");
+
+    let check = ProofOfWorkCheck::new(8);
+    let nonce = (0u64..)
+        .map(|n| n.to_string())
+        .find(|nonce| check.is_solution(&token, nonce))
+        .unwrap();
+
+    let solved = LikeAttempt {
+        proof_of_work: Some(&nonce),
+        ..attempt(Some(&token), Some(BROWSER_UA))
+    };
+    assert!(pipeline.check(&solved).is_ok());
+
+    let wrong = LikeAttempt {
+        proof_of_work: Some("not-a-solution-for-sure"),
+        ..attempt(Some(&token), Some(BROWSER_UA))
+    };
+    if !check.is_solution(&token, "not-a-solution-for-sure") {
+        assert_eq!(
+            pipeline.check(&wrong).unwrap_err().reason,
+            "invalid_proof_of_work"
+        );
+    }
+}
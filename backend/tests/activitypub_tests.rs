@@ -0,0 +1,551 @@
+use axum::{
+    http::{HeaderMap, HeaderValue, Method},
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use axum_test::TestServer;
+use backend::{
+    activitypub::{
+        self, create_activity, object_id,
+        remote::{check_actor_key, check_remote_url, is_public_address},
+        signatures::{self, SignatureHeader},
+        webfinger_document, FederatedPost, Federation, RemoteActor,
+    },
+    correlation::correlation_middleware,
+    trusted_proxies::TrustedProxies,
+};
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use url::Url;
+
+mod test_utils;
+
+fn signed_post(body: &[u8], now: SystemTime) -> (HeaderMap, SignatureHeader) {
+    let url: Url = "https://blog.example.com/api/activitypub/inbox"
+        .parse()
+        .unwrap();
+    let mut headers = HeaderMap::new();
+    signatures::sign_request(
+        &test_utils::test_key(),
+        "https://social.example.com/users/reader#main-key",
+        &Method::POST,
+        &url,
+        &mut headers,
+        Some(body),
+        now,
+    )
+    .unwrap();
+    let signature =
+        SignatureHeader::parse(headers.get("signature").unwrap().to_str().unwrap()).unwrap();
+    (headers, signature)
+}
+
+#[test]
+fn test_parse_signature_header() {
+    let header = SignatureHeader::parse(
+        r#"keyId="https://social.example.com/users/reader#main-key",algorithm="rsa-sha256",headers="(request-target) Host date",signature="c2lnbmF0dXJl""#,
+    )
+    .unwrap();
+    assert_eq!(
+        header.key_id,
+        "https://social.example.com/users/reader#main-key"
+    );
+    assert_eq!(header.headers, ["(request-target)", "host", "date"]);
+    assert_eq!(header.signature, b"signature");
+
+    // Only the date is signed when the headers are not listed
+    let header = SignatureHeader::parse(r#"keyId="key",signature="c2lnbmF0dXJl""#).unwrap();
+    assert_eq!(header.headers, ["date"]);
+
+    assert_eq!(
+        SignatureHeader::parse(r#"keyId="key",algorithm="hmac-sha256",signature="c2ln""#),
+        Err("unsupported_algorithm")
+    );
+    assert_eq!(
+        SignatureHeader::parse(r#"signature="c2ln""#),
+        Err("malformed_signature")
+    );
+    assert_eq!(
+        SignatureHeader::parse(r#"keyId=key"#),
+        Err("malformed_signature")
+    );
+
+    // Numbers may come without quotes
+    let header = SignatureHeader::parse(
+        r#"keyId="key",headers="(request-target) host (created)",created=1402170695,expires="1402170995",signature="c2ln""#,
+    )
+    .unwrap();
+    assert_eq!(header.created, Some(1402170695));
+    assert_eq!(header.signature, b"sig");
+    assert_eq!(
+        SignatureHeader::parse(r#"keyId="key",created=yesterday,signature="c2ln""#),
+        Err("malformed_signature")
+    );
+}
+
+#[test]
+fn test_sign_and_verify_roundtrip() {
+    let key = test_utils::test_key();
+    let body = br#"{"type":"Follow"}"#;
+    let now = SystemTime::now();
+    let (headers, signature) = signed_post(body, now);
+
+    assert_eq!(
+        signatures::verify_request(
+            &key.to_public_key(),
+            &signature,
+            &Method::POST,
+            "/api/activitypub/inbox",
+            &headers,
+            body,
+            now,
+        ),
+        Ok(())
+    );
+
+    // The path nginx forwarded, not the one that was signed
+    assert_eq!(
+        signatures::verify_request(
+            &key.to_public_key(),
+            &signature,
+            &Method::POST,
+            "/activitypub/inbox",
+            &headers,
+            body,
+            now,
+        ),
+        Err("invalid_signature")
+    );
+}
+
+#[test]
+fn test_verify_rejects_tampered_requests() {
+    let key = test_utils::test_key();
+    let body = br#"{"type":"Follow"}"#;
+    let now = SystemTime::now();
+    let (headers, signature) = signed_post(body, now);
+    let verify = |headers: &HeaderMap, body: &[u8], now: SystemTime| {
+        signatures::verify_request(
+            &key.to_public_key(),
+            &signature,
+            &Method::POST,
+            "/api/activitypub/inbox",
+            headers,
+            body,
+            now,
+        )
+    };
+
+    assert_eq!(
+        verify(&headers, br#"{"type":"Undo"}"#, now),
+        Err("digest_mismatch")
+    );
+    assert_eq!(
+        verify(&headers, body, now + Duration::from_secs(13 * 60 * 60)),
+        Err("expired_signature")
+    );
+
+    // A body with a matching digest but not the signed one
+    let other_body = br#"{"type":"Undo"}"#;
+    let mut tampered = headers.clone();
+    tampered.insert(
+        "digest",
+        HeaderValue::from_str(&signatures::digest(other_body)).unwrap(),
+    );
+    assert_eq!(verify(&tampered, other_body, now), Err("invalid_signature"));
+
+    let mut without_date = headers.clone();
+    without_date.remove("date");
+    assert_eq!(verify(&without_date, body, now), Err("invalid_date"));
+}
+
+/// Signature over `names` by the test key, as a server signing `(created)` makes it
+fn signature_over(names: &[&str], headers: &HeaderMap, created: Option<u64>) -> SignatureHeader {
+    use rsa::{
+        pkcs1v15::SigningKey,
+        sha2::Sha256,
+        signature::{SignatureEncoding, Signer},
+    };
+
+    let message = signatures::signing_string(
+        &Method::POST,
+        "/api/activitypub/inbox",
+        headers,
+        names,
+        created,
+    )
+    .unwrap();
+    SignatureHeader {
+        key_id: "https://social.example.com/users/reader#main-key".to_string(),
+        headers: names.iter().map(|name| name.to_string()).collect(),
+        created,
+        signature: SigningKey::<Sha256>::new(test_utils::test_key())
+            .sign(message.as_bytes())
+            .to_vec(),
+    }
+}
+
+#[test]
+fn test_verify_requires_a_signed_host_and_a_recent_creation() {
+    let key = test_utils::test_key();
+    let body = br#"{"type":"Follow"}"#;
+    let now = SystemTime::now();
+    let (headers, _) = signed_post(body, now);
+    let verify = |signature: &SignatureHeader, now: SystemTime| {
+        signatures::verify_request(
+            &key.to_public_key(),
+            signature,
+            &Method::POST,
+            "/api/activitypub/inbox",
+            &headers,
+            body,
+            now,
+        )
+    };
+
+    // Valid for any server without the host
+    let without_host = signature_over(&["(request-target)", "date", "digest"], &headers, None);
+    assert_eq!(
+        verify(&without_host, now),
+        Err("insufficient_signed_headers")
+    );
+
+    let created = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let names = ["(request-target)", "host", "(created)", "digest"];
+    let with_created = signature_over(&names, &headers, Some(created));
+    assert_eq!(verify(&with_created, now), Ok(()));
+    assert_eq!(
+        verify(&with_created, now + Duration::from_secs(13 * 60 * 60)),
+        Err("expired_signature")
+    );
+    assert_eq!(
+        verify(&with_created, now - Duration::from_secs(13 * 60 * 60)),
+        Err("expired_signature")
+    );
+    let without_created = SignatureHeader {
+        created: None,
+        ..with_created.clone()
+    };
+    assert_eq!(verify(&without_created, now), Err("invalid_created"));
+    let far_future = signature_over(&names, &headers, Some(u64::MAX));
+    assert_eq!(verify(&far_future, now), Err("invalid_created"));
+}
+
+#[test]
+fn test_parse_public_key_formats() {
+    use rsa::{
+        pkcs1::EncodeRsaPublicKey,
+        pkcs8::{EncodePublicKey, LineEnding},
+    };
+
+    let public_key = test_utils::test_key().to_public_key();
+    let spki = public_key.to_public_key_pem(LineEnding::LF).unwrap();
+    let pkcs1 = public_key.to_pkcs1_pem(LineEnding::LF).unwrap();
+
+    assert_eq!(signatures::parse_public_key(&spki), Ok(public_key.clone()));
+    assert_eq!(signatures::parse_public_key(&pkcs1), Ok(public_key));
+    assert_eq!(
+        signatures::parse_public_key("not a key"),
+        Err("invalid_public_key")
+    );
+}
+
+#[test]
+fn test_webfinger_document() {
+    let config = test_utils::test_activitypub_config();
+
+    let document = webfinger_document(&config, "acct:blog@blog.example.com").unwrap();
+    assert_eq!(document["subject"], "acct:blog@blog.example.com");
+    assert_eq!(
+        document["links"][0]["href"],
+        "https://blog.example.com/api/activitypub/actor"
+    );
+    assert!(webfinger_document(&config, "acct:someone@blog.example.com").is_none());
+    assert!(webfinger_document(&config, "acct:blog@other.example.com").is_none());
+}
+
+#[test]
+fn test_create_activity() {
+    let config = test_utils::test_activitypub_config();
+    let post = FederatedPost {
+        slug: "rust-tips".to_string(),
+        title: "Rust <tips>".to_string(),
+        description: Some("Tips & tricks".to_string()),
+        date: Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap(),
+        tags: Some("rust, Nix OS".to_string()),
+        url: "https://blog.example.com/posts/rust-tips/".to_string(),
+    };
+
+    let activity = create_activity(&config, &post);
+    assert_eq!(activity["type"], "Create");
+    assert_eq!(
+        activity["actor"],
+        "https://blog.example.com/api/activitypub/actor"
+    );
+    let article = &activity["object"];
+    assert_eq!(
+        article["id"],
+        "https://blog.example.com/api/activitypub/posts/rust-tips"
+    );
+    assert_eq!(article["url"], "https://blog.example.com/posts/rust-tips/");
+    assert_eq!(article["published"], "2024-01-02T03:04:05+00:00");
+    assert!(article["content"]
+        .as_str()
+        .unwrap()
+        .contains("Rust &lt;tips&gt;</a></p><p>Tips &amp; tricks</p>"));
+    assert_eq!(article["tag"][1]["name"], "#NixOS");
+    assert_eq!(
+        article["cc"][0],
+        "https://blog.example.com/api/activitypub/followers"
+    );
+}
+
+#[test]
+fn test_object_id() {
+    assert_eq!(
+        object_id(&json!("https://a.example/1")),
+        Some("https://a.example/1")
+    );
+    assert_eq!(
+        object_id(&json!({ "id": "https://a.example/1", "type": "Follow" })),
+        Some("https://a.example/1")
+    );
+    assert_eq!(object_id(&json!(null)), None);
+}
+
+#[tokio::test]
+async fn test_actor_and_webfinger_endpoints() {
+    let app = Router::new()
+        .route("/.well-known/webfinger", get(activitypub::webfinger))
+        .route("/activitypub/actor", get(activitypub::actor))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app).unwrap();
+
+    let response = server
+        .get("/.well-known/webfinger")
+        .add_query_param("resource", "acct:blog@blog.example.com")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header("content-type"), "application/jrd+json");
+
+    server
+        .get("/.well-known/webfinger")
+        .add_query_param("resource", "acct:nobody@blog.example.com")
+        .await
+        .assert_status_not_found();
+
+    let response = server.get("/activitypub/actor").await;
+    assert_eq!(response.header("content-type"), "application/activity+json");
+    let actor: Value = serde_json::from_str(&response.text()).unwrap();
+    assert_eq!(actor["preferredUsername"], "blog");
+    assert_eq!(
+        actor["publicKey"]["id"],
+        "https://blog.example.com/api/activitypub/actor#main-key"
+    );
+    let public_key =
+        signatures::parse_public_key(actor["publicKey"]["publicKeyPem"].as_str().unwrap()).unwrap();
+    assert_eq!(public_key, test_utils::test_key().to_public_key());
+}
+
+#[tokio::test]
+async fn test_inbox_rejects_unsigned_activities() {
+    let app = Router::new()
+        .route("/activitypub/inbox", post(activitypub::inbox))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies::default()),
+            correlation_middleware,
+        ))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>()).unwrap();
+
+    let response = server
+        .post("/activitypub/inbox")
+        .json(&json!({
+            "type": "Follow",
+            "actor": "https://social.example.com/users/reader",
+            "object": "https://blog.example.com/api/activitypub/actor"
+        }))
+        .await;
+    response.assert_status_unauthorized();
+
+    let response = server.post("/activitypub/inbox").text("not json").await;
+    response.assert_status_bad_request();
+}
+
+#[test]
+fn test_federation_public_path() {
+    let federation = Federation::new(
+        test_utils::test_activitypub_config(),
+        test_utils::test_key(),
+    )
+    .unwrap();
+    assert_eq!(
+        federation.config.public_path("/activitypub/inbox"),
+        "/api/activitypub/inbox"
+    );
+}
+
+#[test]
+fn test_article_slug() {
+    let config = test_utils::test_activitypub_config();
+
+    assert_eq!(
+        config.article_slug("https://blog.example.com/api/activitypub/posts/rust-tips"),
+        Some("rust-tips")
+    );
+    assert_eq!(config.article_slug(&config.article_id("nix")), Some("nix"));
+    assert_eq!(
+        config.article_slug("https://blog.example.com/api/activitypub/posts/"),
+        None
+    );
+    assert_eq!(
+        config.article_slug("https://blog.example.com/api/activitypub/posts/a/b"),
+        None
+    );
+    assert_eq!(
+        config.article_slug("https://other.example.com/api/activitypub/posts/rust-tips"),
+        None
+    );
+}
+
+#[test]
+fn test_fediverse_reactions() {
+    assert_eq!(activitypub::fediverse_reaction("Like"), Some("favourite"));
+    assert_eq!(activitypub::fediverse_reaction("Announce"), Some("boost"));
+    assert_eq!(activitypub::fediverse_reaction("Follow"), None);
+}
+
+fn remote_actor(id: &str, key_id: &str, owner: &str) -> RemoteActor {
+    serde_json::from_value(json!({
+        "id": id,
+        "type": "Person",
+        "inbox": format!("{id}/inbox"),
+        "publicKey": {
+            "id": key_id,
+            "owner": owner,
+            "publicKeyPem": "-----BEGIN PUBLIC KEY-----"
+        }
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_actor_must_own_the_signing_key() {
+    let alice = "https://mastodon.social/users/alice";
+    let key_id = "https://mastodon.social/users/alice#main-key";
+    assert_eq!(
+        check_actor_key(&remote_actor(alice, key_id, alice), key_id),
+        Ok(())
+    );
+
+    // A document on another server claiming to be alice, with the key of its author
+    let forged_key = "https://evil.example/alice#main-key";
+    assert_eq!(
+        check_actor_key(&remote_actor(alice, forged_key, alice), forged_key),
+        Err("actor_origin_mismatch")
+    );
+    // The signature names another key than the document has
+    assert_eq!(
+        check_actor_key(&remote_actor(alice, key_id, alice), forged_key),
+        Err("key_mismatch")
+    );
+    // The key belongs to someone else
+    assert_eq!(
+        check_actor_key(
+            &remote_actor(alice, key_id, "https://mastodon.social/users/bob"),
+            key_id
+        ),
+        Err("key_owner_mismatch")
+    );
+}
+
+#[test]
+fn test_private_addresses_are_refused() {
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+    }
+    assert!(is_public_address("1.1.1.1".parse().unwrap()));
+    assert!(is_public_address("2606:4700::1111".parse().unwrap()));
+
+    let url = |s: &str| s.parse::<Url>().unwrap();
+    assert_eq!(
+        check_remote_url(&url("http://127.0.0.1:9091/admin/jobs")),
+        Err("private_address")
+    );
+    assert_eq!(
+        check_remote_url(&url("http://[::1]/actor")),
+        Err("private_address")
+    );
+    assert_eq!(
+        check_remote_url(&url("http://localhost:9091/admin")),
+        Err("private_address")
+    );
+    assert_eq!(
+        check_remote_url(&url("file:///etc/passwd")),
+        Err("unsupported_scheme")
+    );
+    assert_eq!(
+        check_remote_url(&url("https://mastodon.social/users/alice")),
+        Ok(())
+    );
+}
+
+#[tokio::test]
+async fn test_inbox_does_not_fetch_private_key_ids() {
+    let app = Router::new()
+        .route("/activitypub/inbox", post(activitypub::inbox))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies::default()),
+            correlation_middleware,
+        ))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>()).unwrap();
+
+    let body = json!({
+        "type": "Follow",
+        "actor": "http://127.0.0.1:9091/admin/jobs",
+        "object": "https://blog.example.com/api/activitypub/actor"
+    })
+    .to_string();
+    let url: Url = "https://blog.example.com/api/activitypub/inbox"
+        .parse()
+        .unwrap();
+    let mut headers = HeaderMap::new();
+    signatures::sign_request(
+        &test_utils::test_key(),
+        "http://127.0.0.1:9091/admin/jobs#main-key",
+        &Method::POST,
+        &url,
+        &mut headers,
+        Some(body.as_bytes()),
+        SystemTime::now(),
+    )
+    .unwrap();
+
+    let mut request = server.post("/activitypub/inbox").text(body);
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.add_header(name.clone(), value.clone());
+    }
+    request.await.assert_status_unauthorized();
+}
@@ -0,0 +1,83 @@
+use axum::{http::StatusCode, middleware};
+use axum_test::TestServer;
+use backend::{admin, correlation::correlation_middleware, trusted_proxies::TrustedProxies};
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+
+mod test_utils;
+
+fn create_admin_app() -> TestServer {
+    // The pool is never connected, requests below are rejected before touching it
+    let state = test_utils::test_state();
+    let app = admin::router()
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies::default()),
+            correlation_middleware,
+        ))
+        .with_state(state);
+
+    TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>())
+        .expect("Failed to create test server")
+}
+
+#[tokio::test]
+async fn test_admin_erasure_rejects_invalid_hash() {
+    let server = create_admin_app();
+
+    let response = server.delete("/admin/likes/not-a-hash").await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+
+    let response = server
+        .delete(&format!("/admin/likes/{}", "z".repeat(64)))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_stats_reject_invalid_parameters() {
+    let server = create_admin_app();
+
+    let response = server
+        .get("/admin/stats/aggregate?site_id=flakm.com&period=forever")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    response.assert_json(&serde_json::json!({"error": "Invalid period: forever"}));
+
+    let response = server
+        .get("/admin/stats/aggregate?site_id=flakm.com&metrics=visitors,bounce_rate")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+
+    let response = server
+        .get("/admin/stats/breakdown?site_id=flakm.com&property=visit:browser")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_webhook_creation_rejects_invalid_endpoints() {
+    let server = create_admin_app();
+
+    for (body, reason) in [
+        (
+            json!({ "url": "ftp://tools.example.com/hook" }),
+            "invalid_url",
+        ),
+        (
+            json!({ "url": "https://tools.example.com/hook", "events": ["post.deleted"] }),
+            "unknown_event",
+        ),
+        (
+            json!({ "url": "https://tools.example.com/hook", "like_milestone_every": 0 }),
+            "invalid_milestone",
+        ),
+        (
+            json!({ "url": "https://tools.example.com/hook", "secret": "short" }),
+            "secret_too_short",
+        ),
+    ] {
+        let response = server.post("/admin/webhooks").json(&body).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(response.text(), reason);
+    }
+}
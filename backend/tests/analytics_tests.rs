@@ -0,0 +1,275 @@
+use axum::extract::{Query, State};
+use axum::{http::StatusCode, middleware, routing::post, Router};
+use axum_test::TestServer;
+use backend::{
+    analytics::{
+        ingest_event, insert_event,
+        stats::{aggregate, breakdown, Filters, Period, StatsQuery},
+        AnalyticsConfig, AnalyticsEvent, PlausibleEvent,
+    },
+    correlation::correlation_middleware,
+    retention,
+    trusted_proxies::TrustedProxies,
+    views::VisitorHasher,
+};
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use serde_json::json;
+use sqlx::PgPool;
+use std::{net::SocketAddr, sync::Arc};
+
+mod test_utils;
+
+fn config(domains: &[&str]) -> AnalyticsConfig {
+    AnalyticsConfig {
+        domains: domains.iter().map(|d| d.to_string()).collect(),
+    }
+}
+
+fn parse(
+    payload: serde_json::Value,
+    config: &AnalyticsConfig,
+) -> Result<AnalyticsEvent, &'static str> {
+    let event: PlausibleEvent = serde_json::from_value(payload).expect("valid payload");
+    AnalyticsEvent::from_plausible(event, config)
+}
+
+#[test]
+fn test_tracker_payload() {
+    let event = parse(
+        json!({
+            "n": "pageview",
+            "u": "https://FlakM.com/posts/rust/?utm_source=mastodon",
+            "d": "flakm.com",
+            "r": "https://mastodon.social/",
+            "w": 1280,
+            "m": "{\"author\":\"flakm\",\"nested\":{\"a\":1}}"
+        }),
+        &config(&["flakm.com"]),
+    )
+    .unwrap();
+
+    assert_eq!(event.domain, "flakm.com");
+    assert_eq!(event.name, "pageview");
+    assert_eq!(event.hostname, "flakm.com");
+    assert_eq!(event.pathname, "/posts/rust/");
+    assert_eq!(event.screen_width, Some(1280));
+    assert_eq!(event.props.get("author"), Some(&json!("flakm")));
+    assert!(!event.props.contains_key("nested"));
+}
+
+#[test]
+fn test_events_api_payload() {
+    let event = parse(
+        json!({
+            "name": "Outbound Link: Click",
+            "url": "https://flakm.com/posts/rust/",
+            "domain": "blog.example,flakm.com",
+            "props": {"url": "https://github.com"}
+        }),
+        &config(&["flakm.com"]),
+    )
+    .unwrap();
+
+    assert_eq!(event.domain, "flakm.com");
+    assert_eq!(event.name, "Outbound Link: Click");
+    assert_eq!(event.props.get("url"), Some(&json!("https://github.com")));
+}
+
+#[test]
+fn test_invalid_events() {
+    let flakm = config(&["flakm.com"]);
+    let event = |name: &str, url: &str, domain: &str| {
+        parse(json!({"n": name, "u": url, "d": domain}), &flakm)
+    };
+
+    assert_eq!(
+        event("pageview", "https://flakm.com/", "other.com"),
+        Err("unknown_domain")
+    );
+    assert_eq!(
+        event(" ", "https://flakm.com/", "flakm.com"),
+        Err("invalid_name")
+    );
+    assert_eq!(
+        event("pageview", "/relative", "flakm.com"),
+        Err("invalid_url")
+    );
+
+    // Without configured domains every domain is accepted
+    assert!(parse(
+        json!({"n": "pageview", "u": "https://other.com/", "d": "other.com"}),
+        &AnalyticsConfig::default()
+    )
+    .is_ok());
+}
+
+#[test]
+fn test_periods() {
+    let today = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+    let midnight = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+
+    let period = Period::parse("day", None, today).unwrap();
+    assert_eq!(
+        (period.start, period.end),
+        (midnight(2024, 3, 15), midnight(2024, 3, 16))
+    );
+
+    let period = Period::parse("7d", None, today).unwrap();
+    assert_eq!(period.start, midnight(2024, 3, 9));
+
+    let period = Period::parse("month", None, today).unwrap();
+    assert_eq!(period.start, midnight(2024, 3, 1));
+
+    let period = Period::parse("6mo", None, today).unwrap();
+    assert_eq!(period.start, midnight(2023, 9, 16));
+
+    let period = Period::parse("custom", Some("2024-01-01,2024-01-31"), today).unwrap();
+    assert_eq!(
+        (period.start, period.end),
+        (midnight(2024, 1, 1), midnight(2024, 2, 1))
+    );
+
+    assert!(Period::parse("custom", None, today).is_err());
+    assert!(Period::parse("custom", Some("2024-02-01,2024-01-01"), today).is_err());
+    assert!(Period::parse("0d", None, today).is_err());
+    assert!(Period::parse("week", None, today).is_err());
+}
+
+#[test]
+fn test_filters() {
+    let filters = Filters::parse(Some("event:page==/posts/rust/;event:name==Signup")).unwrap();
+    assert_eq!(filters.page.as_deref(), Some("/posts/rust/"));
+    assert_eq!(filters.name.as_deref(), Some("Signup"));
+
+    assert_eq!(Filters::parse(None).unwrap(), Filters::default());
+    assert!(Filters::parse(Some("visit:browser==Firefox")).is_err());
+}
+
+fn create_ingest_app() -> TestServer {
+    // The pool is never connected, requests below are answered before touching it
+    let app = Router::new()
+        .route("/api/event", post(ingest_event))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies::default()),
+            correlation_middleware,
+        ))
+        .with_state(test_utils::test_state());
+
+    TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>())
+        .expect("Failed to create test server")
+}
+
+#[tokio::test]
+async fn test_ingest_drops_bots_and_rejects_invalid_events() {
+    let server = create_ingest_app();
+
+    let response = server
+        .post("/api/event")
+        .add_header("user-agent", "curl/8.0")
+        .text(r#"{"n":"pageview","u":"https://flakm.com/","d":"flakm.com"}"#)
+        .await;
+    response.assert_status(StatusCode::ACCEPTED);
+    response.assert_text("ok");
+
+    let response = server
+        .post("/api/event")
+        .add_header("user-agent", "Mozilla/5.0 Firefox")
+        .text(r#"{"n":"pageview","u":"https://other.com/","d":"other.com"}"#)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+
+    let response = server
+        .post("/api/event")
+        .add_header("user-agent", "Mozilla/5.0 Firefox")
+        .text("not json")
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+fn stats_query(day: NaiveDate, property: Option<&str>) -> StatsQuery {
+    StatsQuery {
+        site_id: "flakm.com".to_string(),
+        period: Some("custom".to_string()),
+        date: Some(format!("{day},{day}")),
+        metrics: Some("visitors,pageviews".to_string()),
+        filters: None,
+        property: property.map(str::to_string),
+        limit: None,
+    }
+}
+
+#[sqlx::test]
+async fn test_visitors_are_counted_past_retention(pool: PgPool) {
+    let visitors = VisitorHasher::new("salt");
+    let day = Utc::now().date_naive() - Duration::days(200);
+    let pageview = |path: &str| {
+        parse(
+            json!({ "n": "pageview", "u": format!("https://flakm.com{path}"), "d": "flakm.com" }),
+            &config(&[]),
+        )
+        .unwrap()
+    };
+
+    for (ip_hash, path) in [
+        ("alice", "/posts/a/"),
+        ("alice", "/posts/b/"),
+        ("bob", "/posts/a/"),
+    ] {
+        let visitor_id = visitors.daily_id(day, ip_hash);
+        insert_event(&pool, &pageview(path), ip_hash, visitor_id, None)
+            .await
+            .unwrap();
+    }
+    sqlx::query!(
+        "UPDATE analytics_events SET occurred_at = $1",
+        day.and_hms_opt(12, 0, 0).unwrap().and_utc()
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    retention::prune(&pool, Utc::now() - Duration::days(90), 1000)
+        .await
+        .unwrap();
+    let stripped = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM analytics_events WHERE user_ip_hash IS NULL"#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(stripped, 3);
+
+    let totals = aggregate(Query(stats_query(day, None)), State(pool.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        totals.0["results"],
+        json!({ "visitors": { "value": 2 }, "pageviews": { "value": 3 } })
+    );
+    let pages = breakdown(Query(stats_query(day, Some("event:page"))), State(pool))
+        .await
+        .unwrap();
+    assert_eq!(
+        pages.0["results"][0],
+        json!({ "page": "/posts/a/", "visitors": 2, "pageviews": 2 })
+    );
+}
+
+#[test]
+fn test_visitor_ids_change_every_day() {
+    let visitors = VisitorHasher::new("salt");
+    let day = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+
+    assert_eq!(
+        visitors.daily_id(day, "alice"),
+        visitors.daily_id(day, "alice")
+    );
+    assert_ne!(
+        visitors.daily_id(day, "alice"),
+        visitors.daily_id(day, "bob")
+    );
+    assert_ne!(
+        visitors.daily_id(day, "alice"),
+        visitors.daily_id(day + Duration::days(1), "alice")
+    );
+}
@@ -0,0 +1,79 @@
+use axum::{routing::get, Router};
+use axum_test::TestServer;
+use backend::likes::badge::{self, format_count, render_badge, BadgeStyle};
+
+mod test_utils;
+
+#[test]
+fn test_format_count_is_compact() {
+    assert_eq!(format_count(0), "0");
+    assert_eq!(format_count(-3), "0");
+    assert_eq!(format_count(999), "999");
+    assert_eq!(format_count(1_000), "1k");
+    assert_eq!(format_count(1_250), "1.2k");
+    assert_eq!(format_count(12_900), "12k");
+    assert_eq!(format_count(999_999), "999k");
+    assert_eq!(format_count(3_470_000), "3.4M");
+}
+
+#[test]
+fn test_badge_shows_label_and_count() {
+    let svg = render_badge("likes", "42", BadgeStyle::Flat);
+
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.ends_with("</svg>"));
+    assert!(svg.contains("<title>likes: 42</title>"));
+    assert!(svg.contains(">42</text>"));
+    assert!(svg.contains("rx=\"3\""));
+    assert!(svg.contains("linearGradient"));
+
+    let square = render_badge("likes", "42", BadgeStyle::FlatSquare);
+    assert!(square.contains("rx=\"0\""));
+    assert!(!square.contains("linearGradient"));
+
+    let plastic = render_badge("likes", "42", BadgeStyle::Plastic);
+    assert!(plastic.contains("height=\"18\""));
+
+    // A longer count makes a wider badge
+    let wide = render_badge("likes", "1.2k", BadgeStyle::Flat);
+    assert!(wide.len() >= svg.len());
+    assert_ne!(width(&svg), width(&wide));
+}
+
+#[test]
+fn test_badge_without_label_shows_only_the_count() {
+    let svg = render_badge("", "7", BadgeStyle::Flat);
+    assert!(svg.contains("<title>7</title>"));
+    assert_eq!(svg.matches("<text ").count(), 2);
+}
+
+#[test]
+fn test_badge_escapes_label() {
+    let svg = render_badge("<script>\"&'", "1", BadgeStyle::Flat);
+    assert!(!svg.contains("<script>"));
+    assert!(svg.contains("&lt;script&gt;&quot;&amp;&apos;"));
+}
+
+#[tokio::test]
+async fn test_badge_rejects_unknown_paths_and_styles() {
+    let app = Router::new()
+        .route("/badge/:file", get(badge::like_badge))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app).unwrap();
+
+    server
+        .get("/badge/test-post")
+        .await
+        .assert_status_not_found();
+    server
+        .get("/badge/test-post.svg")
+        .add_query_param("style", "3d")
+        .await
+        .assert_status_bad_request();
+}
+
+fn width(svg: &str) -> &str {
+    let start = svg.find("width=\"").unwrap() + 7;
+    let end = start + svg[start..].find('"').unwrap();
+    &svg[start..end]
+}
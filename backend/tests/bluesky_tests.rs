@@ -0,0 +1,240 @@
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use backend::{
+    bluesky::{post_record, BlueskyClient, BlueskyConfig, StrongRef, MAX_POST_CHARS},
+    hugo_posts::HugoBlogPost,
+};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// Requests the mock PDS received, by XRPC method
+type Received = Arc<Mutex<Vec<(String, Value)>>>;
+
+fn authorized(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        == Some("Bearer access-jwt")
+}
+
+async fn create_session(Json(body): Json<Value>) -> impl IntoResponse {
+    if body["password"] != "app-password" {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(
+                json!({ "error": "AuthenticationRequired", "message": "Invalid identifier or password" }),
+            ),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(
+            json!({ "accessJwt": "access-jwt", "refreshJwt": "refresh-jwt", "did": "did:plc:blog", "handle": "blog.example.com" }),
+        ),
+    )
+}
+
+async fn upload_blob(
+    State(received): State<Received>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    if !authorized(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "AuthMissing" })),
+        );
+    }
+    let mime_type = headers[header::CONTENT_TYPE].to_str().unwrap().to_string();
+    received.lock().unwrap().push((
+        "uploadBlob".to_string(),
+        json!({ "mimeType": mime_type, "size": body.len() }),
+    ));
+    (
+        StatusCode::OK,
+        Json(
+            json!({ "blob": { "$type": "blob", "ref": { "$link": "bafkthumb" }, "mimeType": mime_type, "size": body.len() } }),
+        ),
+    )
+}
+
+async fn create_record(
+    State(received): State<Received>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> impl IntoResponse {
+    if !authorized(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "AuthMissing" })),
+        );
+    }
+    received
+        .lock()
+        .unwrap()
+        .push(("createRecord".to_string(), body));
+    (
+        StatusCode::OK,
+        Json(json!({ "uri": "at://did:plc:blog/app.bsky.feed.post/3kpost", "cid": "bafypost" })),
+    )
+}
+
+/// PDS answering the XRPC methods of the syndication, also serving a featured image
+async fn mock_pds() -> (String, Received) {
+    let received = Received::default();
+    let app = Router::new()
+        .route(
+            "/xrpc/com.atproto.server.createSession",
+            post(create_session),
+        )
+        .route("/xrpc/com.atproto.repo.uploadBlob", post(upload_blob))
+        .route("/xrpc/com.atproto.repo.createRecord", post(create_record))
+        .route(
+            "/images/cover.png",
+            get(|| async { ([(header::CONTENT_TYPE, "image/png")], vec![7u8; 2048]) }),
+        )
+        .with_state(received.clone());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, received)
+}
+
+fn client(pds_url: &str, password: &str) -> BlueskyClient {
+    BlueskyClient::new(BlueskyConfig {
+        identifier: "blog.example.com".to_string(),
+        app_password: password.to_string(),
+        pds_url: pds_url.parse().unwrap(),
+    })
+    .unwrap()
+}
+
+fn blog_post(base_url: &str, featured_image: Option<&str>) -> HugoBlogPost {
+    HugoBlogPost {
+        title: "Zażółć gęślą jaźń".to_string(),
+        slug: "polish-post".to_string(),
+        description: "A post with Polish letters".to_string(),
+        date: Utc::now(),
+        featured_image: featured_image.map(str::to_string),
+        tags: Some(vec!["rust".to_string(), "Nix OS".to_string()]),
+        url: format!("{base_url}/posts/polish-post/").parse().unwrap(),
+        content: None,
+        lastmod: None,
+        draft: false,
+        reading_time: None,
+        word_count: None,
+        series: None,
+        summary: None,
+    }
+}
+
+#[test]
+fn test_post_record_hashtag_facets() {
+    let record = post_record(&blog_post("https://blog.example.com", None), None);
+    let text = record["text"].as_str().unwrap();
+
+    assert_eq!(text, "Zażółć gęślą jaźń\n\n#rust #NixOS");
+    // Facets point at the UTF-8 bytes of the hashtags
+    let facets = record["facets"].as_array().unwrap();
+    let hashtags: Vec<&str> = facets
+        .iter()
+        .map(|facet| {
+            let start = facet["index"]["byteStart"].as_u64().unwrap() as usize;
+            let end = facet["index"]["byteEnd"].as_u64().unwrap() as usize;
+            &text[start..end]
+        })
+        .collect();
+    assert_eq!(hashtags, ["#rust", "#NixOS"]);
+    assert_eq!(facets[1]["features"][0]["tag"], "NixOS");
+
+    let external = &record["embed"]["external"];
+    assert_eq!(
+        external["uri"],
+        "https://blog.example.com/posts/polish-post/"
+    );
+    assert_eq!(external["description"], "A post with Polish letters");
+    assert!(external.get("thumb").is_none());
+}
+
+#[test]
+fn test_post_record_stays_within_the_limit() {
+    let mut post = blog_post("https://blog.example.com", None);
+    post.title = "a".repeat(MAX_POST_CHARS - 3);
+
+    let record = post_record(&post, None);
+    assert_eq!(record["text"], post.title);
+    assert!(record.get("facets").is_none());
+
+    post.title = "b".repeat(MAX_POST_CHARS + 10);
+    let record = post_record(&post, None);
+    assert_eq!(
+        record["text"].as_str().unwrap().chars().count(),
+        MAX_POST_CHARS
+    );
+}
+
+#[tokio::test]
+async fn test_create_post_with_thumbnail() {
+    let (pds_url, received) = mock_pds().await;
+    let client = client(&pds_url, "app-password");
+
+    let session = client.login().await.unwrap();
+    assert_eq!(session.did, "did:plc:blog");
+
+    // Relative to the post URL, like Hugo writes them
+    let post = blog_post(&pds_url, Some("/images/cover.png"));
+    let record = client.create_post(&session, &post).await.unwrap();
+    assert_eq!(
+        record,
+        StrongRef {
+            uri: "at://did:plc:blog/app.bsky.feed.post/3kpost".to_string(),
+            cid: "bafypost".to_string(),
+        }
+    );
+
+    let received = received.lock().unwrap();
+    assert_eq!(received[0].0, "uploadBlob");
+    assert_eq!(
+        received[0].1,
+        json!({ "mimeType": "image/png", "size": 2048 })
+    );
+    let (method, body) = &received[1];
+    assert_eq!(method, "createRecord");
+    assert_eq!(body["repo"], "did:plc:blog");
+    assert_eq!(body["collection"], "app.bsky.feed.post");
+    let external = &body["record"]["embed"]["external"];
+    assert_eq!(external["title"], "Zażółć gęślą jaźń");
+    assert_eq!(external["thumb"]["ref"]["$link"], "bafkthumb");
+}
+
+#[tokio::test]
+async fn test_create_post_without_usable_image() {
+    let (pds_url, received) = mock_pds().await;
+    let client = client(&pds_url, "app-password");
+    let session = client.login().await.unwrap();
+
+    let post = blog_post(&pds_url, Some("/images/missing.png"));
+    client.create_post(&session, &post).await.unwrap();
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].1["record"]["embed"]["external"]
+        .get("thumb")
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_login_error_is_reported() {
+    let (pds_url, _) = mock_pds().await;
+    let error = client(&pds_url, "wrong").login().await.unwrap_err();
+    assert!(error.to_string().contains("AuthenticationRequired"));
+}
@@ -7,9 +7,16 @@ use axum::{
     Router,
 };
 use axum_test::TestServer;
-use backend::correlation::{
-    correlation_middleware, CorrelationContext, CORRELATION_ID_HEADER, REQUEST_ID_HEADER,
-    SESSION_ID_HEADER, USER_ID_HEADER,
+use backend::{
+    correlation::{
+        correlation_middleware, CorrelationContext, CORRELATION_ID_HEADER, REQUEST_ID_HEADER,
+        SESSION_ID_HEADER, USER_ID_HEADER,
+    },
+    trusted_proxies::{parse_networks, TrustedProxies, DEFAULT_TRUSTED_PROXIES},
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
 };
 use uuid::Uuid;
 
@@ -29,10 +36,41 @@ async fn test_handler(Extension(ctx): Extension<CorrelationContext>) -> impl Int
     }))
 }
 
-fn create_test_app() -> Router {
+fn create_test_app(
+) -> axum::extract::connect_info::IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    // Test requests come from loopback which is trusted, like nginx in production
+    create_test_app_with_proxies(DEFAULT_TRUSTED_PROXIES)
+}
+
+fn create_test_app_with_proxies(
+    trusted: &str,
+) -> axum::extract::connect_info::IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    create_test_app_with_cloudflare(trusted, "")
+}
+
+fn create_test_app_with_cloudflare(
+    trusted: &str,
+    cloudflare: &str,
+) -> axum::extract::connect_info::IntoMakeServiceWithConnectInfo<Router, SocketAddr> {
+    let trusted_proxies = Arc::new(
+        TrustedProxies::new(parse_networks(trusted, ',').unwrap())
+            .with_cloudflare(parse_networks(cloudflare, ',').unwrap()),
+    );
     Router::new()
         .route("/test", get(test_handler))
-        .layer(middleware::from_fn(correlation_middleware))
+        .layer(middleware::from_fn_with_state(
+            trusted_proxies,
+            correlation_middleware,
+        ))
+        .into_make_service_with_connect_info::<SocketAddr>()
+}
+
+fn trusted_localhost() -> TrustedProxies {
+    TrustedProxies::new(parse_networks(DEFAULT_TRUSTED_PROXIES, ',').unwrap())
+}
+
+fn localhost() -> Option<IpAddr> {
+    Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
 }
 
 #[tokio::test]
@@ -90,7 +128,8 @@ async fn test_correlation_middleware_with_existing_headers() {
     assert_eq!(body["session_id"], session_id);
     assert_eq!(body["user_id"], user_id);
     assert_eq!(body["user_agent"], "test-browser/1.0");
-    assert_eq!(body["remote_ip"], "203.0.113.1");
+    // Closest untrusted hop of x-forwarded-for wins over cf-connecting-ip
+    assert_eq!(body["remote_ip"], "10.0.0.1");
     assert_eq!(body["forwarded_for"], "192.168.1.1, 10.0.0.1");
 }
 
@@ -126,7 +165,7 @@ async fn test_correlation_middleware_generates_missing_ids() {
     assert_eq!(body["session_id"], serde_json::Value::Null);
     assert_eq!(body["user_id"], serde_json::Value::Null);
     assert_eq!(body["user_agent"], "test-browser/2.0");
-    assert_eq!(body["remote_ip"], serde_json::Value::Null);
+    assert_eq!(body["remote_ip"], "127.0.0.1");
     assert_eq!(body["forwarded_for"], serde_json::Value::Null);
 
     // Check response headers contain generated IDs
@@ -150,11 +189,11 @@ async fn test_correlation_middleware_generates_missing_ids() {
 }
 
 #[tokio::test]
-async fn test_ip_extraction_priority() {
-    let app = create_test_app();
+async fn test_ip_extraction_cloudflare_behind_trusted_chain() {
+    // nginx on loopback forwarding requests from a Cloudflare edge
+    let app = create_test_app_with_cloudflare("127.0.0.1/32", "10.0.0.0/8");
     let server = TestServer::new(app).expect("Failed to create test server");
 
-    // Test Cloudflare IP takes priority
     let mut headers = HeaderMap::new();
     headers.insert("cf-connecting-ip", HeaderValue::from_static("203.0.113.10"));
     headers.insert("x-real-ip", HeaderValue::from_static("192.168.1.10"));
@@ -163,7 +202,42 @@ async fn test_ip_extraction_priority() {
     let response = add_headers_to_request(server.get("/test"), headers).await;
     let body: serde_json::Value = response.json();
 
-    assert_eq!(body["remote_ip"], "203.0.113.10"); // Cloudflare should win
+    assert_eq!(body["remote_ip"], "203.0.113.10"); // Every hop is trusted - Cloudflare wins
+}
+
+#[tokio::test]
+async fn test_cf_connecting_ip_ignored_without_cloudflare_edge() {
+    // Every hop is trusted, but none of them is Cloudflare
+    let app = create_test_app_with_proxies("127.0.0.1/32,10.0.0.0/8");
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let mut headers = HeaderMap::new();
+    headers.insert("cf-connecting-ip", HeaderValue::from_static("203.0.113.10"));
+    headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.10"));
+
+    let response = add_headers_to_request(server.get("/test"), headers).await;
+    let body: serde_json::Value = response.json();
+
+    assert_eq!(body["remote_ip"], "10.0.0.10");
+}
+
+#[tokio::test]
+async fn test_ip_extraction_forwarded_for_walks_trusted_hops() {
+    let app = create_test_app_with_proxies("127.0.0.1/32,10.0.0.0/8");
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    // Spoofed first entry, real client and then a trusted proxy
+    let mut headers = HeaderMap::new();
+    headers.insert("cf-connecting-ip", HeaderValue::from_static("203.0.113.10"));
+    headers.insert(
+        "x-forwarded-for",
+        HeaderValue::from_static("1.1.1.1, 198.51.100.7, 10.0.0.10"),
+    );
+
+    let response = add_headers_to_request(server.get("/test"), headers).await;
+    let body: serde_json::Value = response.json();
+
+    assert_eq!(body["remote_ip"], "198.51.100.7");
 }
 
 #[tokio::test]
@@ -171,15 +245,14 @@ async fn test_ip_extraction_fallback() {
     let app = create_test_app();
     let server = TestServer::new(app).expect("Failed to create test server");
 
-    // Test fallback when no Cloudflare IP
+    // Test fallback to x-real-ip when there is no x-forwarded-for
     let mut headers = HeaderMap::new();
     headers.insert("x-real-ip", HeaderValue::from_static("192.168.1.20"));
-    headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.20"));
 
     let response = add_headers_to_request(server.get("/test"), headers).await;
     let body: serde_json::Value = response.json();
 
-    assert_eq!(body["remote_ip"], "192.168.1.20"); // x-real-ip should win over x-forwarded-for
+    assert_eq!(body["remote_ip"], "192.168.1.20");
 }
 
 #[tokio::test]
@@ -187,18 +260,34 @@ async fn test_ip_extraction_x_forwarded_for_only() {
     let app = create_test_app();
     let server = TestServer::new(app).expect("Failed to create test server");
 
-    // Test with only x-forwarded-for (should be in forwarded_for field, not remote_ip)
     let mut headers = HeaderMap::new();
     headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.30"));
 
     let response = add_headers_to_request(server.get("/test"), headers).await;
     let body: serde_json::Value = response.json();
 
-    // x-forwarded-for goes to forwarded_for field, not remote_ip
-    assert_eq!(body["remote_ip"], serde_json::Value::Null);
+    assert_eq!(body["remote_ip"], "10.0.0.30");
     assert_eq!(body["forwarded_for"], "10.0.0.30");
 }
 
+#[tokio::test]
+async fn test_untrusted_peer_headers_ignored() {
+    // Nothing is trusted - the backend is hit directly
+    let app = create_test_app_with_proxies("");
+    let server = TestServer::new(app).expect("Failed to create test server");
+
+    let mut headers = HeaderMap::new();
+    headers.insert("cf-connecting-ip", HeaderValue::from_static("203.0.113.10"));
+    headers.insert("x-real-ip", HeaderValue::from_static("192.168.1.10"));
+    headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.10"));
+
+    let response = add_headers_to_request(server.get("/test"), headers).await;
+    let body: serde_json::Value = response.json();
+
+    assert_eq!(body["remote_ip"], "127.0.0.1"); // TCP peer address
+    assert_eq!(body["forwarded_for"], "10.0.0.10");
+}
+
 #[tokio::test]
 async fn test_no_ip_headers() {
     let app = create_test_app();
@@ -208,7 +297,7 @@ async fn test_no_ip_headers() {
     let response = server.get("/test").await;
     let body: serde_json::Value = response.json();
 
-    assert_eq!(body["remote_ip"], serde_json::Value::Null);
+    assert_eq!(body["remote_ip"], "127.0.0.1");
     assert_eq!(body["forwarded_for"], serde_json::Value::Null);
 }
 
@@ -229,22 +318,25 @@ async fn test_correlation_context_creation() {
     );
     headers.insert("x-forwarded-for", HeaderValue::from_static("192.168.1.100"));
 
-    let ctx = CorrelationContext::from_headers(&headers);
+    let ctx = CorrelationContext::from_headers(&headers, localhost(), &trusted_localhost());
 
     assert_eq!(ctx.correlation_id, "test-corr-id");
     assert_eq!(ctx.request_id, "test-req-id");
     assert_eq!(ctx.session_id, Some("test-session".to_string()));
     assert_eq!(ctx.user_id, Some("test-user".to_string()));
     assert_eq!(ctx.user_agent, Some("test-agent".to_string()));
-    assert_eq!(ctx.remote_ip, Some("203.0.113.100".to_string()));
+    assert_eq!(ctx.remote_ip, Some("192.168.1.100".to_string()));
     assert_eq!(ctx.forwarded_for, Some("192.168.1.100".to_string()));
+    // The client talked to nginx directly, its cf-* headers are its own
+    assert!(!ctx.via_cloudflare);
+    assert_eq!(ctx.cf_header(&headers, "cf-connecting-ip"), None);
 }
 
 #[tokio::test]
 async fn test_correlation_context_with_minimal_headers() {
     let headers = HeaderMap::new(); // Empty headers
 
-    let ctx = CorrelationContext::from_headers(&headers);
+    let ctx = CorrelationContext::from_headers(&headers, None, &trusted_localhost());
 
     // Should generate UUIDs for required fields
     assert!(Uuid::parse_str(&ctx.correlation_id).is_ok());
@@ -256,6 +348,7 @@ async fn test_correlation_context_with_minimal_headers() {
     assert_eq!(ctx.user_agent, None);
     assert_eq!(ctx.remote_ip, None);
     assert_eq!(ctx.forwarded_for, None);
+    assert!(!ctx.via_cloudflare);
 }
 
 #[tokio::test]
@@ -306,3 +399,49 @@ async fn test_multiple_requests_different_ids() {
     assert_ne!(correlation_id_1, correlation_id_2);
     assert_ne!(request_id_1, request_id_2);
 }
+
+#[test]
+fn test_cf_headers_only_through_cloudflare() {
+    let proxies =
+        trusted_localhost().with_cloudflare(parse_networks("173.245.48.0/20", ',').unwrap());
+    let request = |forwarded_for: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-ipcountry", HeaderValue::from_static("PL"));
+        headers.insert("x-forwarded-for", HeaderValue::from_static(forwarded_for));
+        headers
+    };
+
+    // Client -> Cloudflare -> nginx
+    let headers = request("198.51.100.7, 173.245.48.1");
+    let ctx = CorrelationContext::from_headers(&headers, localhost(), &proxies);
+    assert!(ctx.via_cloudflare);
+    assert_eq!(ctx.cf_header(&headers, "cf-ipcountry"), Some("PL"));
+    assert_eq!(ctx.remote_ip.as_deref(), Some("198.51.100.7"));
+
+    // Client -> nginx, with a spoofed Cloudflare hop in front of it
+    let headers = request("173.245.48.1, 198.51.100.7");
+    let ctx = CorrelationContext::from_headers(&headers, localhost(), &proxies);
+    assert!(!ctx.via_cloudflare);
+    assert_eq!(ctx.cf_header(&headers, "cf-ipcountry"), None);
+
+    // Client -> Cloudflare -> backend, skipping nginx
+    let ctx = CorrelationContext::from_headers(
+        &request("198.51.100.7"),
+        Some("173.245.48.1".parse().unwrap()),
+        &proxies,
+    );
+    assert!(ctx.via_cloudflare);
+
+    // Client -> backend
+    let ctx = CorrelationContext::from_headers(
+        &request("198.51.100.7, 173.245.48.1"),
+        Some("203.0.113.1".parse().unwrap()),
+        &proxies,
+    );
+    assert!(!ctx.via_cloudflare);
+
+    // Garbage in the chain hides who the client talked to
+    let headers = request("198.51.100.7, unknown, 173.245.48.1");
+    let ctx = CorrelationContext::from_headers(&headers, localhost(), &proxies);
+    assert!(!ctx.via_cloudflare);
+}
@@ -0,0 +1,81 @@
+use backend::{
+    events::{BackendEvent, EventBus},
+    likes::{cache::LikeCountCache, LikeCounts},
+};
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn test_event_payload_format() {
+    let event = BackendEvent::LikeRecorded {
+        post_slug: "test-post".to_string(),
+        reaction: "heart".to_string(),
+        total_likes: 3,
+        reaction_count: 2,
+    };
+    let payload = serde_json::to_value(&event).unwrap();
+    assert_eq!(
+        payload,
+        serde_json::json!({
+            "type": "like_recorded",
+            "post_slug": "test-post",
+            "reaction": "heart",
+            "total_likes": 3,
+            "reaction_count": 2
+        })
+    );
+
+    let parsed: BackendEvent =
+        serde_json::from_str(r#"{"type":"post_ingested","slug":"new-post"}"#).unwrap();
+    assert_eq!(
+        parsed,
+        BackendEvent::PostIngested {
+            slug: "new-post".to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_subscribers_receive_delivered_events() {
+    let bus = EventBus::default();
+    let mut receiver = bus.subscribe();
+
+    let event = BackendEvent::PostIngested {
+        slug: "new-post".to_string(),
+    };
+    bus.deliver(event.clone());
+
+    assert_eq!(receiver.recv().await.unwrap(), event);
+}
+
+#[tokio::test]
+async fn test_like_recorded_invalidates_cache() {
+    let bus = EventBus::default();
+    let cache = Arc::new(LikeCountCache::new(Duration::from_secs(60)));
+    cache.insert("test-post", LikeCounts::default());
+    cache.insert(
+        "other-post",
+        LikeCounts {
+            total: 5,
+            reactions: Default::default(),
+            sources: Default::default(),
+        },
+    );
+    bus.register(cache.clone());
+
+    bus.deliver(BackendEvent::LikeRecorded {
+        post_slug: "test-post".to_string(),
+        reaction: "heart".to_string(),
+        total_likes: 1,
+        reaction_count: 1,
+    });
+
+    // Registered subscribers run on their own task
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while cache.get("test-post").is_some() {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await
+    .expect("cache entry invalidated");
+    assert_eq!(cache.get("other-post").map(|counts| counts.total), Some(5));
+}
@@ -0,0 +1,301 @@
+use backend::{
+    hugo_posts::{
+        validation::{is_valid_slug, PostValidation, ValidationMode},
+        BlogRepository, HugoBlogPost,
+    },
+    search::search,
+};
+use chrono::{Duration, TimeZone, Utc};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::io::Write;
+
+fn validation(mode: ValidationMode) -> PostValidation {
+    PostValidation {
+        mode,
+        domain: Some("flakm.com".to_string()),
+        max_future: Duration::hours(24),
+    }
+}
+
+fn load(json: &str) -> Vec<HugoBlogPost> {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(json.as_bytes()).unwrap();
+    HugoBlogPost::load_new_posts(file.path(), &validation(ValidationMode::Strict)).unwrap()
+}
+
+fn entry(slug: &str) -> Value {
+    json!({
+        "title": "A post",
+        "slug": slug,
+        "description": "",
+        "date": "2024-01-01T12:00:00Z",
+        "url": format!("https://flakm.com/posts/{slug}/")
+    })
+}
+
+/// File with one valid post and one of each problem
+fn mixed_entries() -> Vec<Value> {
+    let mut empty_title = entry("empty-title");
+    empty_title["title"] = json!("  ");
+    let mut foreign = entry("foreign");
+    foreign["url"] = json!("https://example.com/posts/foreign/");
+    let mut future = entry("future");
+    future["date"] = json!("2030-01-01T00:00:00Z");
+
+    vec![
+        entry("good_post-1"),
+        json!({ "title": "No slug" }),
+        empty_title,
+        entry("bad slug/../"),
+        foreign,
+        future,
+        entry("good_post-1"),
+    ]
+}
+
+#[test]
+fn test_load_posts_of_older_exports() {
+    let posts = load(
+        r#"[{
+            "title": "Old post",
+            "slug": "old-post",
+            "description": "Exported before the extra fields",
+            "date": "2023-12-01T10:00:00+01:00",
+            "featuredImage": null,
+            "tags": ["rust"],
+            "url": "https://flakm.com/posts/old-post/"
+        }]"#,
+    );
+
+    let post = &posts[0];
+    assert_eq!(post.slug, "old-post");
+    assert_eq!(post.content, None);
+    assert_eq!(post.lastmod, None);
+    assert!(!post.draft);
+    assert_eq!(post.reading_time, None);
+    assert_eq!(post.word_count, None);
+    assert_eq!(post.series, None);
+    assert_eq!(post.summary, None);
+}
+
+#[test]
+fn test_load_posts_with_extended_metadata() {
+    let posts = load(
+        r#"[{
+            "title": "New post",
+            "slug": "new-post",
+            "description": "Exported with everything",
+            "date": "2024-01-01T12:00:00Z",
+            "featuredImage": "images/new.png",
+            "tags": ["rust", "nix"],
+            "url": "https://flakm.com/posts/new-post/",
+            "content": "The whole post as plain text",
+            "lastmod": "2024-01-03T08:30:00Z",
+            "draft": true,
+            "readingTime": 7,
+            "wordCount": 1450,
+            "series": ["Building a blog"],
+            "summary": "The first paragraph"
+        }]"#,
+    );
+
+    let post = &posts[0];
+    assert_eq!(
+        post.content.as_deref(),
+        Some("The whole post as plain text")
+    );
+    assert_eq!(
+        post.lastmod,
+        Some(Utc.with_ymd_and_hms(2024, 1, 3, 8, 30, 0).unwrap())
+    );
+    assert!(post.draft);
+    assert_eq!(post.reading_time, Some(7));
+    assert_eq!(post.word_count, Some(1450));
+    assert_eq!(post.series, Some(vec!["Building a blog".to_string()]));
+    assert_eq!(post.summary.as_deref(), Some("The first paragraph"));
+}
+
+#[test]
+fn test_validation_reports_every_problem_with_its_path() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let validated = validation(ValidationMode::Strict).validate(mixed_entries(), now);
+
+    assert_eq!(validated.posts.len(), 1);
+    assert_eq!(validated.posts[0].slug, "good_post-1");
+    let problems: Vec<(&str, &str)> = validated
+        .errors
+        .iter()
+        .map(|e| (e.path.as_str(), e.reason))
+        .collect();
+    assert_eq!(
+        problems,
+        vec![
+            ("$[1]", "invalid_entry"),
+            ("$[2].title", "empty_title"),
+            ("$[3].slug", "invalid_slug"),
+            ("$[4].url", "foreign_url"),
+            ("$[5].date", "future_date"),
+            ("$[6].slug", "duplicate_slug"),
+        ]
+    );
+    assert!(validated.errors[0].message.contains("missing field `slug`"));
+    assert!(validated.errors[5].message.contains("$[0]"));
+
+    let report = validated.report();
+    assert!(report.starts_with("6 problems in the blog posts file:"));
+    assert!(report.contains("\n  $[4].url: host \"example.com\" is not \"flakm.com\""));
+}
+
+#[test]
+fn test_invalid_entries_do_not_take_their_slug() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let mut broken = entry("shared");
+    broken["title"] = json!("");
+    let mut fixed = entry("shared");
+    fixed["title"] = json!("The fixed post");
+
+    let validated =
+        validation(ValidationMode::Lenient).validate(vec![broken, fixed, entry("shared")], now);
+    assert_eq!(validated.posts.len(), 1);
+    assert_eq!(validated.posts[0].title, "The fixed post");
+    let problems: Vec<(&str, &str)> = validated
+        .errors
+        .iter()
+        .map(|e| (e.path.as_str(), e.reason))
+        .collect();
+    assert_eq!(
+        problems,
+        vec![
+            ("$[0].title", "empty_title"),
+            ("$[2].slug", "duplicate_slug")
+        ]
+    );
+    assert!(validated.errors[1].message.contains("$[1]"));
+}
+
+#[test]
+fn test_validation_modes() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+
+    let strict = validation(ValidationMode::Strict);
+    let error = strict
+        .validate(mixed_entries(), now)
+        .into_posts(strict.mode)
+        .unwrap_err();
+    assert!(error.to_string().contains("$[3].slug"));
+
+    let lenient = validation(ValidationMode::Lenient);
+    let posts = lenient
+        .validate(mixed_entries(), now)
+        .into_posts(lenient.mode)
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+
+    // A post dated within the allowed skew is fine
+    let mut soon = entry("soon");
+    soon["date"] = json!((now + Duration::hours(2)).to_rfc3339());
+    let validated = strict.validate(vec![soon], now);
+    assert!(validated.errors.is_empty());
+}
+
+#[test]
+fn test_any_host_without_a_posts_domain() {
+    let now = Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap();
+    let mut foreign = entry("foreign");
+    foreign["url"] = json!("https://blog.example.com/posts/foreign/");
+
+    let unset = PostValidation {
+        domain: None,
+        ..validation(ValidationMode::Strict)
+    };
+    let validated = unset.validate(vec![foreign], now);
+    assert!(validated.errors.is_empty());
+    assert_eq!(validated.posts.len(), 1);
+}
+
+#[test]
+fn test_slug_charset() {
+    assert!(is_valid_slug("automate_boring_stuff"));
+    assert!(is_valid_slug("rusty-LED-2"));
+    assert!(!is_valid_slug(""));
+    assert!(!is_valid_slug("two words"));
+    assert!(!is_valid_slug("post.png"));
+    assert!(!is_valid_slug("zażółć"));
+    assert!(!is_valid_slug(&"a".repeat(201)));
+}
+
+fn post(slug: &str, draft: bool) -> HugoBlogPost {
+    let mut post = entry(slug);
+    post["draft"] = json!(draft);
+    post["content"] = json!("Unfinished thoughts about ferris");
+    serde_json::from_value(post).unwrap()
+}
+
+#[sqlx::test]
+async fn test_drafts_are_announced_once_published(pool: PgPool) {
+    let repo = BlogRepository { db: pool.clone() };
+    sqlx::query!(
+        "INSERT INTO webhook_endpoints (url, secret, events) VALUES ($1, $2, $3)",
+        "https://hooks.example.com/blog",
+        "secret",
+        &["post.published".to_string(), "post.updated".to_string()]
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let outbox = || async {
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM syndication_outbox"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    };
+    let webhooks = || async {
+        sqlx::query_scalar!("SELECT event FROM webhook_deliveries ORDER BY id")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+    };
+
+    repo.new_blog_entry(&post("draft", true)).await.unwrap();
+    assert_eq!(outbox().await, 0);
+    assert!(webhooks().await.is_empty());
+    assert_eq!(search(&pool, "ferris", 10, 0).await.unwrap().total, 0);
+
+    // Published later, the post is announced like a new one
+    repo.new_blog_entry(&post("draft", false)).await.unwrap();
+    assert_eq!(outbox().await, 2);
+    assert_eq!(webhooks().await, vec!["post.published"]);
+    assert_eq!(search(&pool, "ferris", 10, 0).await.unwrap().total, 1);
+}
+
+#[sqlx::test]
+async fn test_unchanged_posts_are_not_announced_again(pool: PgPool) {
+    let repo = BlogRepository { db: pool.clone() };
+    let jobs = || async {
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    };
+
+    repo.new_blog_entry(&post("restarted", false))
+        .await
+        .unwrap();
+    assert_eq!(jobs().await, 2);
+    sqlx::query!("DELETE FROM syndication_outbox")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Ingested again on every restart, nothing is queued
+    repo.new_blog_entry(&post("restarted", false))
+        .await
+        .unwrap();
+    assert_eq!(jobs().await, 2);
+    let outbox = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM syndication_outbox"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox, 0);
+}
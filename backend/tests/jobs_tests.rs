@@ -0,0 +1,400 @@
+use backend::jobs::{self, JobOptions, JobRunner, JobsConfig, Schedule};
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use test_utils::{test_jobs_config, wait_until};
+
+mod test_utils;
+
+#[test]
+fn test_interval_schedule() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+    let schedule = Schedule::Every(Duration::from_secs(60 * 60));
+
+    // Runs right away, then every interval
+    assert_eq!(schedule.first_run(now), Some(now));
+    assert_eq!(
+        schedule.next_run(now),
+        Some(Utc.with_ymd_and_hms(2024, 1, 2, 4, 4, 5).unwrap())
+    );
+}
+
+#[test]
+fn test_cron_schedule() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+    let schedule = Schedule::cron("0 30 3 * * *").unwrap();
+
+    let today = Utc.with_ymd_and_hms(2024, 1, 2, 3, 30, 0).unwrap();
+    assert_eq!(schedule.first_run(now), Some(today));
+    assert_eq!(
+        schedule.next_run(today),
+        Some(Utc.with_ymd_and_hms(2024, 1, 3, 3, 30, 0).unwrap())
+    );
+
+    assert!(Schedule::cron("every day").is_err());
+}
+
+#[test]
+fn test_schedule_from_env() {
+    let every = Duration::from_secs(60);
+
+    std::env::set_var("TEST_JOB_SCHEDULE", "0 0 * * * *");
+    assert!(matches!(
+        Schedule::from_env("TEST_JOB_SCHEDULE", every),
+        Schedule::Cron(_)
+    ));
+
+    // Invalid expressions fall back to the interval
+    std::env::set_var("TEST_JOB_SCHEDULE", "hourly");
+    assert!(matches!(
+        Schedule::from_env("TEST_JOB_SCHEDULE", every),
+        Schedule::Every(d) if d == every
+    ));
+    assert!(matches!(
+        Schedule::from_env("TEST_JOB_SCHEDULE_UNSET", every),
+        Schedule::Every(_)
+    ));
+}
+
+#[test]
+fn test_job_backoff() {
+    let config = JobsConfig {
+        poll_interval: Duration::from_secs(5),
+        lock_timeout: Duration::from_secs(3600),
+        shutdown_timeout: Duration::from_secs(30),
+        retry_base: Duration::from_secs(30),
+        retry_max: Duration::from_secs(3600),
+        retention_days: 7,
+    };
+
+    assert_eq!(config.backoff(1), Duration::from_secs(30));
+    assert_eq!(config.backoff(3), Duration::from_secs(120));
+    assert_eq!(config.backoff(7), Duration::from_secs(1920));
+    assert_eq!(config.backoff(8), Duration::from_secs(3600));
+    // No overflow however many attempts were made
+    assert_eq!(config.backoff(i32::MAX), Duration::from_secs(3600));
+}
+
+/// Payloads of the jobs handled, in the order they were run
+type Handled = Arc<Mutex<Vec<Value>>>;
+
+/// Runner of `test` jobs, which record their payload and then fail if asked to
+fn runner(pool: &PgPool, config: JobsConfig, options: JobOptions) -> (JobRunner, Handled) {
+    let handled = Handled::default();
+    let mut runner = JobRunner::new(pool.clone(), config);
+    let recorded = handled.clone();
+    runner.register("test", options, move |payload| {
+        let recorded = recorded.clone();
+        async move {
+            recorded.lock().unwrap().push(payload.clone());
+            if let Some(secs) = payload["sleep"].as_f64() {
+                tokio::time::sleep(Duration::from_secs_f64(secs)).await;
+            }
+            match payload["fail"].as_bool() {
+                Some(true) => Err(anyhow::anyhow!("Asked to fail")),
+                _ => Ok(()),
+            }
+        }
+    });
+    (runner, handled)
+}
+
+async fn enqueue(pool: &PgPool, payload: Value) -> i64 {
+    jobs::enqueue(pool, "test", &payload, Utc::now())
+        .await
+        .unwrap()
+}
+
+async fn job_status(pool: &PgPool, id: i64) -> (String, i32) {
+    let job = sqlx::query!("SELECT status, attempts FROM jobs WHERE id = $1", id)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    (job.status, job.attempts)
+}
+
+async fn count_jobs(pool: &PgPool, status: &str) -> i64 {
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM jobs WHERE status = $1"#,
+        status
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+#[sqlx::test]
+async fn test_jobs_are_claimed_by_one_runner(pool: PgPool) {
+    for n in 0..20 {
+        enqueue(&pool, json!({ "n": n, "sleep": 0.01 })).await;
+    }
+    let options = JobOptions {
+        concurrency: 4,
+        max_attempts: 1,
+    };
+    let (first, first_handled) = runner(&pool, test_jobs_config(), options.clone());
+    let (second, second_handled) = runner(&pool, test_jobs_config(), options);
+    let (first, second) = (first.start(), second.start());
+
+    wait_until("every job is done", || async {
+        count_jobs(&pool, "done").await == 20
+    })
+    .await;
+    first.shutdown().await;
+    second.shutdown().await;
+
+    let mut handled: Vec<i64> = first_handled
+        .lock()
+        .unwrap()
+        .iter()
+        .chain(second_handled.lock().unwrap().iter())
+        .map(|payload| payload["n"].as_i64().unwrap())
+        .collect();
+    handled.sort();
+    assert_eq!(handled, (0..20).collect::<Vec<_>>());
+}
+
+#[sqlx::test]
+async fn test_failed_jobs_are_retried_until_max_attempts(pool: PgPool) {
+    let id = enqueue(&pool, json!({ "fail": true })).await;
+    let options = JobOptions {
+        concurrency: 1,
+        max_attempts: 3,
+    };
+    let (runner, handled) = runner(&pool, test_jobs_config(), options);
+    let runner = runner.start();
+
+    wait_until("the job is given up", || async {
+        job_status(&pool, id).await.0 == "failed"
+    })
+    .await;
+    runner.shutdown().await;
+    assert_eq!(job_status(&pool, id).await, ("failed".to_string(), 3));
+    assert_eq!(handled.lock().unwrap().len(), 3);
+
+    let last_error = sqlx::query_scalar!("SELECT last_error FROM jobs WHERE id = $1", id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(last_error.as_deref(), Some("Asked to fail"));
+}
+
+#[sqlx::test]
+async fn test_failed_jobs_wait_for_the_backoff(pool: PgPool) {
+    let id = enqueue(&pool, json!({ "fail": true })).await;
+    let config = JobsConfig {
+        retry_base: Duration::from_secs(3600),
+        retry_max: Duration::from_secs(6 * 3600),
+        ..test_jobs_config()
+    };
+    let (runner, handled) = runner(&pool, config, JobOptions::default());
+    let runner = runner.start();
+
+    wait_until("the first attempt failed", || async {
+        sqlx::query_scalar!(
+            r#"SELECT last_error IS NOT NULL AS "failed!" FROM jobs WHERE id = $1"#,
+            id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    })
+    .await;
+    // A few more polls do not run it again
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    runner.shutdown().await;
+
+    assert_eq!(job_status(&pool, id).await, ("pending".to_string(), 1));
+    assert_eq!(handled.lock().unwrap().len(), 1);
+    let wait = sqlx::query_scalar!(
+        r#"SELECT EXTRACT(EPOCH FROM run_at - NOW())::FLOAT8 AS "wait!" FROM jobs WHERE id = $1"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!((3500.0..=3600.0).contains(&wait), "retried in {wait}s");
+}
+
+#[sqlx::test]
+async fn test_abandoned_jobs_are_run_again(pool: PgPool) {
+    let id = enqueue(&pool, json!({})).await;
+    // Claimed by an instance that crashed two hours ago
+    sqlx::query!(
+        r#"
+        UPDATE jobs SET status = 'running', attempts = 1, locked_at = NOW() - INTERVAL '2 hours'
+        WHERE id = $1
+        "#,
+        id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let config = JobsConfig {
+        lock_timeout: Duration::from_secs(3600),
+        ..test_jobs_config()
+    };
+    let (runner, handled) = runner(&pool, config, JobOptions::default());
+    let runner = runner.start();
+
+    wait_until("the job is done", || async {
+        job_status(&pool, id).await.0 == "done"
+    })
+    .await;
+    runner.shutdown().await;
+    assert_eq!(job_status(&pool, id).await, ("done".to_string(), 2));
+    assert_eq!(handled.lock().unwrap().len(), 1);
+}
+
+#[sqlx::test]
+async fn test_long_jobs_keep_their_lock(pool: PgPool) {
+    // Runs for five lock timeouts
+    let id = enqueue(&pool, json!({ "sleep": 1.5 })).await;
+    let config = JobsConfig {
+        lock_timeout: Duration::from_millis(300),
+        ..test_jobs_config()
+    };
+    let options = JobOptions {
+        concurrency: 2,
+        ..JobOptions::default()
+    };
+    let (runner, handled) = runner(&pool, config, options);
+    let runner = runner.start();
+
+    wait_until("the job runs", || async {
+        !handled.lock().unwrap().is_empty()
+    })
+    .await;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(job_status(&pool, id).await, ("running".to_string(), 1));
+
+    wait_until("the job is done", || async {
+        job_status(&pool, id).await.0 == "done"
+    })
+    .await;
+    runner.shutdown().await;
+    // Never taken for abandoned and claimed again
+    assert_eq!(job_status(&pool, id).await, ("done".to_string(), 1));
+    assert_eq!(handled.lock().unwrap().len(), 1);
+}
+
+#[sqlx::test]
+async fn test_cancelled_jobs_are_released_on_shutdown(pool: PgPool) {
+    let id = enqueue(&pool, json!({ "sleep": 60 })).await;
+    let config = JobsConfig {
+        shutdown_timeout: Duration::from_millis(100),
+        ..test_jobs_config()
+    };
+    let (runner, handled) = runner(&pool, config, JobOptions::default());
+    let runner = runner.start();
+
+    wait_until("the job runs", || async {
+        !handled.lock().unwrap().is_empty()
+    })
+    .await;
+    runner.shutdown().await;
+
+    let job = sqlx::query!("SELECT status, locked_at FROM jobs WHERE id = $1", id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(job.status, "pending");
+    assert_eq!(job.locked_at, None);
+}
+
+#[sqlx::test]
+async fn test_scheduled_jobs_are_queued_once(pool: PgPool) {
+    let mut runners = Vec::new();
+    let mut handled = Vec::new();
+    for _ in 0..2 {
+        let (mut runner, runner_handled) = runner(&pool, test_jobs_config(), JobOptions::default());
+        runner.schedule("test", Schedule::Every(Duration::from_secs(3600)));
+        runners.push(runner.start());
+        handled.push(runner_handled);
+    }
+
+    wait_until("the scheduled job is done", || async {
+        count_jobs(&pool, "done").await == 1
+    })
+    .await;
+    // A few more polls of both runners do not queue it again
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    for runner in runners {
+        runner.shutdown().await;
+    }
+
+    let total_jobs = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM jobs"#)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(total_jobs, 1);
+    let runs: usize = handled.iter().map(|h| h.lock().unwrap().len()).sum();
+    assert_eq!(runs, 1);
+}
+
+#[sqlx::test]
+async fn test_enqueue_unless_pending(pool: PgPool) {
+    let payload = json!({});
+    assert!(
+        jobs::enqueue_unless_pending(&pool, "test", &payload, Utc::now())
+            .await
+            .unwrap()
+    );
+    assert!(
+        !jobs::enqueue_unless_pending(&pool, "test", &payload, Utc::now())
+            .await
+            .unwrap()
+    );
+    // Other kinds are queued on their own
+    assert!(
+        jobs::enqueue_unless_pending(&pool, "other", &payload, Utc::now())
+            .await
+            .unwrap()
+    );
+    assert_eq!(count_jobs(&pool, "pending").await, 2);
+
+    // Once the pending one ran, another is queued
+    sqlx::query!("UPDATE jobs SET status = 'done' WHERE kind = 'test'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(
+        jobs::enqueue_unless_pending(&pool, "test", &payload, Utc::now())
+            .await
+            .unwrap()
+    );
+}
+
+#[sqlx::test]
+async fn test_finished_jobs_are_pruned(pool: PgPool) {
+    sqlx::query!(
+        r#"
+        INSERT INTO jobs (kind, status, completed_at, run_at) VALUES
+            ('old-done', 'done', NOW() - INTERVAL '8 days', NOW() - INTERVAL '8 days'),
+            ('old-failed', 'failed', NOW() - INTERVAL '8 days', NOW() - INTERVAL '8 days'),
+            ('recent-done', 'done', NOW() - INTERVAL '6 days', NOW() - INTERVAL '6 days'),
+            ('old-pending', 'pending', NULL, NOW() - INTERVAL '8 days')
+        "#
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let (runner, _) = runner(&pool, test_jobs_config(), JobOptions::default());
+    let runner = runner.start();
+
+    wait_until("old jobs are pruned", || async {
+        count_jobs(&pool, "failed").await == 0
+    })
+    .await;
+    runner.shutdown().await;
+
+    let kinds = sqlx::query_scalar!("SELECT kind FROM jobs ORDER BY kind")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(kinds, ["old-pending", "recent-done"]);
+}
@@ -0,0 +1,43 @@
+use backend::likes::{cache::LikeCountCache, LikeCounts};
+use std::time::Duration;
+
+fn counts(total: i64) -> LikeCounts {
+    LikeCounts {
+        total,
+        reactions: [("heart".to_string(), total)].into(),
+        sources: [("web".to_string(), total)].into(),
+    }
+}
+
+#[test]
+fn test_cache_hit_and_invalidation() {
+    let cache = LikeCountCache::new(Duration::from_secs(60));
+    assert_eq!(cache.get("test-post"), None);
+
+    cache.insert("test-post", counts(42));
+    assert_eq!(cache.get("test-post"), Some(counts(42)));
+
+    cache.invalidate("test-post");
+    assert_eq!(cache.get("test-post"), None);
+
+    cache.insert("test-post", counts(43));
+    cache.insert("other-post", counts(1));
+    cache.invalidate_all();
+    assert_eq!(cache.get("test-post"), None);
+    assert_eq!(cache.get("other-post"), None);
+}
+
+#[test]
+fn test_cache_entries_expire() {
+    let cache = LikeCountCache::new(Duration::from_millis(20));
+    cache.insert("test-post", counts(42));
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(cache.get("test-post"), None);
+}
+
+#[test]
+fn test_zero_ttl_disables_cache() {
+    let cache = LikeCountCache::new(Duration::ZERO);
+    cache.insert("test-post", counts(42));
+    assert_eq!(cache.get("test-post"), None);
+}
@@ -0,0 +1,219 @@
+use axum::{
+    http::{header, StatusCode},
+    middleware,
+    routing::{get, post},
+    Router,
+};
+use axum_test::TestServer;
+use backend::{
+    correlation::correlation_middleware,
+    events::EventBus,
+    likes::{
+        self,
+        cache::LikeCountCache,
+        counters::{
+            self, reconcile_like_counts, reconcile_reaction_counts, reconcile_source_counts,
+        },
+    },
+    state::AppState,
+    trusted_proxies::{parse_networks, TrustedProxies, DEFAULT_TRUSTED_PROXIES},
+};
+use chrono::Utc;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::{net::SocketAddr, sync::Arc};
+use test_utils::{counts, insert_post, insert_web_like, stored_counts, wait_until};
+
+mod test_utils;
+
+fn server(pool: PgPool) -> TestServer {
+    server_with_cache(pool, test_utils::test_state().like_counts)
+}
+
+fn server_with_cache(pool: PgPool, like_counts: Arc<LikeCountCache>) -> TestServer {
+    let trusted_proxies =
+        TrustedProxies::new(parse_networks(DEFAULT_TRUSTED_PROXIES, ',').unwrap());
+    let app = Router::new()
+        .route("/like/:post_slug", post(likes::like_post))
+        .route("/likes/:post_slug", get(likes::get_likes))
+        .route("/likes/:post_slug/token", get(likes::get_like_token))
+        .route("/admin/likes/reconcile", post(counters::reconcile_now))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(trusted_proxies),
+            correlation_middleware,
+        ))
+        .with_state(AppState {
+            pool,
+            like_counts,
+            ..test_utils::test_state()
+        });
+    TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>()).unwrap()
+}
+
+async fn like(server: &TestServer, slug: &str, client_ip: &str) -> Value {
+    server
+        .post(&format!("/like/{slug}"))
+        .add_header("x-forwarded-for", client_ip)
+        .await
+        .json()
+}
+
+#[sqlx::test]
+async fn test_likes_bump_the_counters(pool: PgPool) {
+    insert_post(&pool, "counted").await;
+    let server = server(pool.clone());
+
+    let first = like(&server, "counted", "198.51.100.1").await;
+    assert_eq!(first["success"], true);
+    assert_eq!(first["total_likes"], 1);
+    let second = like(&server, "counted", "198.51.100.2").await;
+    assert_eq!(second["total_likes"], 2);
+    assert_eq!(second["reactions"]["heart"], 2);
+    assert_eq!(second["sources"]["web"], 2);
+
+    // Rejected in the same hour, the counters are left alone
+    let repeated = like(&server, "counted", "198.51.100.1").await;
+    assert_eq!(repeated["success"], false);
+    assert_eq!(repeated["total_likes"], 2);
+    assert_eq!(
+        stored_counts(&pool, "counted").await,
+        (2, counts(&[("heart", 2)]), counts(&[("web", 2)]))
+    );
+
+    let read: Value = server.get("/likes/counted").await.json();
+    assert_eq!(read["total_likes"], 2);
+}
+
+#[sqlx::test]
+async fn test_counts_are_shared_and_tokens_are_not(pool: PgPool) {
+    insert_post(&pool, "cached").await;
+    let server = server(pool.clone());
+    like(&server, "cached", "198.51.100.1").await;
+
+    let read = |client_ip: &'static str| {
+        server
+            .get("/likes/cached")
+            .add_header("x-forwarded-for", client_ip)
+    };
+    let first = read("198.51.100.1").await;
+    let second = read("198.51.100.2").await;
+    assert_eq!(first.header(header::CACHE_CONTROL), "public, max-age=10");
+    assert_eq!(first.header(header::ETAG), second.header(header::ETAG));
+    assert_eq!(first.text(), second.text());
+    let body: Value = first.json();
+    assert_eq!(body.get("like_token"), None);
+
+    let revalidated = read("198.51.100.3")
+        .add_header(header::IF_NONE_MATCH, first.header(header::ETAG))
+        .await;
+    revalidated.assert_status(StatusCode::NOT_MODIFIED);
+
+    let token = |client_ip: &'static str| {
+        server
+            .get("/likes/cached/token")
+            .add_header("x-forwarded-for", client_ip)
+    };
+    let first = token("198.51.100.1").await;
+    let second = token("198.51.100.2").await;
+    assert_eq!(first.header(header::CACHE_CONTROL), "no-store");
+    let first: Value = first.json();
+    let second: Value = second.json();
+    assert!(first["like_token"].is_string());
+    assert_ne!(first["like_token"], second["like_token"]);
+}
+
+#[sqlx::test]
+async fn test_reconcile_repairs_drifted_counters(pool: PgPool) {
+    insert_post(&pool, "drifted").await;
+    insert_post(&pool, "consistent").await;
+    let server = server(pool.clone());
+    like(&server, "drifted", "198.51.100.1").await;
+    like(&server, "drifted", "198.51.100.2").await;
+    like(&server, "consistent", "198.51.100.1").await;
+
+    // A like deleted by hand and counters bumped by hand
+    sqlx::query!(
+        "DELETE FROM blog_post_likes WHERE id = (SELECT MIN(id) FROM blog_post_likes WHERE post_slug = 'drifted')"
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE blog_post_source_counts SET count = 7 WHERE post_slug = 'drifted'")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(reconcile_like_counts(&pool).await.unwrap(), 1);
+    assert_eq!(reconcile_reaction_counts(&pool).await.unwrap(), 1);
+    assert_eq!(reconcile_source_counts(&pool).await.unwrap(), 1);
+    assert_eq!(
+        stored_counts(&pool, "drifted").await,
+        (1, counts(&[("heart", 1)]), counts(&[("web", 1)]))
+    );
+    assert_eq!(
+        stored_counts(&pool, "consistent").await,
+        (1, counts(&[("heart", 1)]), counts(&[("web", 1)]))
+    );
+
+    // Once repaired there is nothing left to do
+    assert_eq!(reconcile_like_counts(&pool).await.unwrap(), 0);
+    assert_eq!(reconcile_reaction_counts(&pool).await.unwrap(), 0);
+    assert_eq!(reconcile_source_counts(&pool).await.unwrap(), 0);
+
+    // Counters of likes that are all gone drop to zero
+    sqlx::query!("DELETE FROM blog_post_likes WHERE post_slug = 'consistent'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(reconcile_like_counts(&pool).await.unwrap(), 1);
+    assert_eq!(reconcile_reaction_counts(&pool).await.unwrap(), 1);
+    assert_eq!(reconcile_source_counts(&pool).await.unwrap(), 1);
+    assert_eq!(
+        stored_counts(&pool, "consistent").await,
+        (0, counts(&[("heart", 0)]), counts(&[("web", 0)]))
+    );
+}
+
+#[sqlx::test]
+async fn test_reconcile_drops_the_cached_counts(pool: PgPool) {
+    insert_post(&pool, "recounted").await;
+    insert_post(&pool, "untouched").await;
+    let cache = test_utils::test_state().like_counts;
+    EventBus::start(pool.clone())
+        .await
+        .unwrap()
+        .register(cache.clone());
+    let server = server_with_cache(pool.clone(), cache.clone());
+    like(&server, "recounted", "198.51.100.1").await;
+    like(&server, "untouched", "198.51.100.1").await;
+
+    let total = |slug: &'static str| {
+        let server = &server;
+        async move {
+            let read: Value = server.get(&format!("/likes/{slug}")).await.json();
+            read["total_likes"].as_i64().unwrap()
+        }
+    };
+    // Likes stored behind the counters' back
+    let drift = |user_ip_hash: &'static str| {
+        insert_web_like(&pool, "recounted", "heart", user_ip_hash, Utc::now())
+    };
+    assert_eq!(total("recounted").await, 1);
+    assert_eq!(total("untouched").await, 1);
+
+    // The scheduled job has no cache at hand, its event drops the repaired post
+    drift("missed-1").await;
+    reconcile_like_counts(&pool).await.unwrap();
+    wait_until("the repaired post is no longer cached", || async {
+        cache.get("recounted").is_none()
+    })
+    .await;
+    assert!(cache.get("untouched").is_some());
+    assert_eq!(total("recounted").await, 2);
+
+    // Repaired from the admin API the counts are fresh right away
+    drift("missed-2").await;
+    let response: Value = server.post("/admin/likes/reconcile").await.json();
+    assert_eq!(response["posts_repaired"], 1);
+    assert_eq!(total("recounted").await, 3);
+}
@@ -0,0 +1,118 @@
+use backend::likes::{
+    counters::{reconcile_like_counts, reconcile_reaction_counts, reconcile_source_counts},
+    sources::{record_fediverse_like, remove_fediverse_like, FEDIVERSE_BOOST, FEDIVERSE_FAVOURITE},
+    LikeCounts,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use test_utils::{counts, insert_post, insert_web_like, stored_counts};
+
+mod test_utils;
+
+const ALICE: &str = "https://mastodon.example/users/alice";
+const BOB: &str = "https://fosstodon.example/users/bob";
+
+fn like_counts(total: i64, reactions: &[(&str, i64)], sources: &[(&str, i64)]) -> LikeCounts {
+    LikeCounts {
+        total,
+        reactions: counts(reactions).into_iter().collect(),
+        sources: counts(sources).into_iter().collect(),
+    }
+}
+
+#[sqlx::test]
+async fn test_fediverse_likes_are_counted_once_per_actor(pool: PgPool) {
+    insert_post(&pool, "federated").await;
+    insert_web_like(&pool, "federated", "heart", "a1b2c3", Utc::now()).await;
+    reconcile_like_counts(&pool).await.unwrap();
+    reconcile_reaction_counts(&pool).await.unwrap();
+    reconcile_source_counts(&pool).await.unwrap();
+
+    let favourited = record_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(
+        favourited,
+        Some(like_counts(
+            2,
+            &[("favourite", 1), ("heart", 1)],
+            &[("fediverse", 1), ("web", 1)]
+        ))
+    );
+
+    // Delivered again, nothing changes
+    let redelivered = record_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(redelivered, None);
+
+    // A boost of the same actor is a reaction of its own
+    let boosted = record_fediverse_like(&pool, "federated", FEDIVERSE_BOOST, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(
+        boosted,
+        Some(like_counts(
+            3,
+            &[("boost", 1), ("favourite", 1), ("heart", 1)],
+            &[("fediverse", 2), ("web", 1)]
+        ))
+    );
+    assert_eq!(
+        stored_counts(&pool, "federated").await,
+        (
+            3,
+            counts(&[("boost", 1), ("favourite", 1), ("heart", 1)]),
+            counts(&[("fediverse", 2), ("web", 1)])
+        )
+    );
+}
+
+#[sqlx::test]
+async fn test_undone_fediverse_likes_are_removed(pool: PgPool) {
+    insert_post(&pool, "federated").await;
+    record_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    record_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, BOB)
+        .await
+        .unwrap();
+
+    // Undo of a favourite that was never recorded
+    let unknown = remove_fediverse_like(&pool, "federated", FEDIVERSE_BOOST, BOB)
+        .await
+        .unwrap();
+    assert_eq!(unknown, None);
+
+    let undone = remove_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(
+        undone,
+        Some(like_counts(1, &[("favourite", 1)], &[("fediverse", 1)]))
+    );
+
+    // Delivered again, nothing changes
+    let redelivered = remove_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(redelivered, None);
+
+    let undone = remove_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, BOB)
+        .await
+        .unwrap();
+    assert_eq!(undone, Some(like_counts(0, &[], &[])));
+    assert_eq!(
+        stored_counts(&pool, "federated").await,
+        (0, counts(&[("favourite", 0)]), counts(&[("fediverse", 0)]))
+    );
+
+    // Favourited again after the undo
+    let favourited = record_fediverse_like(&pool, "federated", FEDIVERSE_FAVOURITE, ALICE)
+        .await
+        .unwrap();
+    assert_eq!(
+        favourited,
+        Some(like_counts(1, &[("favourite", 1)], &[("fediverse", 1)]))
+    );
+}
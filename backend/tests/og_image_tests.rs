@@ -0,0 +1,142 @@
+use ab_glyph::FontRef;
+use axum::{routing::get, Router};
+use axum_test::TestServer;
+use backend::og_image::{
+    self, likes_label, render_card, text_width, wrap_text, CardData, OgImages,
+};
+use chrono::{TimeZone, Utc};
+use std::time::Duration;
+
+mod test_utils;
+
+fn card() -> CardData {
+    CardData {
+        title: "Writing a blog backend in Rust".to_string(),
+        date: Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+        tags: vec!["rust".to_string(), "nix".to_string()],
+        likes: 42,
+    }
+}
+
+fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(include_bytes!("../assets/fonts/DejaVuSans.ttf")).unwrap()
+}
+
+#[test]
+fn test_card_is_a_png_of_open_graph_size() {
+    let png = render_card(&card(), "flakm.com");
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+    let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+    assert_eq!((width, height), (og_image::WIDTH, og_image::HEIGHT));
+}
+
+#[test]
+fn test_content_hash_follows_what_is_drawn() {
+    let hash = card().content_hash("flakm.com");
+    assert_eq!(hash, card().content_hash("flakm.com"));
+    assert_eq!(hash.len(), 64);
+
+    // One more like is not worth a new card, reaching the next bucket is
+    let liked = |likes| CardData { likes, ..card() }.content_hash("flakm.com");
+    assert_eq!(hash, liked(43));
+    assert_eq!(hash, liked(49));
+    assert_ne!(hash, liked(50));
+    assert_ne!(hash, card().content_hash("example.com"));
+
+    // Tags are separated, so moving a letter between them changes the hash
+    let retagged = CardData {
+        tags: vec!["rustn".to_string(), "ix".to_string()],
+        ..card()
+    };
+    assert_ne!(hash, retagged.content_hash("flakm.com"));
+}
+
+#[test]
+fn test_likes_label_buckets() {
+    let label = |likes| {
+        likes_label(likes)
+            .trim_start_matches("\u{2665} ")
+            .to_string()
+    };
+    assert_eq!(label(0), "0 likes");
+    assert_eq!(label(1), "1 like");
+    assert_eq!(label(9), "9 likes");
+    assert_eq!(label(10), "10+ likes");
+    assert_eq!(label(19), "10+ likes");
+    assert_eq!(label(42), "20+ likes");
+    assert_eq!(label(999), "500+ likes");
+    assert_eq!(label(1_000), "1k+ likes");
+    assert_eq!(label(2_345), "2k+ likes");
+    assert_eq!(label(5_000_000), "5M+ likes");
+    assert_eq!(label(-3), "0 likes");
+}
+
+#[test]
+fn test_wrap_text_breaks_long_titles() {
+    let font = font();
+    let text = "one two three four five six seven eight nine ten";
+    let max_width = text_width(&font, 20.0, "one two three");
+
+    let lines = wrap_text(&font, 20.0, text, max_width, 10);
+    assert!(lines.len() > 1);
+    assert_eq!(lines.join(" "), text);
+    assert!(lines
+        .iter()
+        .all(|line| text_width(&font, 20.0, line) <= max_width));
+
+    let cut = wrap_text(&font, 20.0, text, max_width, 2);
+    assert_eq!(cut.len(), 2);
+    assert!(cut[1].ends_with('\u{2026}'));
+    assert!(text_width(&font, 20.0, &cut[1]) <= max_width);
+
+    // A word wider than the line is broken instead of overflowing
+    let long = wrap_text(&font, 20.0, "supercalifragilistic", max_width / 3.0, 10);
+    assert!(long.len() > 1);
+    assert_eq!(long.concat(), "supercalifragilistic");
+}
+
+#[test]
+fn test_prune_cache_keeps_recent_cards() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cards");
+    std::fs::create_dir_all(&cache_dir).unwrap();
+    let stale = cache_dir.join("stale.png");
+    let fresh = cache_dir.join("fresh.png");
+    std::fs::write(&stale, b"png").unwrap();
+    std::fs::write(&fresh, b"png").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&stale)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(7200))
+        .unwrap();
+
+    let og_images = OgImages {
+        cache_dir: cache_dir.clone(),
+        brand: "flakm.com".to_string(),
+        max_age: Duration::from_secs(3600),
+    };
+    assert_eq!(og_images.prune_cache().unwrap(), 1);
+    assert!(!stale.exists());
+    assert!(fresh.exists());
+
+    std::fs::remove_dir_all(&cache_dir).unwrap();
+    assert_eq!(og_images.prune_cache().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_og_image_requires_png_extension() {
+    let app = Router::new()
+        .route("/og/:file", get(og_image::og_image))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app).unwrap();
+
+    server.get("/og/test-post").await.assert_status_not_found();
+    server
+        .get("/og/test-post.jpg")
+        .await
+        .assert_status_not_found();
+}
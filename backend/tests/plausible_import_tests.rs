@@ -0,0 +1,136 @@
+use backend::plausible_import::{read_export, Counts, DateRange, PostPaths};
+use chrono::NaiveDate;
+use std::io::{Cursor, Write};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+fn export_zip(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    let mut cursor = zip.finish().unwrap();
+    cursor.set_position(0);
+    cursor
+}
+
+fn day(day: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap()
+}
+
+fn counts(visitors: i64, pageviews: i64) -> Counts {
+    Counts {
+        visitors,
+        pageviews,
+    }
+}
+
+#[test]
+fn test_reads_dashboard_export() {
+    let export = read_export(export_zip(&[
+        (
+            "visitors.csv",
+            "date,visitors,pageviews,visits,views_per_visit,bounce_rate,visit_duration\n\
+             2024-01-01,10,15,11,1.4,60,30\n\
+             2024-01-02,20,25,21,1.2,55,40\n",
+        ),
+        (
+            "pages.csv",
+            "name,visitors,pageviews,bounce_rate,time_on_page\n/posts/rust/,7,9,50,60\n",
+        ),
+        (
+            "sources.csv",
+            "name,visitors,bounce_rate,visit_duration\nGoogle,5,40,30\n",
+        ),
+        ("countries.csv", "name,visitors\nPoland,4\n"),
+        ("devices.csv", "name,visitors\nDesktop,4\n"),
+    ]))
+    .unwrap();
+
+    let period = DateRange {
+        start: day("2024-01-01"),
+        end: day("2024-01-02"),
+    };
+    assert_eq!(export.visitors.len(), 2);
+    assert_eq!(export.visitors[&day("2024-01-02")], counts(20, 25));
+    assert_eq!(
+        export.pages[&("/posts/rust/".to_string(), period)],
+        counts(7, 9)
+    );
+    assert_eq!(
+        export.sources[&("Google".to_string(), period)],
+        counts(5, 0)
+    );
+    assert_eq!(
+        export.countries[&("Poland".to_string(), period)],
+        counts(4, 0)
+    );
+}
+
+#[test]
+fn test_reads_site_export_and_sums_rows() {
+    let export = read_export(export_zip(&[
+        (
+            "imported_visitors_20240101_20240102.csv",
+            "date,visitors,pageviews,bounces,visits,visit_duration\n2024-01-01,3,4,1,3,10\n",
+        ),
+        (
+            "imported_pages_20240101_20240102.csv",
+            "date,hostname,page,visits,visitors,pageviews\n\
+             2024-01-01,example.com,/posts/rust,2,2,3\n\
+             2024-01-01,www.example.com,/posts/rust,1,1,1\n",
+        ),
+        (
+            "imported_locations_20240101_20240102.csv",
+            "date,country,region,city,visitors,visits\n\
+             2024-01-01,PL,PL-14,0,2,2\n\
+             2024-01-01,PL,PL-12,0,1,1\n",
+        ),
+    ]))
+    .unwrap();
+
+    let first = DateRange {
+        start: day("2024-01-01"),
+        end: day("2024-01-01"),
+    };
+    assert_eq!(
+        export.pages[&("/posts/rust".to_string(), first)],
+        counts(3, 4)
+    );
+    assert_eq!(export.countries[&("PL".to_string(), first)], counts(3, 0));
+}
+
+#[test]
+fn test_undated_files_need_visitors() {
+    let result = read_export(export_zip(&[(
+        "pages.csv",
+        "name,visitors,pageviews\n/,1,1\n",
+    )]));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_rejects_non_zip() {
+    assert!(read_export(Cursor::new(b"date,visitors\n".to_vec())).is_err());
+}
+
+#[test]
+fn test_matches_pages_to_posts() {
+    let paths = PostPaths::new([
+        (
+            "rust".to_string(),
+            "https://example.com/posts/rust/".to_string(),
+        ),
+        (
+            "nix".to_string(),
+            "https://example.com/posts/nix".to_string(),
+        ),
+    ]);
+
+    assert_eq!(paths.slug_for("/posts/rust/"), Some("rust"));
+    assert_eq!(paths.slug_for("/posts/rust"), Some("rust"));
+    assert_eq!(paths.slug_for("/posts/nix/?utm_source=x"), Some("nix"));
+    assert_eq!(paths.slug_for("/posts/nix#comments"), Some("nix"));
+    assert_eq!(paths.slug_for("/"), None);
+    assert_eq!(paths.slug_for("/posts/other/"), None);
+}
@@ -0,0 +1,199 @@
+use axum::{http::StatusCode, middleware};
+use axum_test::TestServer;
+use backend::{
+    admin,
+    correlation::correlation_middleware,
+    correlation::CorrelationContext,
+    events::{BackendEvent, EventBus},
+    likes::{
+        counters::{reconcile_like_counts, reconcile_reaction_counts, reconcile_source_counts},
+        sources::record_fediverse_like,
+    },
+    privacy::{erase_likes, ErasureRequester, LikeOwner, LikesExport},
+    state::AppState,
+    trusted_proxies::TrustedProxies,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use test_utils::{counts, insert_post, insert_web_like, stored_counts};
+
+mod test_utils;
+
+const ERASED: &str = "a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2";
+const KEPT: &str = "0f0e0d0c0b0a0f0e0d0c0b0a0f0e0d0c0b0a0f0e0d0c0b0a0f0e0d0c0b0a0f0e";
+
+fn correlation_ctx() -> CorrelationContext {
+    CorrelationContext {
+        correlation_id: "erase-test".to_string(),
+        request_id: "erase-test-request".to_string(),
+        session_id: None,
+        user_id: None,
+        user_agent: None,
+        remote_ip: None,
+        forwarded_for: None,
+        via_cloudflare: false,
+    }
+}
+
+#[sqlx::test]
+async fn test_erasure_decrements_counters_and_publishes_removals(pool: PgPool) {
+    let now = Utc::now();
+    insert_post(&pool, "first").await;
+    insert_post(&pool, "second").await;
+    insert_post(&pool, "untouched").await;
+    insert_web_like(&pool, "first", "heart", ERASED, now).await;
+    insert_web_like(&pool, "first", "fire", ERASED, now).await;
+    insert_web_like(&pool, "first", "heart", KEPT, now).await;
+    insert_web_like(&pool, "second", "heart", ERASED, now).await;
+    insert_web_like(&pool, "untouched", "heart", KEPT, now).await;
+    reconcile_like_counts(&pool).await.unwrap();
+    reconcile_reaction_counts(&pool).await.unwrap();
+    reconcile_source_counts(&pool).await.unwrap();
+    record_fediverse_like(
+        &pool,
+        "first",
+        "heart",
+        "https://mastodon.example/users/alice",
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    let bus = EventBus::start(pool.clone()).await.unwrap();
+    let mut events = bus.subscribe();
+
+    let erased = erase_likes(
+        &pool,
+        LikeOwner::IpHash(ERASED),
+        ErasureRequester::Admin,
+        &correlation_ctx(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(erased, 3);
+
+    assert_eq!(
+        stored_counts(&pool, "first").await,
+        (
+            2,
+            counts(&[("fire", 0), ("heart", 2)]),
+            counts(&[("fediverse", 1), ("web", 1)])
+        )
+    );
+    assert_eq!(
+        stored_counts(&pool, "second").await,
+        (0, counts(&[("heart", 0)]), counts(&[("web", 0)]))
+    );
+    assert_eq!(
+        stored_counts(&pool, "untouched").await,
+        (1, counts(&[("heart", 1)]), counts(&[("web", 1)]))
+    );
+
+    let audit =
+        sqlx::query!("SELECT requested_by, likes_erased, correlation_id FROM like_erasures")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(audit.requested_by, "admin");
+    assert_eq!(audit.likes_erased, 3);
+    assert_eq!(audit.correlation_id.as_deref(), Some("erase-test"));
+
+    let mut removed = Vec::new();
+    while removed.len() < 3 {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("like removals are published")
+            .unwrap();
+        removed.push(event);
+    }
+    let removal =
+        |post_slug: &str, reaction: &str, total_likes, reaction_count| BackendEvent::LikeRemoved {
+            post_slug: post_slug.to_string(),
+            reaction: reaction.to_string(),
+            total_likes,
+            reaction_count,
+        };
+    assert_eq!(
+        removed,
+        vec![
+            removal("first", "fire", 2, 0),
+            removal("first", "heart", 2, 2),
+            removal("second", "heart", 0, 0),
+        ]
+    );
+
+    // Nothing is left to erase, and nothing is published
+    let erased = erase_likes(
+        &pool,
+        LikeOwner::IpHash(ERASED),
+        ErasureRequester::Visitor,
+        &correlation_ctx(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(erased, 0);
+    assert!(events.try_recv().is_err());
+}
+
+#[sqlx::test]
+async fn test_likes_of_an_actor_are_exported_and_erased(pool: PgPool) {
+    const ALICE: &str = "https://mastodon.example/users/alice";
+    const BOB: &str = "https://fosstodon.example/users/bob";
+    insert_post(&pool, "federated").await;
+    insert_web_like(&pool, "federated", "heart", KEPT, Utc::now()).await;
+    reconcile_like_counts(&pool).await.unwrap();
+    reconcile_reaction_counts(&pool).await.unwrap();
+    reconcile_source_counts(&pool).await.unwrap();
+    for (reaction, actor) in [("favourite", ALICE), ("boost", ALICE), ("favourite", BOB)] {
+        record_fediverse_like(&pool, "federated", reaction, actor)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    let app = admin::router()
+        .layer(middleware::from_fn_with_state(
+            Arc::new(TrustedProxies::default()),
+            correlation_middleware,
+        ))
+        .with_state(AppState {
+            pool: pool.clone(),
+            ..test_utils::test_state()
+        });
+    let server = TestServer::new(app.into_make_service_with_connect_info::<SocketAddr>()).unwrap();
+
+    let response = server
+        .get("/admin/likes/actor")
+        .add_query_param("actor", ALICE)
+        .await;
+    response.assert_status_ok();
+    let export: LikesExport = response.json();
+    assert_eq!(export.remote_actor.as_deref(), Some(ALICE));
+    assert_eq!(export.user_ip_hash, None);
+    let mut reactions: Vec<_> = export.likes.iter().map(|l| l.reaction.as_str()).collect();
+    reactions.sort();
+    assert_eq!(reactions, ["boost", "favourite"]);
+
+    let response = server
+        .delete("/admin/likes/actor")
+        .add_query_param("actor", ALICE)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<serde_json::Value>()["likes_erased"], 2);
+    assert_eq!(
+        stored_counts(&pool, "federated").await,
+        (
+            2,
+            counts(&[("boost", 0), ("favourite", 1), ("heart", 1)]),
+            counts(&[("fediverse", 1), ("web", 1)])
+        )
+    );
+
+    // Only actor ids are accepted
+    server
+        .delete("/admin/likes/actor")
+        .add_query_param("actor", "alice@mastodon.example")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,30 @@
+use backend::likes::reactions::{Reactions, DEFAULT_REACTIONS};
+
+#[test]
+fn test_default_reactions() {
+    let reactions = Reactions::new(DEFAULT_REACTIONS.split(','));
+    assert_eq!(reactions.default_reaction(), "heart");
+    assert!(reactions.is_allowed("rocket"));
+    assert!(reactions.is_allowed("learned-something"));
+    assert!(!reactions.is_allowed("thumbs-down"));
+}
+
+#[test]
+fn test_first_reaction_is_the_default() {
+    let reactions = Reactions::new(["rocket", "heart"]);
+    assert_eq!(reactions.default_reaction(), "rocket");
+    assert!(reactions.is_allowed("heart"));
+}
+
+#[test]
+fn test_invalid_reaction_names_are_dropped() {
+    let reactions = Reactions::new([" Heart", "", "a/b", "fire"]);
+    assert_eq!(reactions.default_reaction(), "fire");
+    assert!(!reactions.is_allowed("Heart"));
+    assert!(!reactions.is_allowed("a/b"));
+
+    let too_long = "x".repeat(33);
+    let reactions = Reactions::new([too_long.as_str()]);
+    assert_eq!(reactions.default_reaction(), "heart");
+    assert!(!reactions.is_allowed(&too_long));
+}
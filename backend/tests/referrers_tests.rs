@@ -0,0 +1,79 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use backend::referrers::Referrer;
+
+fn headers(referer: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::HOST, HeaderValue::from_static("blog.flakm.com"));
+    if let Some(referer) = referer {
+        headers.insert(header::REFERER, HeaderValue::from_str(referer).unwrap());
+    }
+    headers
+}
+
+#[test]
+fn test_document_referrer_reduced_to_host() {
+    let referrer = Referrer::from_request(
+        &headers(Some("https://blog.flakm.com/posts/rust/")),
+        Some("https://news.ycombinator.com/item?id=123"),
+    );
+    assert_eq!(referrer.host.as_deref(), Some("news.ycombinator.com"));
+    assert_eq!(referrer.utm_source, None);
+}
+
+#[test]
+fn test_hosts_are_normalized() {
+    for (url, host) in [
+        ("https://www.reddit.com/r/rust/comments/abc", "reddit.com"),
+        ("https://old.reddit.com/r/rust/", "reddit.com"),
+        ("https://M.Facebook.com/", "facebook.com"),
+        ("https://www.com/", "www.com"),
+        ("https://mastodon.social/@flakm/1", "mastodon.social"),
+    ] {
+        let referrer = Referrer::from_request(&headers(None), Some(url));
+        assert_eq!(referrer.host.as_deref(), Some(host), "{url}");
+    }
+}
+
+#[test]
+fn test_utm_parameters_from_page() {
+    let referrer = Referrer::from_request(
+        &headers(Some(
+            "https://blog.flakm.com/posts/rust/?utm_source=Mastodon&utm_medium=social&utm_campaign=%20Launch%20",
+        )),
+        None,
+    );
+    assert_eq!(referrer.host, None);
+    assert_eq!(referrer.utm_source.as_deref(), Some("mastodon"));
+    assert_eq!(referrer.utm_medium.as_deref(), Some("social"));
+    assert_eq!(referrer.utm_campaign.as_deref(), Some("launch"));
+}
+
+#[test]
+fn test_own_site_is_direct() {
+    let referrer = Referrer::from_request(
+        &headers(Some("https://blog.flakm.com/posts/rust/")),
+        Some("https://www.blog.flakm.com/"),
+    );
+    assert!(referrer.is_direct());
+    assert!(Referrer::from_request(&headers(None), None).is_direct());
+    assert!(Referrer::from_request(&headers(None), Some("not a url")).is_direct());
+}
+
+#[test]
+fn test_referer_header_from_another_site() {
+    let referrer = Referrer::from_request(&headers(Some("https://lobste.rs/s/abc")), None);
+    assert_eq!(referrer.host.as_deref(), Some("lobste.rs"));
+}
+
+#[test]
+fn test_overlong_host_is_direct() {
+    let label = "a".repeat(63);
+    let host = [label.as_str(); 4].join(".");
+    assert_eq!(host.len(), 255);
+    let referrer = Referrer::from_request(&headers(None), Some(&format!("https://{host}/")));
+    assert!(referrer.is_direct());
+
+    let longest = format!("{}.com", "a".repeat(249));
+    let referrer = Referrer::from_request(&headers(None), Some(&format!("https://{longest}/")));
+    assert_eq!(referrer.host.as_deref(), Some(longest.as_str()));
+}
@@ -0,0 +1,79 @@
+use backend::retention;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use test_utils::{insert_post, insert_web_like};
+
+mod test_utils;
+
+const HASH: &str = "a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2c3d4e5f6a1b2";
+
+#[sqlx::test]
+async fn test_prune_strips_metadata_of_old_likes(pool: PgPool) {
+    let now = Utc::now();
+    insert_post(&pool, "kept").await;
+    for days in [91, 120, 365] {
+        insert_web_like(&pool, "kept", "heart", HASH, now - Duration::days(days)).await;
+    }
+    insert_web_like(&pool, "kept", "heart", HASH, now - Duration::days(89)).await;
+    // Metadata left behind on an old like without a hashed IP
+    sqlx::query!(
+        r#"
+        INSERT INTO blog_post_likes (post_slug, reaction, user_agent, cf_country, liked_at, hour_bucket)
+        VALUES ('kept', 'heart', 'curl/8.0', 'PL', $1, '2020-01-01 00')
+        "#,
+        now - Duration::days(200)
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    // Favourite of a fediverse account, only the actor identifies it
+    sqlx::query!(
+        r#"
+        INSERT INTO blog_post_likes (post_slug, reaction, source, remote_actor, liked_at, hour_bucket)
+        VALUES ('kept', 'favourite', 'fediverse', 'https://mastodon.example/users/alice', $1, '2020-01-01 00')
+        "#,
+        now - Duration::days(150)
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE blog_post_likes SET cf_country = 'DE' WHERE remote_actor IS NULL")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // Batches smaller than the old likes, the job keeps going until all are done
+    let stripped = retention::prune(&pool, now - Duration::days(90), 2)
+        .await
+        .unwrap();
+    assert_eq!(stripped, 5);
+
+    let likes = sqlx::query!(
+        r#"
+        SELECT liked_at, user_agent, user_ip_hash, cf_connecting_ip_hash, cf_country, remote_actor
+        FROM blog_post_likes ORDER BY liked_at
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    // The likes themselves stay, they are still counted
+    assert_eq!(likes.len(), 6);
+    for like in &likes[..5] {
+        assert_eq!(like.user_agent, None);
+        assert_eq!(like.user_ip_hash, None);
+        assert_eq!(like.cf_connecting_ip_hash, None);
+        assert_eq!(like.cf_country, None);
+        assert_eq!(like.remote_actor, None);
+    }
+    let recent = &likes[5];
+    assert_eq!(recent.user_agent.as_deref(), Some("Mozilla/5.0"));
+    assert_eq!(recent.user_ip_hash.as_deref(), Some(HASH));
+    assert_eq!(recent.cf_connecting_ip_hash.as_deref(), Some(HASH));
+    assert_eq!(recent.cf_country.as_deref(), Some("DE"));
+
+    let again = retention::prune(&pool, now - Duration::days(90), 2)
+        .await
+        .unwrap();
+    assert_eq!(again, 0);
+}
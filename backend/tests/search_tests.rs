@@ -0,0 +1,69 @@
+use axum::{routing::get, Router};
+use axum_test::TestServer;
+use backend::search::{self, highlight, validate_query, MAX_QUERY_CHARS};
+use sqlx::PgPool;
+use test_utils::insert_post;
+
+mod test_utils;
+
+#[test]
+fn test_validate_query() {
+    assert_eq!(validate_query("  rust nix "), Ok("rust nix"));
+    assert_eq!(
+        validate_query("\"a phrase\" -java"),
+        Ok("\"a phrase\" -java")
+    );
+    assert_eq!(validate_query(""), Err("empty_query"));
+    assert_eq!(validate_query("   "), Err("empty_query"));
+    assert_eq!(
+        validate_query(&"ą".repeat(MAX_QUERY_CHARS)),
+        Ok("ą".repeat(MAX_QUERY_CHARS).as_str())
+    );
+    assert_eq!(
+        validate_query(&"a".repeat(MAX_QUERY_CHARS + 1)),
+        Err("query_too_long")
+    );
+}
+
+#[test]
+fn test_highlight_marks_matches_and_escapes_the_rest() {
+    assert_eq!(
+        highlight("Writing \u{e000}Rust\u{e001} & <script> for the \u{e000}blog\u{e001}"),
+        "Writing <mark>Rust</mark> &amp; &lt;script&gt; for the <mark>blog</mark>"
+    );
+    assert_eq!(highlight("\"quoted\" it's"), "&quot;quoted&quot; it&#39;s");
+}
+
+#[tokio::test]
+async fn test_search_rejects_empty_queries() {
+    let app = Router::new()
+        .route("/search", get(search::search_posts))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app).unwrap();
+
+    server.get("/search").await.assert_status_bad_request();
+    server
+        .get("/search")
+        .add_query_param("q", "  ")
+        .await
+        .assert_status_bad_request();
+}
+
+#[sqlx::test]
+async fn test_total_counts_every_match(pool: PgPool) {
+    for slug in ["first", "second", "third"] {
+        insert_post(&pool, slug).await;
+    }
+
+    let page = search::search(&pool, "post", 2, 0).await.unwrap();
+    assert_eq!((page.total, page.results.len()), (3, 2));
+    let last = search::search(&pool, "post", 2, 2).await.unwrap();
+    assert_eq!((last.total, last.results.len()), (3, 1));
+
+    // Paged past the last match, there still are matches
+    let past = search::search(&pool, "post", 2, 10).await.unwrap();
+    assert_eq!((past.total, past.results.len()), (3, 0));
+
+    let none = search::search(&pool, "ferris", 2, 0).await.unwrap();
+    assert_eq!((none.total, none.results.len()), (0, 0));
+}
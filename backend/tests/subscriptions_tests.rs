@@ -0,0 +1,230 @@
+use axum::{routing::post, Router};
+use axum_test::TestServer;
+use backend::subscriptions::{
+    digest::{digest_text, DigestPost},
+    mailer::SmtpMailer,
+    normalize_email, subscribe, Newsletter, SubscribeResponse, SubscriptionTokens,
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+mod test_utils;
+
+/// Undo the soft line breaks and escapes of the quoted-printable bodies
+fn decode_quoted_printable(data: &str) -> String {
+    data.replace("=\n", "").replace("=3D", "=")
+}
+
+/// Accepts every email and keeps the DATA of each
+async fn fake_smtp_server() -> (u16, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let emails = received.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let emails = emails.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+                let mut data: Option<String> = None;
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if let Some(body) = data.as_mut() {
+                        if line == "." {
+                            emails
+                                .lock()
+                                .unwrap()
+                                .push(decode_quoted_printable(&data.take().unwrap()));
+                            writer.write_all(b"250 queued\r\n").await.unwrap();
+                        } else {
+                            body.push_str(&line);
+                            body.push('\n');
+                        }
+                        continue;
+                    }
+                    let reply: &[u8] = match line.get(..4).map(str::to_uppercase).as_deref() {
+                        Some("DATA") => {
+                            data = Some(String::new());
+                            b"354 go ahead\r\n"
+                        }
+                        Some("QUIT") => {
+                            writer.write_all(b"221 bye\r\n").await.unwrap();
+                            break;
+                        }
+                        _ => b"250 ok\r\n",
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+
+    (port, received)
+}
+
+fn newsletter(port: u16) -> Newsletter {
+    Newsletter::new(
+        SubscriptionTokens::new("secret", Duration::days(2)),
+        "https://blog.example.com/api/",
+        "newsletter@example.com".parse().unwrap(),
+        Box::new(SmtpMailer::from_url(&format!("smtp://127.0.0.1:{port}")).unwrap()),
+    )
+}
+
+#[test]
+fn test_confirmation_tokens() {
+    let tokens = SubscriptionTokens::new("secret", Duration::days(2));
+    let now = Utc::now();
+    let token = tokens.confirmation(42, now);
+
+    assert_eq!(tokens.verify_confirmation(&token, now), Some(42));
+    assert_eq!(
+        tokens.verify_confirmation(&token, now + Duration::days(3)),
+        None
+    );
+    assert_eq!(
+        SubscriptionTokens::new("other", Duration::days(2)).verify_confirmation(&token, now),
+        None
+    );
+    let forged = token.replacen("42.", "43.", 1);
+    assert_eq!(tokens.verify_confirmation(&forged, now), None);
+}
+
+#[test]
+fn test_unsubscribe_tokens() {
+    let tokens = SubscriptionTokens::new("secret", Duration::days(2));
+    let token = tokens.unsubscribe(7);
+
+    assert_eq!(tokens.verify_unsubscribe(&token), Some(7));
+    assert_eq!(tokens.verify_unsubscribe("7.00"), None);
+    // Confirmation tokens can not unsubscribe and the other way around
+    assert_eq!(
+        tokens.verify_unsubscribe(&tokens.confirmation(7, Utc::now())),
+        None
+    );
+    assert_eq!(tokens.verify_confirmation(&token, Utc::now()), None);
+}
+
+#[test]
+fn test_normalize_email() {
+    assert_eq!(
+        normalize_email(" Reader@Example.COM ").map(|a| a.to_string()),
+        Some("reader@example.com".to_string())
+    );
+    assert!(normalize_email("not an email").is_none());
+    assert!(normalize_email("").is_none());
+    assert!(normalize_email(&format!("{}@example.com", "a".repeat(250))).is_none());
+}
+
+#[test]
+fn test_digest_text() {
+    let post = |title: &str| DigestPost {
+        title: title.to_string(),
+        description: Some(format!("About {title}")),
+        url: format!("https://blog.example.com/posts/{title}"),
+    };
+
+    let (subject, body) = digest_text(&[post("rust")]);
+    assert_eq!(subject, "New post: rust");
+    assert!(body.contains("About rust\nhttps://blog.example.com/posts/rust"));
+
+    let (subject, body) = digest_text(&[post("rust"), post("nix")]);
+    assert_eq!(subject, "2 new posts");
+    assert!(body.contains("posts/rust") && body.contains("posts/nix"));
+}
+
+#[tokio::test]
+async fn test_confirmation_email_over_smtp() {
+    let (port, received) = fake_smtp_server().await;
+    let newsletter = newsletter(port);
+
+    let message = newsletter
+        .confirmation_email("reader@example.com".parse().unwrap(), 42, Utc::now())
+        .unwrap();
+    newsletter.send(message).await.unwrap();
+
+    let emails = received.lock().unwrap();
+    assert_eq!(emails.len(), 1);
+    assert!(emails[0].contains("Subject: Confirm your subscription"));
+    let token = emails[0]
+        .split("https://blog.example.com/api/subscribe/confirm?token=")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .expect("confirmation link");
+    assert_eq!(
+        newsletter.tokens.verify_confirmation(token, Utc::now()),
+        Some(42)
+    );
+}
+
+#[tokio::test]
+async fn test_subscriber_email_has_unsubscribe_headers() {
+    let (port, received) = fake_smtp_server().await;
+    let newsletter = newsletter(port);
+
+    let message = newsletter
+        .subscriber_email(
+            "reader@example.com".parse().unwrap(),
+            7,
+            "New post: rust",
+            "rust\nhttps://blog.example.com/posts/rust\n",
+        )
+        .unwrap();
+    newsletter.send(message).await.unwrap();
+
+    let emails = received.lock().unwrap();
+    let unsubscribe = format!(
+        "https://blog.example.com/api/unsubscribe?token={}",
+        newsletter.tokens.unsubscribe(7)
+    );
+    assert!(emails[0].contains(&format!("List-Unsubscribe: <{unsubscribe}>")));
+    assert!(emails[0].contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+    assert!(emails[0].contains(&format!("Unsubscribe: {unsubscribe}")));
+}
+
+#[tokio::test]
+async fn test_failed_delivery_is_an_error() {
+    // Nothing listens on the port any more
+    let port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let newsletter = newsletter(port);
+    let message = newsletter
+        .confirmation_email("reader@example.com".parse().unwrap(), 1, Utc::now())
+        .unwrap();
+    assert!(newsletter.send(message).await.is_err());
+}
+
+#[tokio::test]
+async fn test_subscribe_rejects_invalid_requests() {
+    let app = Router::new()
+        .route("/subscribe", post(subscribe))
+        .with_state(test_utils::test_state());
+    let server = TestServer::new(app).unwrap();
+
+    let response = server
+        .post("/subscribe")
+        .add_header("user-agent", "Mozilla/5.0")
+        .json(&json!({ "email": "not an email" }))
+        .await;
+    let body: SubscribeResponse = response.json();
+    assert!(!body.success);
+    assert_eq!(body.message, "Invalid email address");
+
+    let response = server
+        .post("/subscribe")
+        .add_header("user-agent", "curl/8.0")
+        .json(&json!({ "email": "reader@example.com" }))
+        .await;
+    let body: SubscribeResponse = response.json();
+    assert!(!body.success);
+}
@@ -0,0 +1,248 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use backend::{
+    activitypub::Federation,
+    admin,
+    hugo_posts::{BlogRepository, HugoBlogPost},
+    jobs::JobRunner,
+    state::AppState,
+    syndication::{self, PostSyndication, SyndicationTarget, Syndicator, SYNDICATION_JOB},
+};
+use serde_json::json;
+use sqlx::PgPool;
+use std::sync::Arc;
+use test_utils::{insert_post, test_activitypub_config, test_jobs_config, test_key, wait_until};
+
+mod test_utils;
+
+#[test]
+fn test_syndication_targets() {
+    for target in SyndicationTarget::ALL {
+        assert_eq!(SyndicationTarget::parse(target.as_str()), Some(target));
+    }
+    assert_eq!(
+        SyndicationTarget::parse("activitypub"),
+        Some(SyndicationTarget::ActivityPub)
+    );
+    assert_eq!(SyndicationTarget::parse("mastodon"), None);
+}
+
+fn admin_server(pool: PgPool) -> TestServer {
+    let app = admin::router().with_state(AppState {
+        pool,
+        ..test_utils::test_state()
+    });
+    TestServer::new(app).unwrap()
+}
+
+/// Runner publishing the outbox, Bluesky is not configured
+fn start_syndicator(pool: &PgPool, max_attempts: i32) -> backend::jobs::JobRunnerHandle {
+    let federation = Arc::new(Federation::new(test_activitypub_config(), test_key()).unwrap());
+    let mut jobs = JobRunner::new(pool.clone(), test_jobs_config());
+    Syndicator::new(pool.clone(), federation, None, max_attempts).register(&mut jobs);
+    jobs.start()
+}
+
+async fn queue(pool: &PgPool, slug: &str) {
+    insert_post(pool, slug).await;
+    let mut tx = pool.begin().await.unwrap();
+    syndication::enqueue(&mut tx, slug).await.unwrap();
+    tx.commit().await.unwrap();
+}
+
+async fn status(pool: &PgPool, slug: &str, target: &str) -> String {
+    sqlx::query_scalar!(
+        "SELECT status FROM syndication_outbox WHERE post_slug = $1 AND target = $2",
+        slug,
+        target
+    )
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn syndication_of(server: &TestServer, slug: &str) -> PostSyndication {
+    server
+        .get(&format!("/admin/syndication/{slug}"))
+        .await
+        .json()
+}
+
+#[sqlx::test]
+async fn test_posts_are_queued_once_per_target(pool: PgPool) {
+    let repo = BlogRepository { db: pool.clone() };
+    let mut post = json!({
+        "title": "A post",
+        "slug": "queued",
+        "description": "",
+        "date": "2024-01-01T12:00:00Z",
+        "url": "https://flakm.com/posts/queued/"
+    });
+    let ingest = |post: serde_json::Value| {
+        let repo = &repo;
+        async move {
+            let post: HugoBlogPost = serde_json::from_value(post).unwrap();
+            repo.new_blog_entry(&post).await.unwrap();
+        }
+    };
+
+    ingest(post.clone()).await;
+    // Edited posts are not published again
+    post["title"] = json!("An edited post");
+    ingest(post).await;
+
+    let outbox = sqlx::query!(
+        r#"
+        SELECT o.target, jobs.kind AS "kind?", jobs.payload->>'outbox_id' AS outbox_id, o.id
+        FROM syndication_outbox o LEFT JOIN jobs ON jobs.id = o.job_id
+        WHERE o.post_slug = 'queued' ORDER BY o.target
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let targets: Vec<_> = outbox.iter().map(|row| row.target.as_str()).collect();
+    assert_eq!(targets, ["activitypub", "bluesky"]);
+    for row in &outbox {
+        assert_eq!(row.kind.as_deref(), Some(SYNDICATION_JOB));
+        assert_eq!(row.outbox_id, Some(row.id.to_string()));
+    }
+
+    let jobs = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM jobs WHERE kind = $1"#,
+        SYNDICATION_JOB
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(jobs, 2);
+}
+
+#[sqlx::test]
+async fn test_unconfigured_targets_are_skipped(pool: PgPool) {
+    queue(&pool, "published").await;
+
+    let runner = start_syndicator(&pool, 3);
+    wait_until("both targets are finished", || async {
+        status(&pool, "published", "activitypub").await == "done"
+            && status(&pool, "published", "bluesky").await == "skipped"
+    })
+    .await;
+    runner.shutdown().await;
+
+    let server = admin_server(pool.clone());
+    let post = syndication_of(&server, "published").await;
+    assert_eq!(post.targets.len(), 2);
+    for target in &post.targets {
+        // Succeeded on the first attempt, nothing left to retry
+        assert_eq!(target.attempts, Some(1));
+        assert_eq!(target.next_attempt_at, None);
+        assert_eq!(target.last_error, None);
+        assert!(target.completed_at.is_some());
+    }
+
+    // Done cross-posts are not published twice, skipped ones are queued again
+    server
+        .post("/admin/syndication/published/activitypub/retry")
+        .await
+        .assert_status(StatusCode::CONFLICT);
+    server
+        .post("/admin/syndication/published/bluesky/retry")
+        .await
+        .assert_status_ok();
+    assert_eq!(status(&pool, "published", "bluesky").await, "pending");
+}
+
+#[sqlx::test]
+async fn test_failing_target_is_given_up_and_retried(pool: PgPool) {
+    // Nobody listens there, every delivery fails
+    sqlx::query!(
+        "INSERT INTO activitypub_followers (actor_id, inbox) VALUES ($1, $2)",
+        "https://social.example.com/users/ferris",
+        "http://127.0.0.1:9/inbox"
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    queue(&pool, "unreachable").await;
+    let server = admin_server(pool.clone());
+
+    // Pending while its job is waiting to run
+    server
+        .post("/admin/syndication/unreachable/activitypub/retry")
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let runner = start_syndicator(&pool, 2);
+    wait_until("the runner gives up", || async {
+        status(&pool, "unreachable", "activitypub").await == "failed"
+    })
+    .await;
+    runner.shutdown().await;
+
+    let job = sqlx::query!(
+        r#"
+        SELECT jobs.status, jobs.attempts FROM jobs
+        JOIN syndication_outbox o ON o.job_id = jobs.id
+        WHERE o.post_slug = 'unreachable' AND o.target = 'activitypub'
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!((job.status.as_str(), job.attempts), ("failed", 2));
+
+    let post = syndication_of(&server, "unreachable").await;
+    let activitypub = &post.targets[0];
+    assert_eq!(activitypub.target, "activitypub");
+    assert_eq!(activitypub.attempts, Some(2));
+    assert_eq!(activitypub.next_attempt_at, None);
+    assert!(activitypub.last_error.is_some());
+
+    // Listed among the failed cross-posts
+    let failed: Vec<PostSyndication> = server.get("/admin/syndication?status=failed").await.json();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].post_slug, "unreachable");
+    let done: Vec<PostSyndication> = server.get("/admin/syndication?status=done").await.json();
+    assert!(done.is_empty());
+
+    // Retried with a fresh job and attempts
+    let response = server
+        .post("/admin/syndication/unreachable/activitypub/retry")
+        .await;
+    response.assert_status_ok();
+    let post: PostSyndication = response.json();
+    let activitypub = &post.targets[0];
+    assert_eq!(activitypub.status, "pending");
+    assert_eq!(activitypub.attempts, Some(0));
+    assert!(activitypub.next_attempt_at.is_some());
+    assert_eq!(activitypub.last_error, None);
+
+    // And pending again until its new job ran
+    server
+        .post("/admin/syndication/unreachable/activitypub/retry")
+        .await
+        .assert_status(StatusCode::CONFLICT);
+}
+
+#[sqlx::test]
+async fn test_pending_entries_without_a_job_can_be_retried(pool: PgPool) {
+    queue(&pool, "orphaned").await;
+    // Left pending by a job given up before the outbox followed the jobs
+    sqlx::query!("UPDATE jobs SET status = 'failed', completed_at = NOW()")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let server = admin_server(pool.clone());
+    server
+        .post("/admin/syndication/orphaned/activitypub/retry")
+        .await
+        .assert_status_ok();
+    let live =
+        sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM jobs WHERE status = 'pending'"#)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(live, 1);
+}
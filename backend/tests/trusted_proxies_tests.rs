@@ -0,0 +1,53 @@
+use axum::http::{HeaderMap, HeaderValue};
+use backend::trusted_proxies::{load_networks_file, parse_networks, TrustedProxies};
+use std::{io::Write, net::IpAddr};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_parse_networks_accepts_cidrs_and_addresses() {
+    let networks = parse_networks("127.0.0.1, 10.0.0.0/8 ,::1/128,", ',').unwrap();
+    assert_eq!(networks.len(), 3);
+
+    assert!(parse_networks("not-an-ip", ',').is_err());
+}
+
+#[test]
+fn test_load_networks_file_skips_comments() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    writeln!(file, "# Cloudflare ranges").unwrap();
+    writeln!(file, "173.245.48.0/20").unwrap();
+    writeln!(file).unwrap();
+    writeln!(file, "2400:cb00::/32 # ipv6").unwrap();
+
+    let proxies = TrustedProxies::new(load_networks_file(file.path()).unwrap());
+
+    assert!(proxies.is_trusted(ip("173.245.48.1")));
+    assert!(proxies.is_trusted(ip("2400:cb00::1")));
+    assert!(!proxies.is_trusted(ip("203.0.113.1")));
+}
+
+#[test]
+fn test_ipv4_mapped_peer_is_trusted() {
+    let proxies = TrustedProxies::new(parse_networks("127.0.0.1/32", ',').unwrap());
+    assert!(proxies.is_trusted(ip("::ffff:127.0.0.1")));
+}
+
+#[test]
+fn test_garbage_in_forwarded_for_stops_the_walk() {
+    let proxies = TrustedProxies::new(parse_networks("127.0.0.1/32,10.0.0.0/8", ',').unwrap());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        HeaderValue::from_static("203.0.113.5, unknown, 10.0.0.2"),
+    );
+    headers.insert("cf-connecting-ip", HeaderValue::from_static("203.0.113.9"));
+
+    assert_eq!(
+        proxies.resolve_client_ip(ip("127.0.0.1"), &headers),
+        ip("10.0.0.2")
+    );
+}
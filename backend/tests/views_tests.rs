@@ -0,0 +1,78 @@
+use backend::views::{
+    hyperloglog::{HyperLogLog, REGISTERS},
+    VisitorHasher,
+};
+
+fn sketch_of(visitors: impl IntoIterator<Item = u64>, hasher: &VisitorHasher) -> HyperLogLog {
+    let mut sketch = HyperLogLog::default();
+    for visitor in visitors {
+        sketch.insert(hasher.hash(&format!("ip-hash-{visitor}")));
+    }
+    sketch
+}
+
+fn assert_close(estimate: u64, actual: u64) {
+    let error = (estimate as f64 - actual as f64).abs() / actual as f64;
+    assert!(
+        error < 0.1,
+        "estimate {estimate} too far from {actual} ({:.1}%)",
+        error * 100.0
+    );
+}
+
+#[test]
+fn test_empty_sketch() {
+    assert_eq!(HyperLogLog::default().estimate(), 0);
+}
+
+#[test]
+fn test_estimates_distinct_visitors() {
+    let hasher = VisitorHasher::new("salt");
+    for actual in [10, 100, 1_000, 50_000] {
+        assert_close(sketch_of(0..actual, &hasher).estimate(), actual);
+    }
+}
+
+#[test]
+fn test_repeated_visitors_are_counted_once() {
+    let hasher = VisitorHasher::new("salt");
+    let mut sketch = sketch_of(0..500, &hasher);
+    let before = sketch.clone();
+
+    for visitor in 0..500 {
+        assert!(!sketch.insert(hasher.hash(&format!("ip-hash-{visitor}"))));
+    }
+    assert_eq!(sketch, before);
+}
+
+#[test]
+fn test_merge_is_union() {
+    let hasher = VisitorHasher::new("salt");
+    let mut monday = sketch_of(0..3_000, &hasher);
+    let tuesday = sketch_of(2_000..5_000, &hasher);
+
+    monday.merge(&tuesday);
+    assert_close(monday.estimate(), 5_000);
+}
+
+#[test]
+fn test_sketch_roundtrips_through_bytes() {
+    let hasher = VisitorHasher::new("salt");
+    let sketch = sketch_of(0..100, &hasher);
+    assert_eq!(sketch.as_bytes().len(), REGISTERS);
+    assert_eq!(HyperLogLog::from_bytes(sketch.as_bytes()), sketch);
+
+    // Freshly inserted rows start with no registers at all
+    assert_eq!(HyperLogLog::from_bytes(&[]), HyperLogLog::default());
+}
+
+#[test]
+fn test_visitor_hash_depends_on_salt() {
+    let hasher = VisitorHasher::new("salt");
+    assert_eq!(hasher.hash("ip-hash"), hasher.hash("ip-hash"));
+    assert_ne!(hasher.hash("ip-hash"), hasher.hash("other-ip-hash"));
+    assert_ne!(
+        hasher.hash("ip-hash"),
+        VisitorHasher::new("pepper").hash("ip-hash")
+    );
+}
@@ -0,0 +1,377 @@
+use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+use axum_test::TestServer;
+use backend::{
+    admin,
+    hugo_posts::HugoBlogPost,
+    jobs::JobRunner,
+    likes::sources::{record_fediverse_like, remove_fediverse_like, FEDIVERSE_FAVOURITE},
+    state::AppState,
+    webhooks::{
+        self, signature, validate_webhook, CreateWebhook, CreatedWebhook, WebhookDelivery,
+        WebhookEvent, WebhookSender,
+    },
+};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use test_utils::{insert_post, test_jobs_config, wait_until};
+
+mod test_utils;
+
+fn request(events: Option<Vec<&str>>) -> CreateWebhook {
+    CreateWebhook {
+        url: "https://tools.example.com/hooks/blog".to_string(),
+        events: events.map(|events| events.into_iter().map(str::to_string).collect()),
+        like_milestone_every: Some(10),
+        secret: None,
+    }
+}
+
+#[test]
+fn test_signature() {
+    // echo -n '1700000000.{"id":1}' | openssl dgst -sha256 -hmac 'webhook-secret'
+    assert_eq!(
+        signature("webhook-secret", 1_700_000_000, r#"{"id":1}"#),
+        "sha256=650ecfa11c2e1c08b804991ec86b532f596401b4f82111bb9a258197996955e2"
+    );
+    assert_ne!(
+        signature("webhook-secret", 1_700_000_001, r#"{"id":1}"#),
+        signature("webhook-secret", 1_700_000_000, r#"{"id":1}"#)
+    );
+}
+
+#[test]
+fn test_webhook_events() {
+    for event in WebhookEvent::ALL {
+        assert_eq!(WebhookEvent::parse(event.as_str()), Some(event));
+    }
+    assert_eq!(WebhookEvent::parse("post.deleted"), None);
+}
+
+#[test]
+fn test_validate_webhook() {
+    assert_eq!(
+        validate_webhook(&request(None)).unwrap(),
+        ["post.published", "post.updated", "like.milestone"]
+    );
+    assert_eq!(
+        validate_webhook(&request(Some(vec!["like.milestone"]))).unwrap(),
+        ["like.milestone"]
+    );
+    assert_eq!(validate_webhook(&request(Some(vec![]))), Err("no_events"));
+    assert_eq!(
+        validate_webhook(&request(Some(vec!["post.published", "post.deleted"]))),
+        Err("unknown_event")
+    );
+}
+
+/// Local endpoint answering every delivery with `status`
+#[derive(Clone)]
+struct Receiver {
+    status: StatusCode,
+    received: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+}
+
+impl Receiver {
+    /// Start listening, returns the URL deliveries are sent to
+    async fn start(status: StatusCode) -> (Self, String) {
+        let receiver = Receiver {
+            status,
+            received: Arc::default(),
+        };
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (receiver, url)
+    }
+
+    fn received(&self) -> Vec<(HeaderMap, String)> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+async fn receive(State(receiver): State<Receiver>, headers: HeaderMap, body: String) -> StatusCode {
+    receiver.received.lock().unwrap().push((headers, body));
+    receiver.status
+}
+
+fn admin_server(pool: &PgPool) -> TestServer {
+    let app = admin::router().with_state(AppState {
+        pool: pool.clone(),
+        ..test_utils::test_state()
+    });
+    TestServer::new(app).unwrap()
+}
+
+async fn create_endpoint(server: &TestServer, url: &str, events: &[&str]) -> CreatedWebhook {
+    let response = server
+        .post("/admin/webhooks")
+        .json(&json!({
+            "url": url,
+            "events": events,
+            "like_milestone_every": 2,
+            "secret": "0123456789abcdef-secret"
+        }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    response.json()
+}
+
+async fn deliveries(server: &TestServer, query: &str) -> Vec<WebhookDelivery> {
+    server
+        .get(&format!("/admin/webhooks/deliveries{query}"))
+        .await
+        .json()
+}
+
+/// Runner sending the deliveries, given up after `max_attempts`
+fn start_sender(pool: &PgPool, max_attempts: i32) -> backend::jobs::JobRunnerHandle {
+    let mut jobs = JobRunner::new(pool.clone(), test_jobs_config());
+    WebhookSender::new(pool.clone(), Duration::from_secs(5), max_attempts)
+        .unwrap()
+        .register(&mut jobs);
+    jobs.start()
+}
+
+async fn like(pool: &PgPool, slug: &str, actor: &str) {
+    let actor = format!("https://mastodon.example/users/{actor}");
+    record_fediverse_like(pool, slug, FEDIVERSE_FAVOURITE, &actor)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+fn hugo_post(slug: &str) -> HugoBlogPost {
+    serde_json::from_value(json!({
+        "title": "A post",
+        "slug": slug,
+        "description": "",
+        "date": "2024-01-01T12:00:00Z",
+        "url": format!("https://flakm.com/posts/{slug}/")
+    }))
+    .unwrap()
+}
+
+#[sqlx::test]
+async fn test_milestones_are_announced_once(pool: PgPool) {
+    let server = admin_server(&pool);
+    create_endpoint(
+        &server,
+        "https://hooks.example.com/likes",
+        &["like.milestone"],
+    )
+    .await;
+    insert_post(&pool, "liked").await;
+
+    for actor in ["alice", "bob", "carol", "dave"] {
+        like(&pool, "liked", actor).await;
+    }
+    // Back below the milestone and up again
+    remove_fediverse_like(
+        &pool,
+        "liked",
+        FEDIVERSE_FAVOURITE,
+        "https://mastodon.example/users/dave",
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    like(&pool, "liked", "erin").await;
+    like(&pool, "liked", "frank").await;
+
+    let totals: Vec<Value> = deliveries(&server, "")
+        .await
+        .into_iter()
+        .rev()
+        .map(|delivery| {
+            assert_eq!(delivery.event, "like.milestone");
+            assert_eq!(delivery.payload["post_slug"], "liked");
+            delivery.payload["total_likes"].clone()
+        })
+        .collect();
+    assert_eq!(totals, [json!(2), json!(4)]);
+}
+
+#[sqlx::test]
+async fn test_publications_are_deduplicated(pool: PgPool) {
+    let server = admin_server(&pool);
+    create_endpoint(
+        &server,
+        "https://hooks.example.com/posts",
+        &["post.published", "post.updated"],
+    )
+    .await;
+    let post = hugo_post("announced");
+
+    for published in [true, true, false, false] {
+        let mut tx = pool.begin().await.unwrap();
+        webhooks::post_changed(&mut tx, &post, published)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+    }
+
+    let events: Vec<String> = deliveries(&server, "")
+        .await
+        .into_iter()
+        .rev()
+        .map(|delivery| delivery.event)
+        .collect();
+    assert_eq!(events, ["post.published", "post.updated", "post.updated"]);
+}
+
+#[sqlx::test]
+async fn test_deliveries_are_signed(pool: PgPool) {
+    let (receiver, url) = Receiver::start(StatusCode::OK).await;
+    let server = admin_server(&pool);
+    let endpoint = create_endpoint(&server, &url, &["like.milestone"]).await;
+    insert_post(&pool, "signed").await;
+    like(&pool, "signed", "alice").await;
+    like(&pool, "signed", "bob").await;
+
+    let sender = start_sender(&pool, 3);
+    wait_until("the delivery is sent", || async {
+        deliveries(&server, "?status=delivered").await.len() == 1
+    })
+    .await;
+    sender.shutdown().await;
+
+    let delivery = deliveries(&server, "").await.remove(0);
+    assert_eq!(delivery.attempts, Some(1));
+    assert_eq!(delivery.response_status, Some(200));
+    assert_eq!(delivery.last_error, None);
+    assert!(delivery.delivered_at.is_some());
+
+    let received = receiver.received();
+    assert_eq!(received.len(), 1);
+    let (headers, body) = &received[0];
+    let header = |name: &str| headers[name].to_str().unwrap().to_string();
+    assert_eq!(header("content-type"), "application/json");
+    assert_eq!(header("x-webhook-id"), delivery.id.to_string());
+    assert_eq!(header("x-webhook-event"), "like.milestone");
+    let timestamp: i64 = header("x-webhook-timestamp").parse().unwrap();
+    assert_eq!(
+        header("x-webhook-signature"),
+        signature(&endpoint.secret, timestamp, body)
+    );
+
+    let body: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(body["id"], delivery.id);
+    assert_eq!(body["event"], "like.milestone");
+    assert_eq!(
+        body["data"],
+        json!({ "post_slug": "signed", "total_likes": 2 })
+    );
+}
+
+#[sqlx::test]
+async fn test_failing_receivers_are_given_up(pool: PgPool) {
+    let (receiver, url) = Receiver::start(StatusCode::INTERNAL_SERVER_ERROR).await;
+    let server = admin_server(&pool);
+    create_endpoint(&server, &url, &["like.milestone"]).await;
+    insert_post(&pool, "unheard").await;
+    like(&pool, "unheard", "alice").await;
+    like(&pool, "unheard", "bob").await;
+
+    let sender = start_sender(&pool, 2);
+    wait_until("the delivery is given up", || async {
+        deliveries(&server, "?status=failed").await.len() == 1
+    })
+    .await;
+    sender.shutdown().await;
+
+    assert_eq!(receiver.received().len(), 2);
+    let delivery = deliveries(&server, "").await.remove(0);
+    assert_eq!(delivery.attempts, Some(2));
+    assert_eq!(delivery.response_status, Some(500));
+    assert_eq!(
+        delivery.last_error.as_deref(),
+        Some("Responded with 500 Internal Server Error")
+    );
+    assert_eq!(delivery.delivered_at, None);
+
+    let job_status = sqlx::query_scalar!(
+        "SELECT jobs.status FROM jobs JOIN webhook_deliveries d ON d.job_id = jobs.id WHERE d.id = $1",
+        delivery.id
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(job_status, "failed");
+}
+
+#[sqlx::test]
+async fn test_list_deliveries_filters(pool: PgPool) {
+    let server = admin_server(&pool);
+    let likes = create_endpoint(
+        &server,
+        "https://hooks.example.com/likes",
+        &["like.milestone"],
+    )
+    .await;
+    let all = create_endpoint(
+        &server,
+        "https://hooks.example.com/all",
+        &["like.milestone", "post.published"],
+    )
+    .await;
+    insert_post(&pool, "filtered").await;
+    like(&pool, "filtered", "alice").await;
+    like(&pool, "filtered", "bob").await;
+    let mut tx = pool.begin().await.unwrap();
+    webhooks::post_changed(&mut tx, &hugo_post("filtered"), true)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    sqlx::query!(
+        "UPDATE webhook_deliveries SET status = 'failed' WHERE endpoint_id = $1",
+        likes.endpoint.id
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let listed = |query: String| {
+        let server = &server;
+        async move {
+            deliveries(server, &query)
+                .await
+                .into_iter()
+                .map(|delivery| (delivery.endpoint_id, delivery.event, delivery.status))
+                .collect::<Vec<_>>()
+        }
+    };
+    let (likes, all) = (likes.endpoint.id, all.endpoint.id);
+    let milestone = || "like.milestone".to_string();
+    let published = || "post.published".to_string();
+    let pending = || "pending".to_string();
+    let failed = || "failed".to_string();
+
+    assert_eq!(listed(String::new()).await.len(), 3);
+    assert_eq!(
+        listed(format!("?endpoint_id={all}")).await,
+        [(all, published(), pending()), (all, milestone(), pending())]
+    );
+    assert_eq!(
+        listed("?event=like.milestone".to_string()).await,
+        [
+            (all, milestone(), pending()),
+            (likes, milestone(), failed())
+        ]
+    );
+    assert_eq!(
+        listed(format!("?endpoint_id={all}&event=like.milestone")).await,
+        [(all, milestone(), pending())]
+    );
+    assert_eq!(
+        listed("?status=failed".to_string()).await,
+        [(likes, milestone(), failed())]
+    );
+    assert_eq!(listed("?limit=1".to_string()).await.len(), 1);
+}
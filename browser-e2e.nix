@@ -47,6 +47,7 @@ pkgs.nixosTest {
         backend = {
           enable = true;
           domain = "server";
+          postsDomain = "server";
           posts_path = "${pkgs.writeText "test-posts.json" ''
             [
               {
@@ -64,6 +64,7 @@
     backend = {
       enable = true;
       domain = "blog.flakm.com";
+      postsDomain = "flakm.com";
       posts_path = "${static.packages.x86_64-linux.default}/bloglist.json";
     };
 
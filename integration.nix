@@ -44,6 +44,7 @@ in
         backend = {
           enable = true;
           domain = "server";
+          postsDomain = "server";
           posts_path = "${testBlogPosts}";
         };
 